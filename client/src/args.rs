@@ -0,0 +1,253 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use quote_client::{
+    Backoff, ClientConfig, Protection, Recorder, Rotation, Tickers,
+    TlsSettings, Transport, UdpAddr,
+};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::output::OutputFormat;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Quote streaming client")]
+pub struct Args {
+    #[arg(short, long, default_value = "127.0.0.1:5000")]
+    pub server_addr: String,
+
+    #[arg(short = 'p', long, default_value = "5001")]
+    pub ping_port: u16,
+
+    #[arg(short = 'u', long, default_value = "34254")]
+    pub udp_port: u16,
+
+    #[arg(
+        short = 'c',
+        long,
+        default_value = "127.0.0.1",
+        help = "Client IP address for receiving data"
+    )]
+    pub client_ip: String,
+
+    #[arg(short = 't', long, default_value = "tickers.txt")]
+    pub tickers_file: PathBuf,
+
+    #[arg(
+        short = 'T',
+        long,
+        value_enum,
+        default_value_t = Transport::Udp,
+        help = "How quotes are delivered by the server"
+    )]
+    pub transport: Transport,
+
+    #[arg(
+        short = 'm',
+        long,
+        help = "Join a multicast group instead of registering a stream"
+    )]
+    pub multicast_group: Option<String>,
+
+    #[arg(
+        long,
+        env = "QUOTE_TOKEN",
+        hide_env_values = true,
+        help = "Token sent with AUTH before STREAM"
+    )]
+    pub token: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Connect over TLS, trusting the PEM CA certificates in FILE"
+    )]
+    pub tls_ca: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires_all = ["tls_ca", "tls_key"],
+        help = "PEM client certificate chain for servers that require one"
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_cert",
+        help = "PEM private key for --tls-cert"
+    )]
+    pub tls_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "tls_ca",
+        help = "Name to verify the server certificate against \
+                (default: the server IP)"
+    )]
+    pub tls_server_name: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "mac|aead",
+        help = "Ask for a session key and drop UDP datagrams that are not \
+                authenticated with it (aead also encrypts them)"
+    )]
+    pub datagram_protection: Option<Protection>,
+
+    #[arg(long, help = "Exit when the server is lost instead of reconnecting")]
+    pub no_reconnect: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "no_reconnect",
+        help = "Exit after N failed reconnect attempts in a row \
+                (default: keep trying)"
+    )]
+    pub max_reconnect_attempts: Option<u32>,
+
+    #[arg(
+        short = 'o',
+        long,
+        value_enum,
+        help = "Write quotes to stdout in this format instead of logging them"
+    )]
+    pub output: Option<OutputFormat>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "output",
+        help = "Write --output to FILE instead of stdout"
+    )]
+    pub output_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Report per-ticker rates, latency and gaps instead of \
+                logging each quote"
+    )]
+    pub stats: bool,
+
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value = "30",
+        help = "Log statistics every SECS seconds (0: only on exit)"
+    )]
+    pub stats_interval: u64,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Append every received datagram to the capture FILE"
+    )]
+    pub record: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "MB",
+        requires = "record",
+        help = "Start a new capture file once the current one reaches MB \
+                megabytes"
+    )]
+    pub record_max_mb: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "SECS",
+        requires = "record",
+        help = "Start a new capture file every SECS seconds"
+    )]
+    pub record_max_secs: Option<u64>,
+
+    #[cfg(feature = "tui")]
+    #[arg(
+        long,
+        conflicts_with_all = ["output", "stats"],
+        help = "Show a live dashboard of the subscribed tickers"
+    )]
+    pub tui: bool,
+}
+
+impl Args {
+    pub fn client_config(&self) -> Result<ClientConfig> {
+        let server_addr: SocketAddr = self.server_addr.parse()?;
+        let client_ip: IpAddr = self.client_ip.parse()?;
+        let tickers = read_tickers(&self.tickers_file)?;
+
+        let mut config = ClientConfig::new(server_addr, tickers)
+            .with_ping_port(self.ping_port)
+            .with_udp_port(self.udp_port)
+            .with_client_ip(client_ip)
+            .with_transport(self.transport);
+        if let Some(group) = &self.multicast_group {
+            config = config.with_multicast_group(parse_multicast_group(group)?);
+        }
+        if let Some(token) = &self.token {
+            config = config.with_token(token.clone());
+        }
+        if let Some(ca) = &self.tls_ca {
+            let identity =
+                self.tls_cert.as_deref().zip(self.tls_key.as_deref());
+            let server_name = self
+                .tls_server_name
+                .clone()
+                .unwrap_or_else(|| server_addr.ip().to_string());
+            config =
+                config.with_tls(TlsSettings::new(ca, identity, &server_name)?);
+        }
+        if let Some(protection) = self.datagram_protection {
+            config = config.with_datagram_protection(protection);
+        }
+        if self.no_reconnect {
+            config = config.without_reconnect();
+        } else {
+            config = config.with_reconnect(Backoff {
+                max_attempts: self.max_reconnect_attempts,
+                ..Backoff::default()
+            });
+        }
+        if let Some(path) = &self.record {
+            let rotation = Rotation {
+                max_bytes: self.record_max_mb.map(|mb| mb * 1024 * 1024),
+                max_age: self.record_max_secs.map(Duration::from_secs),
+            };
+            config = config.with_recorder(Recorder::create(path, rotation)?);
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_multicast_group(s: &str) -> Result<UdpAddr> {
+    let group: UdpAddr = s.parse()?;
+    if !group.is_multicast() {
+        return Err(anyhow!("{group} is not a multicast address"));
+    }
+    Ok(group)
+}
+
+fn read_tickers(path: &PathBuf) -> Result<Tickers> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let mut tickers_vec = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let ticker = line.trim();
+        if !ticker.is_empty() && !ticker.starts_with('#') {
+            tickers_vec.push(ticker.to_uppercase());
+        }
+    }
+
+    if tickers_vec.is_empty() {
+        return Err(anyhow!("No tickers found in file"));
+    }
+
+    tickers_vec.join(",").parse()
+}
@@ -0,0 +1,547 @@
+use anyhow::{Context as _, Result};
+use common::{Command, Opener, Response, StockQuote, Tickers, MAX_FRAME_LEN};
+use futures_core::Stream;
+use log::{debug, info, warn};
+use std::future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+    BufReader,
+};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time;
+use tokio_rustls::TlsConnector;
+
+use crate::config::{ClientConfig, Transport};
+use crate::protocol::{self, Agreement, Reaction};
+use crate::recorder::Recorder;
+use crate::session::Session;
+
+type QuoteSender = mpsc::Sender<Result<StockQuote>>;
+
+const QUOTE_BUFFER: usize = 1024;
+const UDP_RECEIVE_BUFFER_SIZE: usize = 4096;
+
+/// Quotes from one stream, for async code. Malformed quotes come through
+/// as errors. The stream ends when the server ends it, or right after an
+/// error if the connection fails. Dropping it stops its tasks.
+pub struct QuoteStream {
+    quotes: mpsc::Receiver<Result<StockQuote>>,
+    _tasks: JoinSet<()>,
+}
+
+impl QuoteStream {
+    /// Connects and asks for the configured stream. The stream is received,
+    /// and the server pinged, by tasks on the current tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server cannot be reached or rejects the
+    /// request.
+    pub async fn connect(config: ClientConfig) -> Result<Self> {
+        let (tx, quotes) = mpsc::channel(QUOTE_BUFFER);
+        let mut tasks = JoinSet::new();
+
+        match config.multicast_group {
+            Some(group) => {
+                info!("Joining multicast group {group}");
+                let socket = Session::join_multicast_group(group)?;
+                socket.set_nonblocking(true)?;
+                let receiver = UdpReceiver::<TcpStream> {
+                    socket: Arc::new(UdpSocket::from_std(socket)?),
+                    control: None,
+                    keep_alive: None,
+                    ping_addr: config.ping_addr,
+                    opener: None,
+                    tickers: config.tickers.clone(),
+                    recorder: config.recorder.clone(),
+                };
+                tasks.spawn(receiver.run(config.ping_interval, tx));
+            }
+            None => open_stream(&config, tx, &mut tasks).await?,
+        }
+
+        Ok(Self {
+            quotes,
+            _tasks: tasks,
+        })
+    }
+
+    /// The next quote, or `None` once the stream has ended.
+    pub async fn recv(&mut self) -> Option<Result<StockQuote>> {
+        self.quotes.recv().await
+    }
+}
+
+impl Stream for QuoteStream {
+    type Item = Result<StockQuote>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.quotes.poll_recv(cx)
+    }
+}
+
+async fn open_stream(
+    config: &ClientConfig,
+    tx: QuoteSender,
+    tasks: &mut JoinSet<()>,
+) -> Result<()> {
+    info!("Connecting to TCP server at {}", config.server_addr);
+    let stream = TcpStream::connect(config.server_addr).await?;
+
+    match &config.tls {
+        Some(tls) => {
+            let stream = TlsConnector::from(tls.config.clone())
+                .connect(tls.server_name.clone(), stream)
+                .await
+                .context("TLS handshake failed")?;
+            info!("TLS session established");
+            start(stream, config, tx, tasks).await
+        }
+        None => start(stream, config, tx, tasks).await,
+    }
+}
+
+/// Opens the stream over `control` and spawns the task receiving it.
+async fn start<C>(
+    control: C,
+    config: &ClientConfig,
+    tx: QuoteSender,
+    tasks: &mut JoinSet<()>,
+) -> Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // Bound first, so no datagram sent right after the reply is lost.
+    let socket = match config.transport {
+        Transport::Tcp => None,
+        Transport::Udp | Transport::Nat => {
+            info!("Setting up UDP socket on port {}", config.udp_port);
+            Some(Arc::new(UdpSocket::bind(config.udp_bind_addr()).await?))
+        }
+    };
+
+    let mut control = BufReader::new(control);
+    let mut agreement = Agreement::default();
+    for command in protocol::stream_commands(config) {
+        protocol::announce(&command);
+        let reply = send_command(&mut control, &command).await?;
+        protocol::accept_reply(&command, reply, &mut agreement)?;
+    }
+
+    let tickers = config.tickers.clone();
+    match socket {
+        Some(socket) => {
+            let keep_alive = KeepAlive {
+                socket: socket.clone(),
+                addr: config.ping_addr,
+                message: protocol::keep_alive(agreement.session_id, None)
+                    .to_string(),
+            };
+            let receiver = UdpReceiver {
+                socket,
+                control: Some(control),
+                keep_alive: Some(keep_alive),
+                ping_addr: config.ping_addr,
+                opener: agreement.opener,
+                tickers,
+                recorder: config.recorder.clone(),
+            };
+            tasks.spawn(receiver.run(config.ping_interval, tx));
+        }
+        None => {
+            tasks.spawn(receive_frames(
+                control,
+                tickers,
+                config.server_addr,
+                config.recorder.clone(),
+                tx,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_command(
+    control: &mut BufReader<impl AsyncRead + AsyncWrite + Unpin>,
+    command: &Command,
+) -> Result<Response> {
+    let stream = control.get_mut();
+    stream.write_all(format!("{command}\n").as_bytes()).await?;
+    stream.flush().await?;
+
+    loop {
+        let mut line = String::new();
+        control.read_line(&mut line).await?;
+        if let Some(reply) = protocol::parse_reply(&line) {
+            return reply;
+        }
+    }
+}
+
+/// Sends the datagram that keeps a UDP stream alive.
+#[derive(Clone)]
+struct KeepAlive {
+    socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+    message: String,
+}
+
+impl KeepAlive {
+    async fn send(&self) {
+        match self
+            .socket
+            .send_to(self.message.as_bytes(), self.addr)
+            .await
+        {
+            Ok(_) => debug!("Sent {} to {}", self.message, self.addr),
+            Err(e) => warn!("Failed to send {}: {e}", self.message),
+        }
+    }
+
+    async fn run(self, interval: Duration) {
+        let mut ticks = time::interval(interval);
+        loop {
+            ticks.tick().await;
+            self.send().await;
+        }
+    }
+}
+
+/// Receives a UDP stream, and the notifications on its control connection
+/// when it has one.
+struct UdpReceiver<C> {
+    socket: Arc<UdpSocket>,
+    control: Option<BufReader<C>>,
+    keep_alive: Option<KeepAlive>,
+    ping_addr: SocketAddr,
+    opener: Option<Opener>,
+    tickers: Tickers,
+    recorder: Option<Recorder>,
+}
+
+impl<C: AsyncRead + Unpin> UdpReceiver<C> {
+    async fn run(mut self, ping_interval: Duration, tx: QuoteSender) {
+        // Dropped, and so aborted, when receiving stops.
+        let mut pinger = JoinSet::new();
+        if let Some(keep_alive) = self.keep_alive.clone() {
+            pinger.spawn(keep_alive.run(ping_interval));
+        }
+
+        let rejected = AtomicU64::new(0);
+        let mut buf = [0_u8; UDP_RECEIVE_BUFFER_SIZE];
+        let mut line = Vec::new();
+
+        loop {
+            tokio::select! {
+                received = self.socket.recv_from(&mut buf) => {
+                    let (len, source) = match received {
+                        Ok(received) => received,
+                        Err(e) => {
+                            let _ = tx.send(Err(e.into())).await;
+                            break;
+                        }
+                    };
+                    let Some(payload) = Session::open_datagram(
+                        &buf[..len],
+                        &mut self.opener,
+                        source,
+                        self.ping_addr,
+                        &rejected,
+                    ) else {
+                        continue;
+                    };
+                    if let Some(recorder) = &self.recorder {
+                        recorder.record(source, &payload);
+                    }
+                    if *payload == *Response::BYE.as_bytes() {
+                        info!("Server ended the stream");
+                        break;
+                    }
+                    if !forward(&payload, &self.tickers, &tx).await {
+                        break;
+                    }
+                }
+                read = read_control(self.control.as_mut(), &mut line) => {
+                    if self.on_control_line(read, &mut line).await
+                        == Reaction::Stop
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let rejected = rejected.load(Ordering::Relaxed);
+        if rejected > 0 {
+            warn!("Rejected {rejected} unauthenticated datagrams");
+        }
+    }
+
+    async fn on_control_line(
+        &mut self,
+        read: io::Result<usize>,
+        line: &mut Vec<u8>,
+    ) -> Reaction {
+        match read {
+            Ok(0) => {
+                debug!("Server closed the control connection");
+                self.control = None;
+                return Reaction::Ignore;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Control connection failed: {e}");
+                self.control = None;
+                return Reaction::Ignore;
+            }
+        }
+
+        let text = String::from_utf8_lossy(line).into_owned();
+        line.clear();
+        let reaction = protocol::react(&text, &self.tickers);
+        if reaction == Reaction::KeepAlive {
+            if let Some(keep_alive) = &self.keep_alive {
+                keep_alive.send().await;
+            }
+        }
+        reaction
+    }
+}
+
+/// Reads the next control line into `line`. `read_until` keeps partial
+/// lines in `line`, so this is safe to cancel. Never completes without a
+/// control connection.
+async fn read_control(
+    control: Option<&mut BufReader<impl AsyncRead + Unpin>>,
+    line: &mut Vec<u8>,
+) -> io::Result<usize> {
+    match control {
+        Some(control) => control.read_until(b'\n', line).await,
+        None => future::pending().await,
+    }
+}
+
+async fn receive_frames(
+    mut reader: impl AsyncRead + Unpin,
+    tickers: Tickers,
+    source: SocketAddr,
+    recorder: Option<Recorder>,
+    tx: QuoteSender,
+) {
+    loop {
+        match read_frame(&mut reader).await {
+            Ok(Some(frame)) => {
+                if let Some(recorder) = &recorder {
+                    recorder.record(source, &frame);
+                }
+                if frame == Response::BYE.as_bytes() {
+                    info!("Server ended the stream");
+                    break;
+                }
+                if !forward(&frame, &tickers, &tx).await {
+                    break;
+                }
+            }
+            Ok(None) => {
+                warn!("Server closed the TCP stream");
+                break;
+            }
+            Err(e) => {
+                let _ = tx.send(Err(e.into())).await;
+                break;
+            }
+        }
+    }
+}
+
+/// Reads one length-prefixed frame, like [`common::FrameReader`]. `None`
+/// on a clean end of stream.
+async fn read_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> io::Result<Option<Vec<u8>>> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {len} bytes exceeds {MAX_FRAME_LEN}"),
+        ));
+    }
+
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+/// Passes on what `data` decodes to. False once nobody is listening.
+async fn forward(data: &[u8], tickers: &Tickers, tx: &QuoteSender) -> bool {
+    let data = String::from_utf8_lossy(data);
+    match protocol::decode_quote(&data, tickers) {
+        Some(item) => tx.send(item).await.is_ok(),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::write_frame;
+    use tokio::net::TcpListener;
+
+    const AAPL: &str =
+        r#"{"ticker":"AAPL","price":"1.5","volume":10,"timestamp":1}"#;
+    const TSLA: &str =
+        r#"{"ticker":"TSLA","price":"2.5","volume":20,"timestamp":2}"#;
+
+    /// A server on loopback: its control listener and ping socket.
+    struct TestServer {
+        listener: TcpListener,
+        pings: UdpSocket,
+    }
+
+    impl TestServer {
+        async fn bind() -> Self {
+            Self {
+                listener: TcpListener::bind("127.0.0.1:0").await.unwrap(),
+                pings: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            }
+        }
+
+        fn config(&self, transport: Transport) -> ClientConfig {
+            let udp_port = std::net::UdpSocket::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            ClientConfig::new(
+                self.listener.local_addr().unwrap(),
+                Tickers::one("AAPL"),
+            )
+            .with_ping_port(self.pings.local_addr().unwrap().port())
+            .with_udp_port(udp_port)
+            .with_transport(transport)
+        }
+
+        /// Accepts the client and answers its command with `reply`.
+        async fn accept(&self, reply: &str) -> (BufReader<TcpStream>, String) {
+            let (stream, _) = self.listener.accept().await.unwrap();
+            let mut control = BufReader::new(stream);
+            let mut command = String::new();
+            control.read_line(&mut command).await.unwrap();
+            write_line(&mut control, reply).await;
+            (control, command.trim().to_string())
+        }
+
+        async fn ping(&self) -> String {
+            let mut buf = [0; 64];
+            let len = time::timeout(
+                Duration::from_secs(2),
+                self.pings.recv(&mut buf),
+            )
+            .await
+            .expect("no ping")
+            .unwrap();
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        }
+    }
+
+    async fn write_line(control: &mut BufReader<TcpStream>, line: &str) {
+        let stream = control.get_mut();
+        stream
+            .write_all(format!("{line}\n").as_bytes())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn streams_udp_quotes_while_pinging() {
+        let server = TestServer::bind().await;
+        let config = server.config(Transport::Udp);
+        let target = config.udp_stream_addr();
+
+        let (connected, (_control, command)) =
+            tokio::join!(QuoteStream::connect(config), server.accept("OK"));
+        let mut quotes = connected.unwrap();
+        assert_eq!(command, format!("STREAM udp://{target} AAPL"));
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for datagram in [AAPL, TSLA, "garbage"] {
+            sender.send_to(datagram.as_bytes(), target).await.unwrap();
+        }
+        assert_eq!(server.ping().await, "PING");
+        sender.send_to(b"BYE", target).await.unwrap();
+
+        assert_eq!(quotes.recv().await.unwrap().unwrap().ticker, "AAPL");
+        assert!(quotes.recv().await.unwrap().is_err());
+        assert!(quotes.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn answers_notifications() {
+        let server = TestServer::bind().await;
+        let config = server
+            .config(Transport::Udp)
+            .with_ping_interval(Duration::from_secs(3600));
+
+        let (connected, (mut control, _)) =
+            tokio::join!(QuoteStream::connect(config), server.accept("OK"));
+        let mut quotes = connected.unwrap();
+        assert_eq!(server.ping().await, "PING");
+
+        write_line(&mut control, "NOTICE EXPIRING udp://127.0.0.1:1 2").await;
+        assert_eq!(server.ping().await, "PING");
+        write_line(&mut control, "NOTICE SHUTDOWN").await;
+
+        assert!(quotes.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn streams_tcp_frames() {
+        let server = TestServer::bind().await;
+
+        let (connected, (mut control, command)) = tokio::join!(
+            QuoteStream::connect(server.config(Transport::Tcp)),
+            server.accept("OK")
+        );
+        let mut quotes = connected.unwrap();
+        assert_eq!(command, "STREAM tcp AAPL");
+
+        let mut frames = Vec::new();
+        for frame in [TSLA, AAPL, "BYE"] {
+            write_frame(&mut frames, frame.as_bytes()).unwrap();
+        }
+        control.get_mut().write_all(&frames).await.unwrap();
+
+        assert_eq!(quotes.recv().await.unwrap().unwrap().ticker, "AAPL");
+        assert!(quotes.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn fails_when_rejected() {
+        let server = TestServer::bind().await;
+
+        let (connected, _) = tokio::join!(
+            QuoteStream::connect(server.config(Transport::Tcp)),
+            server.accept("ERR Unknown ticker")
+        );
+
+        let error = connected.err().unwrap();
+        assert_eq!(error.to_string(), "Server error: Unknown ticker");
+    }
+}
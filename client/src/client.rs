@@ -1,173 +1,493 @@
 use anyhow::{anyhow, Result};
-use common::{Response, StockQuote};
-use log::{debug, error, info, warn};
-use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpStream, UdpSocket};
-use std::sync::atomic::{AtomicBool, Ordering};
+use common::{StockQuote, Tickers};
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use log::{error, info, warn};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::config::ClientConfig;
+use crate::reconnect::ClientEvent;
+use crate::rtt::{RttStats, RttTracker};
+use crate::session::Session;
 
-pub struct Client {
-    config: ClientConfig,
-    running: Arc<AtomicBool>,
+/// A quote subscription. Quotes arrive through [`Self::quotes`] until the
+/// client is shut down. A lost stream is reconnected, unless configured
+/// otherwise; see [`Self::events`].
+pub struct QuoteClient {
+    shared: Arc<Shared>,
+    supervisor: Mutex<Option<JoinHandle<()>>>,
+    quote_rx: Receiver<StockQuote>,
+    events: Receiver<ClientEvent>,
 }
 
-impl Client {
-    const UDP_RECEIVE_BUFFER_SIZE: usize = 4096;
-    const TCP_READ_TIMEOUT_SECS: u64 = 5;
-    const UDP_READ_TIMEOUT_MS: u64 = 500;
+/// What the client shares with its reconnecting thread.
+struct Shared {
+    /// `None` while reconnecting and once shut down.
+    session: Mutex<Option<Session>>,
+    /// What to connect to next; follows [`QuoteClient::set_tickers`].
+    config: Mutex<ClientConfig>,
+    /// False once shut down, or once reconnecting gave up.
+    active: AtomicBool,
+    quote_tx: Sender<StockQuote>,
+    event_tx: Sender<ClientEvent>,
+    rtt: RttTracker,
+    received_bytes: Arc<AtomicU64>,
+}
 
-    pub fn new(config: ClientConfig) -> Self {
-        Self {
-            config,
-            running: Arc::new(AtomicBool::new(true)),
-        }
+impl QuoteClient {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Connects and asks for the configured stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server cannot be reached or rejects the
+    /// request. Only later losses are reconnected.
+    pub fn connect(config: ClientConfig) -> Result<Self> {
+        let (quote_tx, quote_rx) = unbounded();
+        let (event_tx, events) = unbounded();
+        let rtt = RttTracker::new();
+        let received_bytes = Arc::new(AtomicU64::new(0));
+        let session = Session::start(
+            config.clone(),
+            quote_tx.clone(),
+            rtt.clone(),
+            received_bytes.clone(),
+        )?;
+
+        let shared = Arc::new(Shared {
+            session: Mutex::new(Some(session)),
+            config: Mutex::new(config),
+            active: AtomicBool::new(true),
+            quote_tx,
+            event_tx,
+            rtt,
+            received_bytes,
+        });
+        let supervisor = {
+            let shared = shared.clone();
+            thread::spawn(move || shared.supervise())
+        };
+
+        Ok(Self {
+            shared,
+            supervisor: Mutex::new(Some(supervisor)),
+            quote_rx,
+            events,
+        })
+    }
+
+    /// Blocks for each quote; ends once the client stops and every quote
+    /// received before that was taken.
+    pub const fn quotes(&self) -> Quotes<'_> {
+        Quotes { client: self }
+    }
+
+    /// Waits up to `timeout` for the next quote.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<StockQuote> {
+        self.quote_rx.recv_timeout(timeout).ok()
+    }
+
+    /// Disconnects and reconnects, as they happen.
+    pub fn events(&self) -> Receiver<ClientEvent> {
+        self.events.clone()
     }
 
-    pub fn run(&self) -> Result<()> {
-        info!("Connecting to TCP server at {}", self.config.server_addr);
-        let mut tcp_stream = TcpStream::connect(self.config.server_addr)?;
-        tcp_stream.set_read_timeout(Some(Duration::from_secs(
-            Self::TCP_READ_TIMEOUT_SECS,
-        )))?;
+    /// Round trips of the pings sent so far, across reconnects. Streams
+    /// over TCP or from a multicast group are not pinged.
+    pub fn rtt(&self) -> RttStats {
+        self.shared.rtt.stats()
+    }
 
-        info!("Setting up UDP socket on port {}", self.config.udp_port);
-        let udp_socket =
-            Arc::new(UdpSocket::bind(self.config.udp_bind_addr())?);
-        udp_socket.set_read_timeout(Some(Duration::from_millis(
-            Self::UDP_READ_TIMEOUT_MS,
-        )))?;
+    /// Size of the quote payloads received so far, across reconnects.
+    pub fn bytes_received(&self) -> u64 {
+        self.shared.received_bytes.load(Ordering::Relaxed)
+    }
 
-        self.send_stream_command(&mut tcp_stream)?;
+    pub fn tickers(&self) -> Tickers {
+        self.shared.config.lock().tickers().clone()
+    }
 
-        let ping_handle = self.spawn_ping_thread(udp_socket.clone());
-        let recv_handle = self.spawn_receive_thread(udp_socket);
+    /// Switches the subscription to `tickers` by replacing the stream with
+    /// a new one. While reconnecting, the next attempt uses them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client was shut down or the server rejects
+    /// the new stream. After a rejection the client reconnects as if the
+    /// stream had been lost.
+    pub fn set_tickers(&self, tickers: Tickers) -> Result<()> {
+        if !self.is_running() {
+            return Err(anyhow!("Client is shut down"));
+        }
 
-        ping_handle
-            .join()
-            .map_err(|_| anyhow!("Ping thread panicked"))?;
-        recv_handle
-            .join()
-            .map_err(|_| anyhow!("Receive thread panicked"))?;
+        let mut session = self.shared.session.lock();
+        let config = {
+            let mut config = self.shared.config.lock();
+            *config = config.clone().with_tickers(tickers);
+            config.clone()
+        };
+        let Some(current) = session.take() else {
+            return Ok(());
+        };
+        current.stop();
 
-        info!("Client shutdown complete");
+        info!("Resubscribing to {}", config.tickers());
+        *session = Some(self.shared.start_session(config)?);
+        drop(session);
         Ok(())
     }
 
-    #[allow(dead_code)]
+    /// False once shut down, or once the stream was lost for good.
+    pub fn is_running(&self) -> bool {
+        self.shared.active.load(Ordering::SeqCst)
+    }
+
+    /// Ends the stream and waits for the client's threads.
     pub fn shutdown(&self) {
-        info!("Initiating shutdown...");
-        self.running.store(false, Ordering::SeqCst);
+        if self.shared.active.swap(false, Ordering::SeqCst) {
+            info!("Initiating shutdown...");
+        }
+
+        let supervisor = self.supervisor.lock().take();
+        if supervisor.is_some_and(|handle| handle.join().is_err()) {
+            error!("Reconnect thread panicked");
+        }
+        let session = self.shared.session.lock().take();
+        if let Some(session) = session {
+            session.stop();
+        }
     }
+}
 
-    pub fn running(&self) -> Arc<AtomicBool> {
-        self.running.clone()
+impl Drop for QuoteClient {
+    fn drop(&mut self) {
+        self.shutdown();
     }
+}
 
-    fn send_stream_command(&self, tcp_stream: &mut TcpStream) -> Result<()> {
-        let tickers_str = self.config.tickers.to_string();
-        let udp_addr = self.config.udp_stream_addr();
-        let command = format!(
-            "STREAM udp://{}:{} {}\n",
-            udp_addr.ip(),
-            udp_addr.port(),
-            tickers_str
-        );
+impl Shared {
+    const TICK: Duration = Duration::from_millis(100);
 
-        info!("Sending command: {}", command.trim());
-        tcp_stream.write_all(command.as_bytes())?;
-        tcp_stream.flush()?;
+    /// Watches the session and replaces it when the server ends it.
+    fn supervise(&self) {
+        while self.active.load(Ordering::SeqCst) {
+            thread::sleep(Self::TICK);
 
-        let mut reader = BufReader::new(tcp_stream.try_clone()?);
-        let mut response_line = String::new();
-        reader.read_line(&mut response_line)?;
+            let ended = {
+                let mut session = self.session.lock();
+                match session.as_ref() {
+                    Some(current) if current.is_running() => continue,
+                    Some(_) => session.take(),
+                    // A resubscription failed.
+                    None => None,
+                }
+            };
+            if let Some(ended) = ended {
+                let reason = ended
+                    .ended_by()
+                    .unwrap_or_else(|| "Stream stopped".to_string());
+                ended.stop();
+                warn!("Lost the stream: {reason}");
+                self.emit(ClientEvent::Disconnected { reason });
+            }
 
-        let response: Response = response_line.parse()?;
-        match response {
-            Response::Ok => {
-                info!("Server accepted STREAM command");
-                Ok(())
+            if !self.reconnect() {
+                warn!("Not reconnecting; client stopped");
+                self.active.store(false, Ordering::SeqCst);
+                self.emit(ClientEvent::GaveUp);
             }
-            Response::Error(msg) => Err(anyhow!("Server error: {msg}")),
         }
     }
 
-    fn spawn_ping_thread(&self, udp_socket: Arc<UdpSocket>) -> JoinHandle<()> {
-        let ping_addr = self.config.ping_addr;
-        let interval = self.config.ping_interval;
-        let running = self.running.clone();
+    /// Starts a new session, backing off between attempts. False when
+    /// reconnecting is disabled or out of attempts.
+    fn reconnect(&self) -> bool {
+        let Some(backoff) = self.config.lock().reconnect else {
+            return false;
+        };
 
-        thread::spawn(move || {
-            Self::ping_loop(&udp_socket, ping_addr, interval, &running);
-        })
+        for attempt in 1.. {
+            if !backoff.allows(attempt) {
+                break;
+            }
+            let delay = backoff.delay(attempt);
+            info!(
+                "Reconnecting in {:.1}s (attempt {attempt})",
+                delay.as_secs_f32()
+            );
+            self.emit(ClientEvent::Reconnecting { attempt, delay });
+            if !self.sleep(delay) {
+                return true;
+            }
+
+            let config = self.config.lock().clone();
+            match self.start_session(config) {
+                Ok(started) => {
+                    let mut session = self.session.lock();
+                    // Shut down or resubscribed in the meantime.
+                    if !self.active.load(Ordering::SeqCst) || session.is_some()
+                    {
+                        drop(session);
+                        started.stop();
+                        return true;
+                    }
+                    *session = Some(started);
+                    drop(session);
+
+                    info!("Reconnected after {attempt} attempts");
+                    self.emit(ClientEvent::Reconnected { attempts: attempt });
+                    return true;
+                }
+                Err(e) => warn!("Reconnect attempt {attempt} failed: {e}"),
+            }
+        }
+        false
+    }
+
+    fn start_session(&self, config: ClientConfig) -> Result<Session> {
+        Session::start(
+            config,
+            self.quote_tx.clone(),
+            self.rtt.clone(),
+            self.received_bytes.clone(),
+        )
     }
 
-    fn ping_loop(
-        socket: &Arc<UdpSocket>,
-        ping_addr: std::net::SocketAddr,
-        interval: Duration,
-        running: &Arc<AtomicBool>,
-    ) {
-        while running.load(Ordering::SeqCst) {
-            match socket.send_to(b"PING", ping_addr) {
-                Ok(_) => debug!("Sent PING to {ping_addr}"),
-                Err(e) => warn!("Failed to send PING: {e}"),
+    /// Sleeps for `delay`, or until shut down. False if shut down.
+    fn sleep(&self, delay: Duration) -> bool {
+        let deadline = Instant::now() + delay;
+        while self.active.load(Ordering::SeqCst) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return true;
             }
-            thread::sleep(interval);
+            thread::sleep(left.min(Self::TICK));
         }
-        debug!("Ping thread stopped");
+        false
     }
 
-    fn spawn_receive_thread(
-        &self,
-        udp_socket: Arc<UdpSocket>,
-    ) -> JoinHandle<()> {
-        let running = self.running.clone();
+    fn emit(&self, event: ClientEvent) {
+        // Nobody listening is fine.
+        let _ = self.event_tx.send(event);
+    }
+}
 
-        thread::spawn(move || {
-            Self::receive_loop(&udp_socket, &running);
-        })
+/// Iterator returned by [`QuoteClient::quotes`].
+pub struct Quotes<'a> {
+    client: &'a QuoteClient,
+}
+
+impl Iterator for Quotes<'_> {
+    type Item = StockQuote;
+
+    fn next(&mut self) -> Option<StockQuote> {
+        loop {
+            match self
+                .client
+                .quote_rx
+                .recv_timeout(QuoteClient::POLL_INTERVAL)
+            {
+                Ok(quote) => return Some(quote),
+                Err(RecvTimeoutError::Timeout) if self.client.is_running() => {}
+                Err(_) => return self.client.quote_rx.try_recv().ok(),
+            }
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Transport;
+    use crate::reconnect::Backoff;
+    use common::write_frame;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+
+    const AAPL: &str =
+        r#"{"ticker":"AAPL","price":"1.5","volume":10,"timestamp":1}"#;
+    const TSLA: &str =
+        r#"{"ticker":"TSLA","price":"2.5","volume":20,"timestamp":2}"#;
 
-    fn receive_loop(socket: &Arc<UdpSocket>, running: &Arc<AtomicBool>) {
-        let mut buf = [0_u8; Self::UDP_RECEIVE_BUFFER_SIZE];
+    /// Accepts `STREAM tcp` connections, answers `OK` and sends `frames`.
+    /// Connections are held until the client closes them, unless `hang_up`.
+    /// Returns the commands received.
+    fn serve(
+        frames: &'static [&'static str],
+        hang_up: bool,
+    ) -> (SocketAddr, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (command_tx, commands) = unbounded();
 
-        while running.load(Ordering::SeqCst) {
-            match socket.recv_from(&mut buf) {
-                Ok((len, _addr)) => {
-                    let data = String::from_utf8_lossy(&buf[..len]);
-                    Self::handle_received_data(&data);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                command_tx.send(line.trim().to_string()).unwrap();
+
+                let stream = reader.get_mut();
+                writeln!(stream, "OK").unwrap();
+                for frame in frames {
+                    write_frame(stream, frame.as_bytes()).unwrap();
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
-                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
-                Err(e) => {
-                    if running.load(Ordering::SeqCst) {
-                        error!("Failed to receive UDP data: {e}");
-                    }
+                if !hang_up {
+                    let _ = stream.read(&mut [0; 1]);
                 }
             }
-        }
-        debug!("Receive thread stopped");
+        });
+
+        (addr, commands)
     }
 
-    fn handle_received_data(data: &str) {
-        let data = data.trim();
-        if data == "PONG" {
-            debug!("Received PONG");
-            return;
-        }
+    fn config(addr: SocketAddr, tickers: &str) -> ClientConfig {
+        ClientConfig::new(addr, tickers.parse().unwrap())
+            .with_transport(Transport::Tcp)
+            .with_reconnect(Backoff {
+                initial: Duration::from_millis(20),
+                max: Duration::from_millis(20),
+                max_attempts: Some(3),
+            })
+    }
+
+    fn connect(addr: SocketAddr, tickers: &str) -> QuoteClient {
+        QuoteClient::connect(config(addr, tickers)).unwrap()
+    }
+
+    #[test]
+    fn yields_subscribed_quotes_until_bye() {
+        let (addr, commands) = serve(&[AAPL, TSLA, AAPL, "BYE"], false);
+
+        let client =
+            QuoteClient::connect(config(addr, "AAPL").without_reconnect())
+                .unwrap();
+        let tickers: Vec<_> =
+            client.quotes().map(|quote| quote.ticker).collect();
+
+        assert_eq!(commands.recv().unwrap(), "STREAM tcp AAPL");
+        assert_eq!(tickers, ["AAPL", "AAPL"]);
+        assert!(!client.is_running());
+        assert_eq!(
+            client.bytes_received(),
+            (2 * AAPL.len() + TSLA.len()) as u64
+        );
+        assert_eq!(
+            client.events().try_iter().collect::<Vec<_>>(),
+            [
+                ClientEvent::Disconnected {
+                    reason: "Server ended the stream".to_string()
+                },
+                ClientEvent::GaveUp
+            ]
+        );
+    }
 
-        match data.parse::<StockQuote>() {
-            Ok(quote) => {
-                info!(
-                    "[{}] {} - Price: {}, Volume: {}",
-                    quote.timestamp, quote.ticker, quote.price, quote.volume
-                );
+    #[test]
+    fn resubscribes_with_new_tickers() {
+        let (addr, commands) = serve(&[AAPL, TSLA], false);
+        let client = connect(addr, "AAPL");
+        assert_eq!(client.quotes().next().unwrap().ticker, "AAPL");
+
+        client.set_tickers("TSLA".parse().unwrap()).unwrap();
+
+        assert_eq!(client.quotes().next().unwrap().ticker, "TSLA");
+        assert_eq!(client.tickers(), Tickers::one("TSLA"));
+        assert_eq!(
+            commands.try_iter().collect::<Vec<_>>(),
+            ["STREAM tcp AAPL", "STREAM tcp TSLA"]
+        );
+    }
+
+    #[test]
+    fn stops_on_shutdown() {
+        let (addr, _commands) = serve(&[], false);
+        let client = connect(addr, "AAPL");
+        assert!(client.is_running());
+
+        client.shutdown();
+
+        assert!(!client.is_running());
+        assert!(client.quotes().next().is_none());
+        assert!(client.set_tickers(Tickers::one("TSLA")).is_err());
+    }
+
+    #[test]
+    fn reconnects_and_resubscribes() {
+        let (addr, commands) = serve(&[AAPL], true);
+        let client = connect(addr, "AAPL");
+        let events = client.events();
+
+        let quotes: Vec<_> = client.quotes().take(2).collect();
+
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(
+            events.recv().unwrap(),
+            ClientEvent::Disconnected {
+                reason: "Server closed the TCP stream".to_string()
             }
-            Err(e) => warn!("Failed to parse quote '{data}': {e}"),
-        }
+        );
+        assert!(matches!(
+            events.recv().unwrap(),
+            ClientEvent::Reconnecting { attempt: 1, .. }
+        ));
+        assert_eq!(
+            events.recv().unwrap(),
+            ClientEvent::Reconnected { attempts: 1 }
+        );
+        assert_eq!(commands.recv().unwrap(), commands.recv().unwrap());
+    }
+
+    #[test]
+    fn resubscribes_after_bye() {
+        let (addr, commands) = serve(&[AAPL, "BYE"], false);
+        let client = connect(addr, "AAPL");
+        let events = client.events();
+
+        let quotes: Vec<_> = client.quotes().take(2).collect();
+
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(
+            events.recv().unwrap(),
+            ClientEvent::Disconnected {
+                reason: "Server ended the stream".to_string()
+            }
+        );
+        assert!(matches!(
+            events.recv().unwrap(),
+            ClientEvent::Reconnecting { attempt: 1, .. }
+        ));
+        assert_eq!(commands.recv().unwrap(), commands.recv().unwrap());
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            // Serve once, then refuse every later connection.
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            reader.read_line(&mut String::new()).unwrap();
+            writeln!(reader.get_mut(), "OK").unwrap();
+        });
+
+        let client = connect(addr, "AAPL");
+
+        assert!(client.quotes().next().is_none());
+        let events: Vec<_> = client.events().try_iter().collect();
+        assert_eq!(events.last(), Some(&ClientEvent::GaveUp));
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| matches!(e, ClientEvent::Reconnecting { .. }))
+                .count(),
+            3
+        );
     }
 }
@@ -1,90 +1,159 @@
-use anyhow::{anyhow, Result};
-use clap::Parser;
-use common::Tickers;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::net::{IpAddr, SocketAddr};
-use std::path::PathBuf;
+use clap::ValueEnum;
+use common::{Protection, StreamTarget, Tickers, UdpAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::time::Duration;
 
-const DEFAULT_PING_INTERVAL_SECS: u64 = 2;
-
-#[derive(Parser, Debug)]
-#[command(author, version, about = "Quote streaming client")]
-pub struct Args {
-    #[arg(short, long, default_value = "127.0.0.1:5000")]
-    pub server_addr: String,
-
-    #[arg(short = 'p', long, default_value = "5001")]
-    pub ping_port: u16,
+use crate::reconnect::Backoff;
+use crate::recorder::Recorder;
+use crate::tls::TlsSettings;
 
-    #[arg(short = 'u', long, default_value = "34254")]
-    pub udp_port: u16,
-
-    #[arg(
-        short = 'c',
-        long,
-        default_value = "127.0.0.1",
-        help = "Client IP address for receiving data"
-    )]
-    pub client_ip: String,
+const DEFAULT_PING_PORT: u16 = 5001;
+const DEFAULT_UDP_PORT: u16 = 34254;
+const DEFAULT_PING_INTERVAL_SECS: u64 = 2;
 
-    #[arg(short = 't', long, default_value = "tickers.txt")]
-    pub tickers_file: PathBuf,
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Quotes arrive as datagrams on the local UDP port
+    Udp,
+    /// Quotes arrive as length-prefixed frames on the TCP connection
+    Tcp,
+    /// Quotes arrive on the local UDP port, addressed to wherever the
+    /// client's registration datagrams appear to come from (behind NAT)
+    Nat,
 }
 
+/// Where and how to stream quotes from. Starts from the defaults of the
+/// `client` binary; adjust with the `with_*` methods.
 #[derive(Debug, Clone)]
+#[must_use]
 pub struct ClientConfig {
-    pub server_addr: SocketAddr,
-    pub ping_addr: SocketAddr,
-    pub udp_port: u16,
-    pub client_ip: IpAddr,
-    pub tickers: Tickers,
-    pub ping_interval: Duration,
+    pub(crate) server_addr: SocketAddr,
+    pub(crate) ping_addr: SocketAddr,
+    pub(crate) udp_port: u16,
+    pub(crate) client_ip: IpAddr,
+    pub(crate) tickers: Tickers,
+    pub(crate) ping_interval: Duration,
+    pub(crate) transport: Transport,
+    pub(crate) multicast_group: Option<UdpAddr>,
+    pub(crate) token: Option<String>,
+    pub(crate) tls: Option<TlsSettings>,
+    pub(crate) datagram_protection: Option<Protection>,
+    pub(crate) reconnect: Option<Backoff>,
+    pub(crate) recorder: Option<Recorder>,
 }
 
 impl ClientConfig {
-    pub fn from_args(args: &Args) -> Result<Self> {
-        let server_addr: SocketAddr = args.server_addr.parse()?;
-        let ping_addr = SocketAddr::new(server_addr.ip(), args.ping_port);
-        let client_ip: IpAddr = args.client_ip.parse()?;
-        let tickers = read_tickers(&args.tickers_file)?;
-
-        Ok(Self {
+    pub fn new(server_addr: SocketAddr, tickers: Tickers) -> Self {
+        Self {
             server_addr,
-            ping_addr,
-            udp_port: args.udp_port,
-            client_ip,
+            ping_addr: SocketAddr::new(server_addr.ip(), DEFAULT_PING_PORT),
+            udp_port: DEFAULT_UDP_PORT,
+            client_ip: Ipv4Addr::LOCALHOST.into(),
             tickers,
             ping_interval: Duration::from_secs(DEFAULT_PING_INTERVAL_SECS),
-        })
+            transport: Transport::Udp,
+            multicast_group: None,
+            token: None,
+            tls: None,
+            datagram_protection: None,
+            reconnect: Some(Backoff::default()),
+            recorder: None,
+        }
     }
 
-    pub fn udp_bind_addr(&self) -> SocketAddr {
-        SocketAddr::new("0.0.0.0".parse().unwrap(), self.udp_port)
+    /// The server's UDP port for pings and NAT registrations.
+    pub const fn with_ping_port(mut self, port: u16) -> Self {
+        self.ping_addr.set_port(port);
+        self
     }
 
-    pub const fn udp_stream_addr(&self) -> SocketAddr {
-        SocketAddr::new(self.client_ip, self.udp_port)
+    /// The local UDP port quotes arrive on.
+    pub const fn with_udp_port(mut self, port: u16) -> Self {
+        self.udp_port = port;
+        self
     }
-}
 
-fn read_tickers(path: &PathBuf) -> Result<Tickers> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let mut tickers_vec = Vec::new();
+    /// The address the server streams UDP quotes to.
+    pub const fn with_client_ip(mut self, ip: IpAddr) -> Self {
+        self.client_ip = ip;
+        self
+    }
 
-    for line in reader.lines() {
-        let line = line?;
-        let ticker = line.trim();
-        if !ticker.is_empty() && !ticker.starts_with('#') {
-            tickers_vec.push(ticker.to_uppercase());
-        }
+    pub const fn with_ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    pub const fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Listens to a multicast group instead of asking for a stream. The
+    /// tickers then only filter what the group carries.
+    pub const fn with_multicast_group(mut self, group: UdpAddr) -> Self {
+        self.multicast_group = Some(group);
+        self
+    }
+
+    /// Sent with `AUTH` before `STREAM`.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn with_tls(mut self, tls: TlsSettings) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub const fn with_datagram_protection(
+        mut self,
+        protection: Protection,
+    ) -> Self {
+        self.datagram_protection = Some(protection);
+        self
+    }
+
+    /// Reconnects with `backoff` when the stream is lost. On by default.
+    pub const fn with_reconnect(mut self, backoff: Backoff) -> Self {
+        self.reconnect = Some(backoff);
+        self
+    }
+
+    pub const fn without_reconnect(mut self) -> Self {
+        self.reconnect = None;
+        self
+    }
+
+    /// Records every datagram received, across reconnects.
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
     }
 
-    if tickers_vec.is_empty() {
-        return Err(anyhow!("No tickers found in file"));
+    pub(crate) fn with_tickers(mut self, tickers: Tickers) -> Self {
+        self.tickers = tickers;
+        self
     }
 
-    tickers_vec.join(",").parse()
+    pub const fn tickers(&self) -> &Tickers {
+        &self.tickers
+    }
+
+    pub(crate) fn udp_bind_addr(&self) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), self.udp_port)
+    }
+
+    pub(crate) const fn udp_stream_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.client_ip, self.udp_port)
+    }
+
+    pub(crate) fn stream_target(&self) -> StreamTarget {
+        match self.transport {
+            Transport::Udp => UdpAddr::from(self.udp_stream_addr()).into(),
+            Transport::Tcp => StreamTarget::Tcp,
+            Transport::Nat => StreamTarget::Nat,
+        }
+    }
 }
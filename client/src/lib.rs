@@ -0,0 +1,26 @@
+//! Client library for the quote streaming server.
+//!
+//! Describe the stream with a [`ClientConfig`], open it with
+//! [`QuoteClient::connect`] and read [`StockQuote`]s from
+//! [`QuoteClient::quotes`].
+
+#[cfg(feature = "async")]
+mod async_client;
+mod client;
+mod config;
+mod protocol;
+mod reconnect;
+mod recorder;
+mod rtt;
+mod session;
+mod tls;
+
+#[cfg(feature = "async")]
+pub use async_client::QuoteStream;
+pub use client::{QuoteClient, Quotes};
+pub use common::{Protection, StockQuote, Tickers, UdpAddr};
+pub use config::{ClientConfig, Transport};
+pub use reconnect::{Backoff, ClientEvent};
+pub use recorder::{Recorder, Rotation};
+pub use rtt::RttStats;
+pub use tls::TlsSettings;
@@ -1,32 +1,134 @@
-mod client;
-mod config;
+mod args;
+mod output;
+mod stats;
+#[cfg(feature = "tui")]
+mod tui;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use args::Args;
 use clap::Parser;
-use client::Client;
-use config::{Args, ClientConfig};
-use log::error;
-use std::sync::atomic::Ordering;
+use log::{debug, error, info};
+use output::QuoteWriter;
+use parking_lot::Mutex;
+use quote_client::QuoteClient;
+use stats::QuoteStats;
+use std::io::{ErrorKind, Write};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const REPORT_TICK: Duration = Duration::from_millis(100);
 
 fn main() -> Result<()> {
-    env_logger::Builder::from_env(
+    let args = Args::parse();
+
+    let mut logger = env_logger::Builder::from_env(
         env_logger::Env::default().default_filter_or("info"),
-    )
-    .init();
+    );
+    // The dashboard owns the terminal and shows the logs itself.
+    #[cfg(feature = "tui")]
+    let dashboard = args.tui.then(|| {
+        let logs = tui::LogPanel::default();
+        logger.target(env_logger::Target::Pipe(Box::new(logs.clone())));
+        logs
+    });
+    logger.init();
 
-    let args = Args::parse();
-    let config = ClientConfig::from_args(&args)?;
-    let client = Client::new(config);
+    let config = args.client_config()?;
+    let mut writer = args
+        .output
+        .map(|format| QuoteWriter::create(format, args.output_file.as_deref()))
+        .transpose()?;
+    let client = match QuoteClient::connect(config) {
+        Ok(client) => Arc::new(client),
+        Err(e) => {
+            error!("Client error: {e}");
+            return Err(e);
+        }
+    };
 
-    let running = client.running();
-    ctrlc::set_handler(move || {
-        running.store(false, Ordering::SeqCst);
-    })?;
+    let handle = client.clone();
+    ctrlc::set_handler(move || handle.shutdown())?;
 
-    if let Err(e) = client.run() {
-        error!("Client error: {e}");
-        return Err(e);
+    let stats = args.stats.then(|| Arc::new(Mutex::new(QuoteStats::new())));
+    let reporter = (args.stats_interval > 0).then(|| {
+        spawn_stats_reporter(
+            client.clone(),
+            stats.clone(),
+            Duration::from_secs(args.stats_interval),
+        )
+    });
+
+    #[cfg(feature = "tui")]
+    let result = dashboard.map_or_else(
+        || print_quotes(&client, writer.as_mut(), stats.as_deref()),
+        |logs| tui::run(&client, &logs),
+    );
+    #[cfg(not(feature = "tui"))]
+    let result = print_quotes(&client, writer.as_mut(), stats.as_deref());
+
+    client.shutdown();
+    if let Some(reporter) = reporter {
+        let _ = reporter.join();
     }
+    report(&client, stats.as_deref());
+    info!("Client shutdown complete");
+    result
+}
 
+/// Logs or writes quotes until the client stops.
+fn print_quotes(
+    client: &QuoteClient,
+    mut writer: Option<&mut QuoteWriter<Box<dyn Write>>>,
+    stats: Option<&Mutex<QuoteStats>>,
+) -> Result<()> {
+    for quote in client.quotes() {
+        if let Some(stats) = &stats {
+            stats.lock().record(&quote);
+        }
+        match &mut writer {
+            Some(writer) => match writer.write(&quote) {
+                Ok(()) => {}
+                // Whoever read our output is gone, e.g. `| head`.
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => {
+                    debug!("Output closed");
+                    break;
+                }
+                Err(e) => return Err(anyhow!("Failed to write quote: {e}")),
+            },
+            None if stats.is_none() => info!(
+                "[{}] {} - Price: {}, Volume: {}",
+                quote.timestamp, quote.ticker, quote.price, quote.volume
+            ),
+            None => {}
+        }
+    }
     Ok(())
 }
+
+/// Reports every `interval` while the client runs.
+fn spawn_stats_reporter(
+    client: Arc<QuoteClient>,
+    stats: Option<Arc<Mutex<QuoteStats>>>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut next_report = Instant::now() + interval;
+        while client.is_running() {
+            if Instant::now() >= next_report {
+                report(&client, stats.as_deref());
+                next_report += interval;
+            }
+            thread::sleep(REPORT_TICK);
+        }
+    })
+}
+
+/// Logs ping round trips, and the quote figures with `--stats`.
+fn report(client: &QuoteClient, stats: Option<&Mutex<QuoteStats>>) {
+    info!("{}", client.rtt());
+    if let Some(stats) = stats {
+        let report = stats.lock().report(client.bytes_received());
+        info!("Quote statistics:\n{report}");
+    }
+}
@@ -0,0 +1,165 @@
+//! Quotes written for other tools with `--output`, apart from the logs.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use quote_client::StockQuote;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One JSON object per line
+    Json,
+    /// Comma-separated values, with a header line
+    Csv,
+    /// Aligned columns for reading
+    Table,
+}
+
+/// Writes each quote in `format`, flushing after every quote so readers of
+/// a pipe see it right away.
+pub struct QuoteWriter<W: Write> {
+    format: OutputFormat,
+    out: W,
+    started: bool,
+}
+
+impl QuoteWriter<Box<dyn Write>> {
+    /// Writes to `path`, replacing the file, or to stdout.
+    pub fn create(format: OutputFormat, path: Option<&Path>) -> Result<Self> {
+        let out: Box<dyn Write> = match path {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(io::stdout().lock()),
+        };
+        Ok(Self::new(format, out))
+    }
+}
+
+impl<W: Write> QuoteWriter<W> {
+    const CSV_HEADER: &str = "timestamp,ticker,price,volume";
+
+    pub const fn new(format: OutputFormat, out: W) -> Self {
+        Self {
+            format,
+            out,
+            started: false,
+        }
+    }
+
+    pub fn write(&mut self, quote: &StockQuote) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            self.write_header()?;
+        }
+
+        match self.format {
+            OutputFormat::Json => writeln!(self.out, "{quote}")?,
+            OutputFormat::Csv => writeln!(
+                self.out,
+                "{},{},{},{}",
+                quote.timestamp,
+                csv_field(&quote.ticker),
+                quote.price,
+                quote.volume
+            )?,
+            OutputFormat::Table => writeln!(
+                self.out,
+                "{:<12}  {:<8} {:>12.2} {:>10}",
+                time_of_day(quote.timestamp),
+                quote.ticker,
+                quote.price.round_dp(2),
+                quote.volume
+            )?,
+        }
+        self.out.flush()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Json => Ok(()),
+            OutputFormat::Csv => writeln!(self.out, "{}", Self::CSV_HEADER),
+            OutputFormat::Table => writeln!(
+                self.out,
+                "{:<12}  {:<8} {:>12} {:>10}",
+                "TIME (UTC)", "TICKER", "PRICE", "VOLUME"
+            ),
+        }
+    }
+}
+
+/// Quotes a field holding a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// `HH:MM:SS.mmm` of a millisecond Unix timestamp.
+fn time_of_day(timestamp_ms: u64) -> String {
+    let millis = timestamp_ms % 1000;
+    let secs = timestamp_ms / 1000 % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}.{millis:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn quote() -> StockQuote {
+        r#"{"ticker":"AAPL","price":"187.456","volume":1200,"timestamp":1792330740272}"#
+            .parse()
+            .unwrap()
+    }
+
+    fn written(format: OutputFormat) -> String {
+        let mut writer = QuoteWriter::new(format, Vec::new());
+        writer.write(&quote()).unwrap();
+        writer.write(&quote()).unwrap();
+        String::from_utf8(writer.out).unwrap()
+    }
+
+    #[rstest]
+    #[case(
+        OutputFormat::Csv,
+        "timestamp,ticker,price,volume\n\
+         1792330740272,AAPL,187.456,1200\n\
+         1792330740272,AAPL,187.456,1200\n"
+    )]
+    #[case(
+        OutputFormat::Table,
+        "TIME (UTC)    TICKER          PRICE     VOLUME\n\
+         13:39:00.272  AAPL           187.46       1200\n\
+         13:39:00.272  AAPL           187.46       1200\n"
+    )]
+    fn writes_header_once(
+        #[case] format: OutputFormat,
+        #[case] expected: &str,
+    ) {
+        assert_eq!(written(format), expected);
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let lines: Vec<StockQuote> = written(OutputFormat::Json)
+            .lines()
+            .map(|line| line.parse().unwrap())
+            .collect();
+
+        assert_eq!(lines, [quote(), quote()]);
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("AAPL"), "AAPL");
+        assert_eq!(csv_field("A,\"B\""), "\"A,\"\"B\"\"\"");
+    }
+}
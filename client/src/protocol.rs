@@ -0,0 +1,302 @@
+//! The client's side of the control protocol, shared by the blocking and
+//! async clients, which only differ in how lines and datagrams travel.
+
+use anyhow::{anyhow, Result};
+use common::{
+    Command, Notification, Opener, Pong, Probe, Response, SessionId,
+    StockQuote, Tickers,
+};
+use log::{debug, info, warn};
+
+use crate::config::{ClientConfig, Transport};
+
+/// What the server agreed to while the stream was opened.
+#[derive(Default)]
+pub struct Agreement {
+    /// Set for `STREAM nat`, to be registered over UDP.
+    pub session_id: Option<SessionId>,
+    /// Set when datagrams are protected.
+    pub opener: Option<Opener>,
+}
+
+/// The commands that open the stream `config` describes: `STREAM`,
+/// preceded by `AUTH` and `SECURE` when configured.
+pub fn stream_commands(config: &ClientConfig) -> Vec<Command> {
+    let auth = config.token.clone().map(Command::Auth);
+    let secure = config
+        .datagram_protection
+        .filter(|_| config.transport != Transport::Tcp)
+        .map(Command::Secure);
+    let stream =
+        Command::stream(config.stream_target(), config.tickers.clone());
+
+    auth.into_iter().chain(secure).chain([stream]).collect()
+}
+
+/// The datagram keeping a UDP stream alive. A NAT session is bound by
+/// `REGISTER` datagrams, which double as keep-alives and refresh the NAT
+/// mapping.
+pub const fn keep_alive(
+    session_id: Option<SessionId>,
+    probe: Option<Probe>,
+) -> Command {
+    match session_id {
+        Some(id) => Command::Register(id, probe),
+        None => Command::Ping(probe),
+    }
+}
+
+/// Logs a command about to be sent, without leaking tokens.
+pub fn announce(command: &Command) {
+    match command {
+        Command::Auth(_) => info!("Authenticating"),
+        Command::Secure(_) => debug!("Sending command: {command}"),
+        _ => info!("Sending command: {command}"),
+    }
+}
+
+/// Parses a line read while waiting for a reply. Notifications may arrive
+/// first; they are logged and give `None`.
+pub fn parse_reply(line: &str) -> Option<Result<Response>> {
+    if !Notification::is_notification(line) {
+        return Some(line.trim_end().parse());
+    }
+    match line.parse() {
+        Ok(notification) => log_notification(&notification),
+        Err(e) => warn!("Invalid notification '{}': {e}", line.trim()),
+    }
+    None
+}
+
+/// Checks the reply to one of [`stream_commands`] and records what it
+/// grants.
+pub fn accept_reply(
+    command: &Command,
+    reply: Response,
+    agreement: &mut Agreement,
+) -> Result<()> {
+    if reply == Response::Bye {
+        return Err(anyhow!("Server is shutting down"));
+    }
+
+    match (command, reply) {
+        (Command::Auth(_), Response::Error(msg)) => {
+            Err(anyhow!("Authentication failed: {msg}"))
+        }
+        (Command::Auth(_), _) => {
+            info!("Server accepted AUTH command");
+            Ok(())
+        }
+        (Command::Secure(protection), Response::Key(key)) => {
+            info!("Datagrams protected with {protection}");
+            agreement.opener = Some(Opener::new(key, *protection));
+            Ok(())
+        }
+        (Command::Secure(_), Response::Error(msg)) => {
+            Err(anyhow!("SECURE rejected: {msg}"))
+        }
+        (Command::Stream { .. }, Response::Ok) => {
+            info!("Server accepted STREAM command");
+            Ok(())
+        }
+        (Command::Stream { .. }, Response::Session(id)) => {
+            info!("Server opened session {id}, registering over UDP");
+            agreement.session_id = Some(id);
+            Ok(())
+        }
+        (Command::Stream { .. }, Response::Key(_)) => {
+            Err(anyhow!("Unexpected key in reply to STREAM"))
+        }
+        (Command::Stream { .. }, Response::Error(msg)) => {
+            Err(anyhow!("Server error: {msg}"))
+        }
+        (_, reply) => Err(anyhow!("Unexpected reply: {reply}")),
+    }
+}
+
+/// Turns a received payload into a quote. `PONG` and quotes for tickers
+/// outside `tickers` give `None`.
+pub fn decode_quote(
+    data: &str,
+    tickers: &Tickers,
+) -> Option<Result<StockQuote>> {
+    let data = data.trim();
+    if Pong::from_datagram(data.as_bytes()).is_some() {
+        debug!("Received PONG");
+        return None;
+    }
+
+    match data.parse::<StockQuote>() {
+        Ok(quote) if !tickers.contains(&quote.ticker) => {
+            debug!("Skipping unsubscribed ticker {}", quote.ticker);
+            None
+        }
+        Ok(quote) => Some(Ok(quote)),
+        Err(e) => Some(Err(anyhow!("Failed to parse quote '{data}': {e}"))),
+    }
+}
+
+/// What to do about a line the server pushed on the control connection.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reaction {
+    Ignore,
+    /// The stream is about to expire; ping right away.
+    KeepAlive,
+    /// The server is going away.
+    Stop,
+}
+
+pub fn react(line: &str, tickers: &Tickers) -> Reaction {
+    let line = line.trim();
+    let notification = match line.parse::<Notification>() {
+        Ok(notification) => notification,
+        Err(_) if line == Response::BYE => {
+            info!("Server closed the control connection");
+            return Reaction::Stop;
+        }
+        Err(e) => {
+            warn!("Unexpected control message '{line}': {e}");
+            return Reaction::Ignore;
+        }
+    };
+
+    match &notification {
+        Notification::Halted(ticker) | Notification::Resumed(ticker)
+            if !tickers.contains(ticker) =>
+        {
+            debug!("{notification}");
+            Reaction::Ignore
+        }
+        Notification::Expiring { .. } => {
+            log_notification(&notification);
+            Reaction::KeepAlive
+        }
+        Notification::Shutdown => {
+            log_notification(&notification);
+            Reaction::Stop
+        }
+        _ => {
+            log_notification(&notification);
+            Reaction::Ignore
+        }
+    }
+}
+
+pub fn log_notification(notification: &Notification) {
+    match notification {
+        Notification::Expiring { target, secs } => warn!(
+            "Server drops the stream to {target} in {secs}s without a ping"
+        ),
+        Notification::Halted(ticker) => {
+            warn!("Server halted quotes for {ticker}");
+        }
+        Notification::Resumed(ticker) => {
+            info!("Server resumed quotes for {ticker}");
+        }
+        Notification::Shutdown => info!("Server is shutting down"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{DatagramKey, Protection};
+    use rstest::rstest;
+    use std::net::SocketAddr;
+
+    fn config() -> ClientConfig {
+        let addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        ClientConfig::new(addr, Tickers::one("AAPL"))
+    }
+
+    #[rstest]
+    #[case(config(), &["STREAM"])]
+    #[case(config().with_token("t"), &["AUTH", "STREAM"])]
+    #[case(
+        config().with_token("t").with_datagram_protection(Protection::Mac),
+        &["AUTH", "SECURE", "STREAM"]
+    )]
+    #[case(
+        config()
+            .with_transport(Transport::Tcp)
+            .with_datagram_protection(Protection::Mac),
+        &["STREAM"]
+    )]
+    fn opens_with_configured_commands(
+        #[case] config: ClientConfig,
+        #[case] expected: &[&str],
+    ) {
+        let keywords: Vec<_> = stream_commands(&config)
+            .iter()
+            .map(|command| {
+                command.to_string().split(' ').next().unwrap().to_string()
+            })
+            .collect();
+
+        assert_eq!(keywords, expected);
+    }
+
+    #[test]
+    fn records_what_replies_grant() {
+        let mut agreement = Agreement::default();
+        let secure = Command::Secure(Protection::Aead);
+        let stream = stream_commands(&config()).remove(0);
+
+        accept_reply(
+            &secure,
+            Response::Key(DatagramKey::generate()),
+            &mut agreement,
+        )
+        .unwrap();
+        accept_reply(
+            &stream,
+            Response::Session(SessionId::from(3)),
+            &mut agreement,
+        )
+        .unwrap();
+
+        assert!(agreement.opener.is_some());
+        assert_eq!(agreement.session_id, Some(SessionId::from(3)));
+    }
+
+    #[rstest]
+    #[case(Command::Auth("t".to_string()), Response::Error("no".to_string()))]
+    #[case(Command::Secure(Protection::Mac), Response::Ok)]
+    #[case(Command::Ping(None), Response::Ok)]
+    #[case(Command::Ping(None), Response::Bye)]
+    fn rejects_refusals(#[case] command: Command, #[case] reply: Response) {
+        let result = accept_reply(&command, reply, &mut Agreement::default());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn decodes_subscribed_quotes() {
+        let tickers = Tickers::one("AAPL");
+        let quote = r#"{"ticker":"AAPL","price":"1","volume":1,"timestamp":1}"#;
+
+        assert!(decode_quote("PONG\n", &tickers).is_none());
+        assert!(decode_quote("PONG 3 1200", &tickers).is_none());
+        assert!(
+            decode_quote(&quote.replace("AAPL", "TSLA"), &tickers).is_none()
+        );
+        assert!(decode_quote(quote, &tickers).unwrap().is_ok());
+        assert!(decode_quote("garbage", &tickers).unwrap().is_err());
+    }
+
+    #[rstest]
+    #[case("NOTICE EXPIRING udp://127.0.0.1:9000 2\n", Reaction::KeepAlive)]
+    #[case("NOTICE HALTED AAPL", Reaction::Ignore)]
+    #[case("NOTICE HALTED TSLA", Reaction::Ignore)]
+    #[case("NOTICE SHUTDOWN", Reaction::Stop)]
+    #[case("BYE\n", Reaction::Stop)]
+    #[case("OK", Reaction::Ignore)]
+    fn reacts_to_control_lines(#[case] line: &str, #[case] expected: Reaction) {
+        assert_eq!(react(line, &Tickers::one("AAPL")), expected);
+    }
+
+    #[test]
+    fn skips_notifications_before_reply() {
+        assert!(parse_reply("NOTICE HALTED AAPL\n").is_none());
+        assert_eq!(parse_reply("OK\n").unwrap().unwrap(), Response::Ok);
+    }
+}
@@ -0,0 +1,87 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// How long to wait between reconnect attempts: doubling from `initial`
+/// up to `max`, with jitter so clients dropped together do not all come
+/// back at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// Give up after this many failed attempts in a row; `None` retries
+    /// forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay before attempt `attempt`, counting from 1: somewhere in
+    /// the upper half of the capped exponential delay.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let ceiling = self.initial.saturating_mul(1 << exponent).min(self.max);
+        let half = ceiling / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt <= max)
+    }
+}
+
+/// What happened to a [`QuoteClient`](crate::QuoteClient)'s connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    /// The stream ended without the client being shut down.
+    Disconnected { reason: String },
+    /// Reconnect attempt `attempt` starts after `delay`.
+    Reconnecting { attempt: u32, delay: Duration },
+    /// The stream is back, with the current tickers.
+    Reconnected { attempts: u32 },
+    /// Reconnecting is disabled or ran out of attempts; the client has
+    /// stopped.
+    GaveUp,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn delay_stays_within_the_capped_window(attempt in 1_u32..100) {
+            let backoff = Backoff::default();
+            let ceiling = backoff
+                .initial
+                .saturating_mul(1 << (attempt - 1).min(31))
+                .min(backoff.max);
+
+            let delay = backoff.delay(attempt);
+
+            prop_assert!(delay >= ceiling / 2);
+            prop_assert!(delay <= ceiling);
+        }
+    }
+
+    #[test]
+    fn limits_attempts() {
+        let backoff = Backoff {
+            max_attempts: Some(2),
+            ..Backoff::default()
+        };
+
+        assert!(backoff.allows(2));
+        assert!(!backoff.allows(3));
+        assert!(Backoff::default().allows(u32::MAX));
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use common::{CaptureRecord, CaptureWriter};
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// When [`Recorder`] starts a new capture file. The full file is renamed
+/// to the first free `<path>.<n>`, so `<path>` is always the live one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+/// Appends every datagram the client receives to a capture file (see
+/// [`common::CaptureReader`]). Clones share the file, which outlives
+/// reconnects.
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    path: PathBuf,
+    rotation: Rotation,
+    writer: CaptureWriter<BufWriter<File>>,
+    bytes: u64,
+    opened: Instant,
+    failures: u64,
+}
+
+impl Recorder {
+    /// Appends to the capture at `path`, or starts one there.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or the header written.
+    pub fn create(
+        path: impl Into<PathBuf>,
+        rotation: Rotation,
+    ) -> Result<Self> {
+        let path = path.into();
+        let (writer, bytes) = Inner::open(&path).with_context(|| {
+            format!("Failed to open capture {}", path.display())
+        })?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Inner {
+                path,
+                rotation,
+                writer,
+                bytes,
+                opened: Instant::now(),
+                failures: 0,
+            })),
+        })
+    }
+
+    /// Records `payload` as received from `source` just now. Failures are
+    /// logged rather than returned, so they never stop the stream.
+    pub fn record(&self, source: SocketAddr, payload: &[u8]) {
+        let record = CaptureRecord::now(source, payload);
+        self.inner.lock().record(&record);
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock();
+        f.debug_struct("Recorder")
+            .field("path", &inner.path)
+            .field("rotation", &inner.rotation)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Inner {
+    fn open(path: &Path) -> io::Result<(CaptureWriter<BufWriter<File>>, u64)> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let bytes = file.metadata()?.len();
+        let out = BufWriter::new(file);
+        if bytes == 0 {
+            let writer = CaptureWriter::new(out)?;
+            Ok((writer, common::CAPTURE_MAGIC.len() as u64))
+        } else {
+            Ok((CaptureWriter::resume(out), bytes))
+        }
+    }
+
+    fn record(&mut self, record: &CaptureRecord) {
+        if let Err(e) = self.write(record) {
+            self.failures += 1;
+            if self.failures == 1 {
+                warn!("Failed to record to {}: {e}", self.path.display());
+            } else {
+                debug!("Failed to record to {}: {e}", self.path.display());
+            }
+        }
+    }
+
+    fn write(&mut self, record: &CaptureRecord) -> io::Result<()> {
+        if self.is_due() {
+            self.rotate()?;
+        }
+        self.writer.write(record)?;
+        self.bytes += record.encoded_len() as u64;
+        Ok(())
+    }
+
+    fn is_due(&self) -> bool {
+        let full = self.rotation.max_bytes.is_some_and(|max| self.bytes >= max);
+        let old = self
+            .rotation
+            .max_age
+            .is_some_and(|max| self.opened.elapsed() >= max);
+        full || old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut n = 1;
+        let rotated = loop {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}"));
+            let candidate = PathBuf::from(name);
+            if !candidate.exists() {
+                break candidate;
+            }
+            n += 1;
+        };
+        fs::rename(&self.path, &rotated)?;
+        info!("Rotated capture to {}", rotated.display());
+
+        (self.writer, self.bytes) = Self::open(&self.path)?;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::CaptureReader;
+    use tempfile::TempDir;
+
+    fn read(path: &Path) -> Vec<Vec<u8>> {
+        CaptureReader::new(File::open(path).unwrap())
+            .unwrap()
+            .map(|record| record.unwrap().payload)
+            .collect()
+    }
+
+    fn source() -> SocketAddr {
+        "127.0.0.1:5000".parse().unwrap()
+    }
+
+    #[test]
+    fn appends_to_existing_capture() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("quotes.qcap");
+
+        Recorder::create(&path, Rotation::default())
+            .unwrap()
+            .record(source(), b"first");
+        Recorder::create(&path, Rotation::default())
+            .unwrap()
+            .record(source(), b"second");
+
+        assert_eq!(read(&path), [b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("quotes.qcap");
+        let rotation = Rotation {
+            max_bytes: Some(64),
+            max_age: None,
+        };
+
+        let recorder = Recorder::create(&path, rotation).unwrap();
+        for payload in [[1; 40], [2; 40], [3; 40]] {
+            recorder.record(source(), &payload);
+        }
+        drop(recorder);
+
+        let rotated = |n| dir.path().join(format!("quotes.qcap.{n}"));
+        assert_eq!(read(&rotated(1)), [vec![1; 40]]);
+        assert_eq!(read(&rotated(2)), [vec![2; 40]]);
+        assert_eq!(read(&path), [vec![3; 40]]);
+    }
+}
@@ -0,0 +1,242 @@
+use common::Probe;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Hands out the probes pings carry and times the `PONG`s echoing them.
+/// Clones share the same figures, which outlive reconnects.
+#[derive(Clone)]
+pub struct RttTracker {
+    epoch: Instant,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_seq: u64,
+    /// Highest sequence answered so far; older echoes are late.
+    answered_seq: Option<u64>,
+    answered: u64,
+    late: u64,
+    min: Option<Duration>,
+    total: Duration,
+    last: Option<Duration>,
+    /// Smoothed variation between consecutive round trips (RFC 3550).
+    jitter: Duration,
+    /// Most recent round trips, for the percentile.
+    window: VecDeque<Duration>,
+}
+
+impl RttTracker {
+    const WINDOW: usize = 1024;
+
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            inner: Arc::new(Mutex::new(Inner::default())),
+        }
+    }
+
+    /// The probe for the next ping.
+    pub fn probe(&self) -> Probe {
+        let mut inner = self.inner.lock();
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        drop(inner);
+
+        Probe {
+            seq,
+            sent_micros: self.micros(),
+        }
+    }
+
+    /// Records the `PONG` echoing `probe` and returns its round trip, or
+    /// `None` if it answers an older ping than one already answered, or no
+    /// ping of ours.
+    pub fn pong(&self, probe: Probe) -> Option<Duration> {
+        let now = self.micros();
+        let mut inner = self.inner.lock();
+        if probe.seq >= inner.next_seq || probe.sent_micros > now {
+            return None;
+        }
+        if inner.answered_seq.is_some_and(|seq| probe.seq <= seq) {
+            inner.late += 1;
+            return None;
+        }
+
+        let rtt = Duration::from_micros(now - probe.sent_micros);
+        inner.record(probe.seq, rtt);
+        drop(inner);
+        Some(rtt)
+    }
+
+    pub fn stats(&self) -> RttStats {
+        let inner = self.inner.lock();
+        let p99 = if inner.window.is_empty() {
+            None
+        } else {
+            let mut sorted: Vec<_> = inner.window.iter().copied().collect();
+            sorted.sort_unstable();
+            let rank = (sorted.len() * 99).div_ceil(100);
+            Some(sorted[rank.saturating_sub(1)])
+        };
+        let avg = u32::try_from(inner.answered)
+            .ok()
+            .filter(|&answered| answered > 0)
+            .map(|answered| inner.total / answered);
+
+        RttStats {
+            sent: inner.next_seq,
+            answered: inner.answered,
+            late: inner.late,
+            min: inner.min,
+            avg,
+            p99,
+            jitter: inner.jitter,
+        }
+    }
+
+    fn micros(&self) -> u64 {
+        u64::try_from(self.epoch.elapsed().as_micros()).unwrap_or(u64::MAX)
+    }
+}
+
+impl Default for RttTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inner {
+    fn record(&mut self, seq: u64, rtt: Duration) {
+        self.answered_seq = Some(seq);
+        self.answered += 1;
+        self.min = Some(self.min.map_or(rtt, |min| min.min(rtt)));
+        self.total += rtt;
+        if let Some(last) = self.last {
+            let variation = rtt.abs_diff(last);
+            let step = variation.abs_diff(self.jitter) / 16;
+            self.jitter = if variation > self.jitter {
+                self.jitter + step
+            } else {
+                self.jitter.saturating_sub(step)
+            };
+        }
+        self.last = Some(rtt);
+
+        if self.window.len() == RttTracker::WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(rtt);
+    }
+}
+
+/// Round trips of the pings sent so far. `p99` covers the most recent
+/// pings only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RttStats {
+    pub sent: u64,
+    pub answered: u64,
+    /// Answers that arrived after a newer ping had been answered.
+    pub late: u64,
+    pub min: Option<Duration>,
+    pub avg: Option<Duration>,
+    pub p99: Option<Duration>,
+    pub jitter: Duration,
+}
+
+impl fmt::Display for RttStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        if let (Some(min), Some(avg), Some(p99)) =
+            (self.min, self.avg, self.p99)
+        {
+            write!(
+                f,
+                "RTT min {:.2}ms avg {:.2}ms p99 {:.2}ms jitter {:.2}ms, ",
+                ms(min),
+                ms(avg),
+                ms(p99),
+                ms(self.jitter)
+            )?;
+        }
+        write!(f, "{}/{} pings answered", self.answered, self.sent)?;
+        if self.late > 0 {
+            write!(f, ", {} late", self.late)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn answer(tracker: &RttTracker, rtts_ms: &[u64]) {
+        let mut inner = tracker.inner.lock();
+        for &ms in rtts_ms {
+            let seq = inner.next_seq;
+            inner.next_seq += 1;
+            inner.record(seq, Duration::from_millis(ms));
+        }
+    }
+
+    #[test]
+    fn summarizes_round_trips() {
+        let tracker = RttTracker::new();
+        answer(&tracker, &[10, 30, 20]);
+        tracker.probe();
+
+        let stats = tracker.stats();
+
+        assert_eq!(stats.sent, 4);
+        assert_eq!(stats.answered, 3);
+        assert_eq!(stats.min, Some(Duration::from_millis(10)));
+        assert_eq!(stats.avg, Some(Duration::from_millis(20)));
+        assert_eq!(stats.p99, Some(Duration::from_millis(30)));
+        assert!(stats.jitter > Duration::ZERO);
+    }
+
+    #[test]
+    fn ignores_late_and_foreign_pongs() {
+        let tracker = RttTracker::new();
+        let first = tracker.probe();
+        let second = tracker.probe();
+
+        assert!(tracker.pong(second).is_some());
+        assert!(tracker.pong(first).is_none());
+        assert!(tracker.pong(second).is_none());
+        assert!(tracker.pong(Probe { seq: 9, ..second }).is_none());
+
+        let stats = tracker.stats();
+        assert_eq!((stats.answered, stats.late), (1, 2));
+    }
+
+    #[test]
+    fn reports_pings_without_answers() {
+        let tracker = RttTracker::new();
+        tracker.probe();
+
+        assert_eq!(tracker.stats().to_string(), "0/1 pings answered");
+    }
+
+    proptest! {
+        #[test]
+        fn p99_lies_between_min_and_max(
+            rtts in prop::collection::vec(1_u64..1000, 1..300)
+        ) {
+            let tracker = RttTracker::new();
+            answer(&tracker, &rtts);
+
+            let stats = tracker.stats();
+            let max = Duration::from_millis(*rtts.iter().max().unwrap());
+
+            prop_assert!(stats.min <= stats.p99);
+            prop_assert!(stats.p99 <= Some(max));
+            prop_assert!(stats.avg <= Some(max));
+        }
+    }
+}
@@ -0,0 +1,722 @@
+use anyhow::Result;
+use common::{
+    Command, FrameReader, Opener, Pong, Response, SessionId, StockQuote,
+    Tickers, UdpAddr,
+};
+use crossbeam::channel::Sender;
+use log::{debug, error, info, warn};
+use parking_lot::Mutex;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::borrow::Cow;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::config::{ClientConfig, Transport};
+use crate::protocol::{self, Agreement, Reaction};
+use crate::recorder::Recorder;
+use crate::rtt::RttTracker;
+
+/// What the server agreed to in reply to `STREAM`.
+struct StreamSetup<S> {
+    /// Keeps any bytes the server sent after the reply, which matters for
+    /// TCP streams.
+    control: BufReader<S>,
+    session_id: Option<SessionId>,
+    opener: Option<Opener>,
+}
+
+/// Whether a session runs, and why the server stopped it. Shared by the
+/// session's threads; the first reason given wins.
+#[derive(Clone)]
+struct Status {
+    running: Arc<AtomicBool>,
+    ended_by: Arc<Mutex<Option<String>>>,
+    last_pong: Arc<Mutex<Instant>>,
+}
+
+impl Status {
+    fn new() -> Self {
+        Self {
+            running: Arc::new(AtomicBool::new(true)),
+            ended_by: Arc::new(Mutex::new(None)),
+            last_pong: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Stops the session on the server's account.
+    fn end(&self, reason: impl Into<String>) {
+        let mut ended_by = self.ended_by.lock();
+        if self.running.swap(false, Ordering::SeqCst) {
+            *ended_by = Some(reason.into());
+        }
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    fn pong(&self) {
+        *self.last_pong.lock() = Instant::now();
+    }
+
+    fn since_pong(&self) -> Duration {
+        self.last_pong.lock().elapsed()
+    }
+}
+
+/// Where received payloads go: decoded into quotes for the configured
+/// tickers, and counted.
+struct QuoteSink {
+    tickers: Tickers,
+    quotes: Sender<StockQuote>,
+    received_bytes: Arc<AtomicU64>,
+    recorder: Option<Recorder>,
+}
+
+impl QuoteSink {
+    /// Any datagram, quote or not, goes to the capture.
+    fn record(&self, source: SocketAddr, payload: &[u8]) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(source, payload);
+        }
+    }
+
+    fn deliver(&self, payload: &[u8]) {
+        self.received_bytes
+            .fetch_add(payload.len() as u64, Ordering::Relaxed);
+        let data = String::from_utf8_lossy(payload);
+        match protocol::decode_quote(&data, &self.tickers) {
+            Some(Ok(quote)) => {
+                // Nobody listening just means the client is going away.
+                let _ = self.quotes.send(quote);
+            }
+            Some(Err(e)) => warn!("{e}"),
+            None => {}
+        }
+    }
+}
+
+/// Sends the datagrams keeping a UDP stream alive, each carrying a probe
+/// for timing its `PONG`.
+struct Pinger {
+    socket: Arc<UdpSocket>,
+    addr: SocketAddr,
+    session_id: Option<SessionId>,
+    rtt: RttTracker,
+}
+
+impl Pinger {
+    fn send(&self) {
+        let message =
+            protocol::keep_alive(self.session_id, Some(self.rtt.probe()));
+        match self
+            .socket
+            .send_to(message.to_string().as_bytes(), self.addr)
+        {
+            Ok(_) => debug!("Sent {message} to {}", self.addr),
+            Err(e) => warn!("Failed to send {message}: {e}"),
+        }
+    }
+}
+
+/// One stream from the server and the threads receiving it. Quotes for
+/// the configured tickers are sent to `quotes`.
+pub struct Session {
+    config: ClientConfig,
+    quotes: Sender<StockQuote>,
+    rtt: RttTracker,
+    received_bytes: Arc<AtomicU64>,
+    status: Status,
+    rejected_datagrams: Arc<AtomicU64>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Session {
+    const UDP_RECEIVE_BUFFER_SIZE: usize = 4096;
+    const TCP_READ_TIMEOUT_SECS: u64 = 5;
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+    const UDP_READ_TIMEOUT_MS: u64 = 500;
+    const TCP_STREAM_READ_TIMEOUT_MS: u64 = 500;
+    const CONTROL_READ_TIMEOUT_MS: u64 = 500;
+    const PING_TICK: Duration = Duration::from_millis(100);
+    /// Pings that may go unanswered before the server counts as lost.
+    const MISSED_PONGS: u32 = 3;
+
+    /// Sets up the stream and returns once its threads are running. Setup
+    /// errors, such as a rejected `STREAM`, are returned here. Ping round
+    /// trips are recorded in `rtt`, quote payload sizes added to
+    /// `received_bytes`.
+    pub fn start(
+        config: ClientConfig,
+        quotes: Sender<StockQuote>,
+        rtt: RttTracker,
+        received_bytes: Arc<AtomicU64>,
+    ) -> Result<Self> {
+        let mut session = Self {
+            config,
+            quotes,
+            rtt,
+            received_bytes,
+            status: Status::new(),
+            rejected_datagrams: Arc::new(AtomicU64::new(0)),
+            threads: Vec::new(),
+        };
+        session.threads = match session.config.multicast_group {
+            Some(group) => session.start_multicast(group)?,
+            None => session.start_stream()?,
+        };
+        Ok(session)
+    }
+
+    /// False once the server has ended the stream, the server was lost, or
+    /// [`Self::stop`] was called.
+    pub fn is_running(&self) -> bool {
+        self.status.is_running()
+    }
+
+    /// Why the stream ended, unless it was stopped by [`Self::stop`].
+    pub fn ended_by(&self) -> Option<String> {
+        self.status.ended_by.lock().clone()
+    }
+
+    /// Stops the threads and waits for them.
+    pub fn stop(self) {
+        self.status.stop();
+        for handle in self.threads {
+            if handle.join().is_err() {
+                error!("Client thread panicked");
+            }
+        }
+
+        let rejected = self.rejected_datagrams.load(Ordering::Relaxed);
+        if rejected > 0 {
+            warn!("Rejected {rejected} unauthenticated datagrams");
+        }
+    }
+
+    fn sink(&self) -> QuoteSink {
+        QuoteSink {
+            tickers: self.config.tickers.clone(),
+            quotes: self.quotes.clone(),
+            received_bytes: self.received_bytes.clone(),
+            recorder: self.config.recorder.clone(),
+        }
+    }
+
+    fn start_stream(&self) -> Result<Vec<JoinHandle<()>>> {
+        info!("Connecting to TCP server at {}", self.config.server_addr);
+        let tcp_stream = TcpStream::connect_timeout(
+            &self.config.server_addr,
+            Self::CONNECT_TIMEOUT,
+        )?;
+        tcp_stream.set_read_timeout(Some(Duration::from_secs(
+            Self::TCP_READ_TIMEOUT_SECS,
+        )))?;
+        // Kept to adjust timeouts once the stream is wrapped in TLS.
+        let socket = tcp_stream.try_clone()?;
+
+        match &self.config.tls {
+            Some(tls) => {
+                let tls_stream = tls.connect(tcp_stream)?;
+                info!("TLS session established");
+                self.start_control(tls_stream, &socket)
+            }
+            None => self.start_control(tcp_stream, &socket),
+        }
+    }
+
+    fn start_control(
+        &self,
+        control: impl Read + Write + Send + 'static,
+        socket: &TcpStream,
+    ) -> Result<Vec<JoinHandle<()>>> {
+        match self.config.transport {
+            Transport::Udp | Transport::Nat => self.start_udp(control, socket),
+            Transport::Tcp => self.start_tcp(control, socket),
+        }
+    }
+
+    fn start_udp(
+        &self,
+        control: impl Read + Write + Send + 'static,
+        socket: &TcpStream,
+    ) -> Result<Vec<JoinHandle<()>>> {
+        info!("Setting up UDP socket on port {}", self.config.udp_port);
+        let udp_socket =
+            Arc::new(UdpSocket::bind(self.config.udp_bind_addr())?);
+        udp_socket.set_read_timeout(Some(Duration::from_millis(
+            Self::UDP_READ_TIMEOUT_MS,
+        )))?;
+
+        // The control connection stays open for as long as we stream, and
+        // carries the server's notifications.
+        let StreamSetup {
+            control,
+            session_id,
+            opener,
+        } = self.send_stream_command(control)?;
+        socket.set_read_timeout(Some(Duration::from_millis(
+            Self::CONTROL_READ_TIMEOUT_MS,
+        )))?;
+
+        let pinger = Arc::new(Pinger {
+            socket: udp_socket.clone(),
+            addr: self.config.ping_addr,
+            session_id,
+            rtt: self.rtt.clone(),
+        });
+
+        let control_handle = self.spawn_control_thread(control, pinger.clone());
+        let ping_handle = self.spawn_ping_thread(pinger);
+        let recv_handle = self.spawn_receive_thread(udp_socket, opener);
+
+        Ok(vec![control_handle, ping_handle, recv_handle])
+    }
+
+    fn start_tcp(
+        &self,
+        control: impl Read + Write + Send + 'static,
+        socket: &TcpStream,
+    ) -> Result<Vec<JoinHandle<()>>> {
+        let StreamSetup {
+            control: reader, ..
+        } = self.send_stream_command(control)?;
+        socket.set_read_timeout(Some(Duration::from_millis(
+            Self::TCP_STREAM_READ_TIMEOUT_MS,
+        )))?;
+
+        let sink = self.sink();
+        let server_addr = self.config.server_addr;
+        let status = self.status.clone();
+        let handle = thread::spawn(move || {
+            Self::receive_frames_loop(
+                &mut FrameReader::new(reader),
+                &sink,
+                server_addr,
+                &status,
+            );
+        });
+        Ok(vec![handle])
+    }
+
+    fn start_multicast(&self, group: UdpAddr) -> Result<Vec<JoinHandle<()>>> {
+        info!("Joining multicast group {group}");
+        let udp_socket = Self::join_multicast_group(group)?;
+        udp_socket.set_read_timeout(Some(Duration::from_millis(
+            Self::UDP_READ_TIMEOUT_MS,
+        )))?;
+
+        Ok(vec![self.spawn_receive_thread(Arc::new(udp_socket), None)])
+    }
+
+    /// Binds the group port with `SO_REUSEADDR`, so several clients on one
+    /// host can listen to the same group.
+    pub(crate) fn join_multicast_group(group: UdpAddr) -> Result<UdpSocket> {
+        let group_addr = group.socket_addr();
+        let bind_addr = match group_addr.ip() {
+            IpAddr::V4(_) => {
+                SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), group_addr.port())
+            }
+            IpAddr::V6(_) => {
+                SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), group_addr.port())
+            }
+        };
+
+        let socket = Socket::new(
+            Domain::for_address(group_addr),
+            Type::DGRAM,
+            Some(Protocol::UDP),
+        )?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&bind_addr.into())?;
+
+        let socket = UdpSocket::from(socket);
+        match group_addr.ip() {
+            IpAddr::V4(ip) => {
+                socket.join_multicast_v4(&ip, &Ipv4Addr::UNSPECIFIED)?;
+            }
+            IpAddr::V6(ip) => socket.join_multicast_v6(&ip, 0)?,
+        }
+
+        Ok(socket)
+    }
+
+    /// Sends `STREAM`, preceded by `AUTH` and `SECURE` when configured, and
+    /// waits for the replies.
+    fn send_stream_command<S: Read + Write>(
+        &self,
+        control: S,
+    ) -> Result<StreamSetup<S>> {
+        let mut reader = BufReader::new(control);
+        let mut agreement = Agreement::default();
+
+        for command in protocol::stream_commands(&self.config) {
+            protocol::announce(&command);
+            let reply = Self::send_command(&mut reader, &command)?;
+            protocol::accept_reply(&command, reply, &mut agreement)?;
+        }
+
+        Ok(StreamSetup {
+            control: reader,
+            session_id: agreement.session_id,
+            opener: agreement.opener,
+        })
+    }
+
+    fn send_command(
+        control: &mut BufReader<impl Read + Write>,
+        command: &Command,
+    ) -> Result<Response> {
+        let stream = control.get_mut();
+        writeln!(stream, "{command}")?;
+        stream.flush()?;
+
+        loop {
+            let mut line = String::new();
+            control.read_line(&mut line)?;
+            if let Some(reply) = protocol::parse_reply(&line) {
+                return reply;
+            }
+        }
+    }
+
+    fn spawn_control_thread(
+        &self,
+        mut control: BufReader<impl Read + Send + 'static>,
+        pinger: Arc<Pinger>,
+    ) -> JoinHandle<()> {
+        let tickers = self.config.tickers.clone();
+        let status = self.status.clone();
+
+        thread::spawn(move || {
+            Self::control_loop(&mut control, &pinger, &tickers, &status);
+        })
+    }
+
+    /// Reads what the server pushes on the control connection while quotes
+    /// arrive over UDP. An expiry warning is answered with an immediate
+    /// keep-alive; shutdown or losing the connection ends the session.
+    fn control_loop(
+        control: &mut impl BufRead,
+        pinger: &Pinger,
+        tickers: &Tickers,
+        status: &Status,
+    ) {
+        let mut line = Vec::new();
+
+        while status.is_running() {
+            match control.read_until(b'\n', &mut line) {
+                Ok(0) => {
+                    status.end("Server closed the control connection");
+                    break;
+                }
+                Ok(_) => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => {
+                    status.end(format!("Control connection failed: {e}"));
+                    break;
+                }
+            }
+
+            let text = String::from_utf8_lossy(&line).trim().to_string();
+            line.clear();
+            match protocol::react(&text, tickers) {
+                Reaction::Ignore => {}
+                Reaction::KeepAlive => pinger.send(),
+                Reaction::Stop => {
+                    status.end("Server is shutting down");
+                    break;
+                }
+            }
+        }
+        debug!("Control thread stopped");
+    }
+
+    fn spawn_ping_thread(&self, pinger: Arc<Pinger>) -> JoinHandle<()> {
+        let interval = self.config.ping_interval;
+        let status = self.status.clone();
+
+        thread::spawn(move || {
+            Self::ping_loop(&pinger, interval, &status);
+        })
+    }
+
+    /// Pings every `interval`. Warns once a ping goes unanswered, and ends
+    /// the session once the server has not answered for
+    /// [`Self::MISSED_PONGS`] intervals.
+    fn ping_loop(pinger: &Pinger, interval: Duration, status: &Status) {
+        let unanswered = interval * 3 / 2;
+        let pong_timeout = interval * Self::MISSED_PONGS;
+        let mut unresponsive = false;
+        while status.is_running() {
+            let silence = status.since_pong();
+            if silence > pong_timeout {
+                status.end(format!(
+                    "No PONG for {}s",
+                    silence.as_secs_f32().round()
+                ));
+                break;
+            }
+            if silence > unanswered && !unresponsive {
+                warn!(
+                    "Server unresponsive: no PONG for {:.1}s",
+                    silence.as_secs_f32()
+                );
+            } else if silence <= unanswered && unresponsive {
+                info!("Server answers pings again");
+            }
+            unresponsive = silence > unanswered;
+
+            pinger.send();
+
+            // Sleep in ticks, so stopping does not wait out the interval.
+            let next_ping = Instant::now() + interval;
+            while status.is_running() && Instant::now() < next_ping {
+                thread::sleep(Self::PING_TICK.min(interval));
+            }
+        }
+        debug!("Ping thread stopped");
+    }
+
+    fn spawn_receive_thread(
+        &self,
+        udp_socket: Arc<UdpSocket>,
+        mut opener: Option<Opener>,
+    ) -> JoinHandle<()> {
+        let sink = self.sink();
+        let rejected = self.rejected_datagrams.clone();
+        let rtt = self.rtt.clone();
+        let status = self.status.clone();
+        let ping_addr = self.config.ping_addr;
+
+        thread::spawn(move || {
+            Self::receive_loop(
+                &udp_socket,
+                &sink,
+                &mut opener,
+                ping_addr,
+                &rejected,
+                &rtt,
+                &status,
+            );
+        })
+    }
+
+    fn receive_loop(
+        socket: &Arc<UdpSocket>,
+        sink: &QuoteSink,
+        opener: &mut Option<Opener>,
+        ping_addr: SocketAddr,
+        rejected: &AtomicU64,
+        rtt: &RttTracker,
+        status: &Status,
+    ) {
+        let mut buf = [0_u8; Self::UDP_RECEIVE_BUFFER_SIZE];
+
+        while status.is_running() {
+            match socket.recv_from(&mut buf) {
+                Ok((len, addr)) => {
+                    let Some(payload) = Self::open_datagram(
+                        &buf[..len],
+                        opener,
+                        addr,
+                        ping_addr,
+                        rejected,
+                    ) else {
+                        continue;
+                    };
+                    sink.record(addr, &payload);
+                    if *payload == *Response::BYE.as_bytes() {
+                        info!("Server ended the stream");
+                        status.end("Server ended the stream");
+                        break;
+                    }
+                    if let Some(Pong(probe)) = Pong::from_datagram(&payload) {
+                        status.pong();
+                        if let Some(rtt) = probe.and_then(|p| rtt.pong(p)) {
+                            debug!("PONG after {rtt:?}");
+                        }
+                        continue;
+                    }
+                    sink.deliver(&payload);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => {
+                    if status.is_running() {
+                        error!("Failed to receive UDP data: {e}");
+                    }
+                }
+            }
+        }
+        debug!("Receive thread stopped");
+    }
+
+    /// Unwraps a protected datagram, or returns `None` and counts it when
+    /// it fails authentication. `PONG` replies are never protected, so they
+    /// are only trusted from the server's `ping_addr`.
+    pub(crate) fn open_datagram<'a>(
+        datagram: &'a [u8],
+        opener: &mut Option<Opener>,
+        source: SocketAddr,
+        ping_addr: SocketAddr,
+        rejected: &AtomicU64,
+    ) -> Option<Cow<'a, [u8]>> {
+        let Some(opener) = opener else {
+            return Some(Cow::Borrowed(datagram));
+        };
+        if source == ping_addr && Pong::from_datagram(datagram).is_some() {
+            return Some(Cow::Borrowed(datagram));
+        }
+
+        match opener.open(datagram) {
+            Ok(payload) => Some(Cow::Owned(payload)),
+            Err(e) => {
+                if rejected.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!("Rejected datagram from {source}: {e}");
+                } else {
+                    debug!("Rejected datagram from {source}: {e}");
+                }
+                None
+            }
+        }
+    }
+
+    fn receive_frames_loop(
+        reader: &mut FrameReader<impl Read>,
+        sink: &QuoteSink,
+        source: SocketAddr,
+        status: &Status,
+    ) {
+        while status.is_running() {
+            match reader.read_frame() {
+                Ok(Some(frame)) => {
+                    sink.record(source, &frame);
+                    if frame == Response::BYE.as_bytes() {
+                        info!("Server ended the stream");
+                        status.end("Server ended the stream");
+                        break;
+                    }
+                    sink.deliver(&frame);
+                }
+                Ok(None) => {
+                    status.end("Server closed the TCP stream");
+                    break;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => {
+                    status.end(format!("Failed to receive TCP data: {e}"));
+                    break;
+                }
+            }
+        }
+        debug!("TCP receive loop stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{DatagramKey, Protection, Sealer};
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn source() -> SocketAddr {
+        "127.0.0.1:5001".parse().unwrap()
+    }
+
+    fn aead() -> Opener {
+        Opener::new(DatagramKey::generate(), Protection::Aead)
+    }
+
+    #[rstest]
+    fn counts_unauthenticated_datagrams(source: SocketAddr) {
+        let key = DatagramKey::generate();
+        let sealer = Sealer::new(key.clone(), Protection::Mac);
+        let mut opener = Some(Opener::new(key, Protection::Mac));
+        let rejected = AtomicU64::new(0);
+        let spoofed =
+            br#"{"ticker":"AAPL","price":"1","volume":1,"timestamp":1}"#;
+
+        let authentic = sealer.seal(b"quote");
+        let opened = Session::open_datagram(
+            &authentic,
+            &mut opener,
+            source,
+            source,
+            &rejected,
+        );
+        assert_eq!(opened.as_deref(), Some(&b"quote"[..]));
+
+        let forged = Session::open_datagram(
+            spoofed,
+            &mut opener,
+            source,
+            source,
+            &rejected,
+        );
+        assert!(forged.is_none());
+        let replayed = Session::open_datagram(
+            &authentic,
+            &mut opener,
+            source,
+            source,
+            &rejected,
+        );
+        assert!(replayed.is_none());
+        assert_eq!(rejected.load(Ordering::Relaxed), 2);
+    }
+
+    #[rstest]
+    fn passes_pong(
+        source: SocketAddr,
+        #[values(None, Some(aead()))] mut opener: Option<Opener>,
+        #[values(&b"PONG"[..], &b"PONG 4 1200"[..])] pong: &[u8],
+    ) {
+        let rejected = AtomicU64::new(0);
+
+        let opened = Session::open_datagram(
+            pong,
+            &mut opener,
+            source,
+            source,
+            &rejected,
+        );
+
+        assert_eq!(opened.as_deref(), Some(pong));
+        assert_eq!(rejected.load(Ordering::Relaxed), 0);
+    }
+
+    #[rstest]
+    fn rejects_unsealed_pong_from_elsewhere(source: SocketAddr) {
+        let ping_addr = SocketAddr::new(source.ip(), 5601);
+        let rejected = AtomicU64::new(0);
+
+        let opened = Session::open_datagram(
+            b"PONG",
+            &mut Some(aead()),
+            source,
+            ping_addr,
+            &rejected,
+        );
+
+        assert!(opened.is_none());
+        assert_eq!(rejected.load(Ordering::Relaxed), 1);
+    }
+}
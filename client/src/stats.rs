@@ -0,0 +1,220 @@
+//! Per-ticker figures for `--stats`: message rates, generation-to-receive
+//! latency and gaps in each ticker's stream.
+
+use quote_client::StockQuote;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Latencies in power-of-two millisecond buckets: `<1ms`, `<2ms`, `<4ms`
+/// up to `<1024ms`, and one for the rest.
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; Self::BUCKETS],
+    count: u64,
+    max_ms: u64,
+}
+
+impl Histogram {
+    const BUCKETS: usize = 12;
+
+    fn record(&mut self, ms: u64) {
+        let bucket = ms.bit_width() as usize;
+        self.buckets[bucket.min(Self::BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    /// Upper bound of the bucket holding quantile `q`.
+    fn quantile(&self, q: f64) -> String {
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let rank = ((self.count as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::label(bucket);
+            }
+        }
+        Self::label(Self::BUCKETS - 1)
+    }
+
+    fn label(bucket: usize) -> String {
+        if bucket == Self::BUCKETS - 1 {
+            format!(">={}ms", 1_u64 << (bucket - 1))
+        } else {
+            format!("<{}ms", 1_u64 << bucket)
+        }
+    }
+}
+
+#[derive(Default)]
+struct TickerStats {
+    received: u64,
+    /// `received` as of the previous report.
+    reported: u64,
+    latency: Histogram,
+    last_timestamp: Option<u64>,
+    /// Smoothed time between consecutive quotes, in milliseconds.
+    spacing_ms: Option<f64>,
+    gaps: u64,
+}
+
+impl TickerStats {
+    /// A quote later than this many typical spacings counts as a gap.
+    const GAP_FACTOR: f64 = 3.0;
+
+    #[allow(clippy::cast_precision_loss)]
+    fn record(&mut self, timestamp: u64, latency_ms: u64) {
+        self.received += 1;
+        self.latency.record(latency_ms);
+
+        if let Some(last) = self.last_timestamp.replace(timestamp) {
+            let spacing = timestamp.saturating_sub(last) as f64;
+            match self.spacing_ms {
+                Some(typical) if spacing > typical * Self::GAP_FACTOR => {
+                    self.gaps += 1;
+                }
+                Some(typical) => {
+                    self.spacing_ms = Some(typical + (spacing - typical) / 8.0);
+                }
+                None => self.spacing_ms = Some(spacing),
+            }
+        }
+    }
+}
+
+pub struct QuoteStats {
+    started: Instant,
+    last_report: Instant,
+    tickers: BTreeMap<String, TickerStats>,
+}
+
+impl QuoteStats {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            last_report: now,
+            tickers: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, quote: &StockQuote) {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| u64::try_from(now.as_millis()).unwrap_or(0));
+        self.record_at(quote, now_ms);
+    }
+
+    /// Quotes stamped later than `received_ms`, by a server clock ahead of
+    /// ours, count as 0ms.
+    fn record_at(&mut self, quote: &StockQuote, received_ms: u64) {
+        self.tickers
+            .entry(quote.ticker.clone())
+            .or_default()
+            .record(
+                quote.timestamp,
+                received_ms.saturating_sub(quote.timestamp),
+            );
+    }
+
+    /// One line per ticker, with its rate since the previous report, then
+    /// the totals.
+    pub fn report(&mut self, bytes_received: u64) -> String {
+        let since_report = self.last_report.elapsed().as_secs_f64();
+        let since_start = self.started.elapsed().as_secs_f64();
+        self.last_report = Instant::now();
+
+        let mut report = String::new();
+        let mut total = 0;
+        for (ticker, stats) in &mut self.tickers {
+            #[allow(clippy::cast_precision_loss)]
+            let rate = (stats.received - stats.reported) as f64
+                / since_report.max(1e-3);
+            stats.reported = stats.received;
+            total += stats.received;
+
+            let histogram: Vec<_> = stats
+                .latency
+                .buckets
+                .iter()
+                .enumerate()
+                .filter(|(_, &count)| count > 0)
+                .map(|(bucket, count)| {
+                    format!("{}:{count}", Histogram::label(bucket))
+                })
+                .collect();
+            let _ = writeln!(
+                report,
+                "{ticker:<6} {:>8} quotes {rate:>7.1}/s  latency p50 {} \
+                 p99 {} max {}ms  gaps {}  [{}]",
+                stats.received,
+                stats.latency.quantile(0.5),
+                stats.latency.quantile(0.99),
+                stats.latency.max_ms,
+                stats.gaps,
+                histogram.join(" "),
+            );
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        let _ = write!(
+            report,
+            "total  {total:>8} quotes {:>7.1}/s  {:.1} KiB received",
+            total as f64 / since_start.max(1e-3),
+            bytes_received as f64 / 1024.0,
+        );
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn quote(ticker: &str, timestamp: u64) -> StockQuote {
+        format!(
+            r#"{{"ticker":"{ticker}","price":"1","volume":1,"timestamp":{timestamp}}}"#
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case(0, "<1ms")]
+    #[case(1, "<2ms")]
+    #[case(3, "<4ms")]
+    #[case(1023, "<1024ms")]
+    #[case(5000, ">=1024ms")]
+    fn buckets_by_powers_of_two(#[case] ms: u64, #[case] label: &str) {
+        let mut histogram = Histogram::default();
+        histogram.record(ms);
+
+        assert_eq!(histogram.quantile(0.5), label);
+    }
+
+    #[test]
+    fn counts_gaps_and_latency() {
+        let mut stats = QuoteStats::new();
+        for timestamp in [100, 200, 300, 400, 1000, 1100] {
+            stats.record_at(&quote("AAPL", timestamp), timestamp + 5);
+        }
+        stats.record_at(&quote("TSLA", 100), 50);
+
+        let aapl = &stats.tickers["AAPL"];
+        assert_eq!(aapl.received, 6);
+        assert_eq!(aapl.gaps, 1);
+        assert_eq!(aapl.latency.max_ms, 5);
+        assert_eq!(stats.tickers["TSLA"].latency.max_ms, 0);
+
+        let report = stats.report(2048);
+        assert!(report.contains("gaps 1"));
+        assert!(report.ends_with("2.0 KiB received"));
+    }
+}
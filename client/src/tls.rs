@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Context, Result};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConnection, RootCertStore, StreamOwned};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use common::tls::{load_certs, load_private_key};
+
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// How to reach the server over TLS.
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    pub config: Arc<rustls::ClientConfig>,
+    pub server_name: ServerName<'static>,
+}
+
+impl TlsSettings {
+    /// Trusts the certificates in `ca`. With `identity` (certificate chain
+    /// and private key), authenticates to servers that ask for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be loaded, the key does not match
+    /// the certificate, or `server_name` is invalid.
+    pub fn new(
+        ca: &Path,
+        identity: Option<(&Path, &Path)>,
+        server_name: &str,
+    ) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(ca)? {
+            roots.add(cert).with_context(|| {
+                format!("Invalid CA certificate in {}", ca.display())
+            })?;
+        }
+
+        let builder =
+            rustls::ClientConfig::builder().with_root_certificates(roots);
+        let config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(
+                    load_certs(cert)?,
+                    load_private_key(key)?,
+                )
+                .context("Client certificate does not match private key")?,
+            None => builder.with_no_client_auth(),
+        };
+
+        let server_name = ServerName::try_from(server_name.to_string())
+            .with_context(|| {
+                format!("Invalid TLS server name '{server_name}'")
+            })?;
+
+        Ok(Self {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+
+    /// Completes the handshake, so certificate problems are reported
+    /// before any command is sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the handshake fails.
+    pub fn connect(&self, stream: TcpStream) -> Result<TlsStream> {
+        let conn = ClientConnection::new(
+            self.config.clone(),
+            self.server_name.clone(),
+        )?;
+        let mut tls = StreamOwned::new(conn, stream);
+        while tls.conn.is_handshaking() {
+            tls.conn
+                .complete_io(&mut tls.sock)
+                .map_err(|e| anyhow!("TLS handshake failed: {e}"))?;
+        }
+        Ok(tls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::CertifiedKey;
+    use rstest::{fixture, rstest};
+    use rustls::ServerConnection;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread;
+    use tempfile::TempDir;
+
+    struct Pki {
+        dir: TempDir,
+        server: Arc<rustls::ServerConfig>,
+    }
+
+    impl Pki {
+        fn path(&self, name: &str) -> PathBuf {
+            self.dir.path().join(name)
+        }
+    }
+
+    #[fixture]
+    fn pki() -> Pki {
+        let dir = TempDir::new().unwrap();
+        let CertifiedKey { cert, signing_key } =
+            rcgen::generate_simple_self_signed(vec![
+                "localhost".to_string(),
+                "127.0.0.1".to_string(),
+            ])
+            .unwrap();
+        fs::write(dir.path().join("ca.pem"), cert.pem()).unwrap();
+
+        let server = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone()],
+                rustls::pki_types::PrivateKeyDer::try_from(
+                    signing_key.serialize_der(),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+        Pki {
+            dir,
+            server: Arc::new(server),
+        }
+    }
+
+    /// Accepts one TLS connection and answers its first line with `OK`.
+    fn serve_once(server: Arc<rustls::ServerConfig>) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(server).unwrap();
+            let mut reader = BufReader::new(StreamOwned::new(conn, stream));
+            let mut line = String::new();
+            if reader.read_line(&mut line).is_ok() {
+                let _ = writeln!(reader.get_mut(), "OK");
+            }
+        });
+
+        addr
+    }
+
+    #[rstest]
+    #[case("localhost")]
+    #[case("127.0.0.1")]
+    fn talks_to_trusted_server(pki: Pki, #[case] server_name: &str) {
+        let addr = serve_once(pki.server.clone());
+        let settings =
+            TlsSettings::new(&pki.path("ca.pem"), None, server_name).unwrap();
+
+        let tls = settings.connect(TcpStream::connect(addr).unwrap()).unwrap();
+        let mut reader = BufReader::new(tls);
+        writeln!(reader.get_mut(), "PING").unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+
+        assert_eq!(line, "OK\n");
+    }
+
+    #[rstest]
+    fn rejects_wrong_server_name(pki: Pki) {
+        let addr = serve_once(pki.server.clone());
+        let settings =
+            TlsSettings::new(&pki.path("ca.pem"), None, "quotes.example")
+                .unwrap();
+
+        let result = settings.connect(TcpStream::connect(addr).unwrap());
+
+        assert!(result.is_err());
+    }
+
+    #[rstest]
+    fn rejects_missing_ca(pki: Pki) {
+        let result = TlsSettings::new(&pki.path("missing.pem"), None, "x");
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,422 @@
+//! `--tui`: a live dashboard of the subscribed tickers, with keys to add
+//! and remove tickers.
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use quote_client::{QuoteClient, StockQuote, Tickers};
+use ratatui::crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+const LOG_LINES: usize = 6;
+const HELP: &str = "a: add ticker  d: remove ticker  q: quit";
+
+/// Keeps the latest log lines for the events panel, as the dashboard owns
+/// the terminal.
+#[derive(Clone, Default)]
+pub struct LogPanel(Arc<Mutex<VecDeque<String>>>);
+
+impl Write for LogPanel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        let mut lines = self.0.lock();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            if lines.len() == LOG_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+        drop(lines);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Shows the dashboard until the user quits or the client stops.
+pub fn run(client: &QuoteClient, logs: &LogPanel) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new(client.tickers()).run(&mut terminal, client, logs);
+    ratatui::restore();
+    result
+}
+
+struct TickerRow {
+    open: Decimal,
+    last: Decimal,
+    previous: Decimal,
+    volume: u64,
+    ticks: u64,
+    updated: Instant,
+}
+
+impl TickerRow {
+    fn new(quote: &StockQuote, now: Instant) -> Self {
+        Self {
+            open: quote.price,
+            last: quote.price,
+            previous: quote.price,
+            volume: u64::from(quote.volume),
+            ticks: 1,
+            updated: now,
+        }
+    }
+
+    fn update(&mut self, quote: &StockQuote, now: Instant) {
+        self.previous = self.last;
+        self.last = quote.price;
+        self.volume += u64::from(quote.volume);
+        self.ticks += 1;
+        self.updated = now;
+    }
+
+    fn change(&self) -> Decimal {
+        self.last - self.open
+    }
+
+    fn change_percent(&self) -> Decimal {
+        if self.open.is_zero() {
+            Decimal::ZERO
+        } else {
+            self.change() / self.open * Decimal::ONE_HUNDRED
+        }
+    }
+
+    fn to_row(&self, ticker: &str, now: Instant) -> Row<'static> {
+        let tick = match self.last.cmp(&self.previous) {
+            Ordering::Greater => Style::new().fg(Color::Green),
+            Ordering::Less => Style::new().fg(Color::Red),
+            Ordering::Equal => Style::new(),
+        };
+        let trend = match self.change().cmp(&Decimal::ZERO) {
+            Ordering::Greater => Style::new().fg(Color::Green),
+            Ordering::Less => Style::new().fg(Color::Red),
+            Ordering::Equal => Style::new(),
+        };
+
+        Row::new([
+            Line::raw(ticker.to_string()),
+            Line::styled(format!("{:.2}", self.last.round_dp(2)), tick),
+            Line::styled(signed(self.change()), trend),
+            Line::styled(format!("{}%", signed(self.change_percent())), trend),
+            Line::raw(self.volume.to_string()),
+            Line::raw(self.ticks.to_string()),
+            Line::raw(format!(
+                "{:.1}s",
+                now.duration_since(self.updated).as_secs_f32()
+            )),
+        ])
+    }
+}
+
+/// `value` to cents, with a sign either way.
+fn signed(value: Decimal) -> String {
+    let value = value.round_dp(2);
+    if value.is_sign_negative() && !value.is_zero() {
+        format!("{value:.2}")
+    } else {
+        format!("+{:.2}", value.abs())
+    }
+}
+
+/// Last quotes per ticker; a ticker opens at its first quote.
+#[derive(Default)]
+struct Board {
+    rows: HashMap<String, TickerRow>,
+}
+
+impl Board {
+    fn record(&mut self, quote: &StockQuote, now: Instant) {
+        match self.rows.get_mut(&quote.ticker) {
+            Some(row) => row.update(quote, now),
+            None => {
+                self.rows
+                    .insert(quote.ticker.clone(), TickerRow::new(quote, now));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prompt {
+    Add,
+    Remove,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    None,
+    Quit,
+    Subscribe(Tickers),
+}
+
+struct App {
+    board: Board,
+    tickers: Tickers,
+    prompt: Option<(Prompt, String)>,
+    message: Option<String>,
+}
+
+impl App {
+    fn new(tickers: Tickers) -> Self {
+        Self {
+            board: Board::default(),
+            tickers,
+            prompt: None,
+            message: None,
+        }
+    }
+
+    fn run(
+        mut self,
+        terminal: &mut DefaultTerminal,
+        client: &QuoteClient,
+        logs: &LogPanel,
+    ) -> Result<()> {
+        while client.is_running() {
+            let now = Instant::now();
+            while let Some(quote) = client.recv_timeout(Duration::ZERO) {
+                self.board.record(&quote, now);
+            }
+            terminal.draw(|frame| self.draw(frame, logs, now))?;
+
+            if !event::poll(FRAME_INTERVAL)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            match self.on_key(key) {
+                Action::None => {}
+                Action::Quit => break,
+                Action::Subscribe(tickers) => {
+                    self.message = Some(match client.set_tickers(tickers) {
+                        Ok(()) => format!("Subscribed to {}", client.tickers()),
+                        Err(e) => format!("Resubscribing failed: {e}"),
+                    });
+                    self.tickers = client.tickers();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Action {
+        if key.kind != KeyEventKind::Press {
+            return Action::None;
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL)
+            && key.code == KeyCode::Char('c')
+        {
+            return Action::Quit;
+        }
+
+        let Some((prompt, input)) = &mut self.prompt else {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+                KeyCode::Char('a' | '+') => {
+                    self.prompt = Some((Prompt::Add, String::new()));
+                }
+                KeyCode::Char('d' | '-') => {
+                    self.prompt = Some((Prompt::Remove, String::new()));
+                }
+                _ => {}
+            }
+            return Action::None;
+        };
+
+        match key.code {
+            KeyCode::Char(c)
+                if c.is_ascii_alphanumeric() || ".-".contains(c) =>
+            {
+                input.push(c.to_ascii_uppercase());
+            }
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                let (prompt, ticker) = (*prompt, input.clone());
+                self.prompt = None;
+                return self.submit(prompt, &ticker);
+            }
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn submit(&mut self, prompt: Prompt, ticker: &str) -> Action {
+        if ticker.is_empty() {
+            return Action::None;
+        }
+
+        let subscribed = self.tickers.contains(ticker);
+        let tickers: Vec<_> = match prompt {
+            Prompt::Add if subscribed => {
+                self.message = Some(format!("{ticker} is already subscribed"));
+                return Action::None;
+            }
+            Prompt::Remove if !subscribed => {
+                self.message = Some(format!("{ticker} is not subscribed"));
+                return Action::None;
+            }
+            Prompt::Add => self.tickers.iter().chain([ticker]).collect(),
+            Prompt::Remove => {
+                self.tickers.iter().filter(|&t| t != ticker).collect()
+            }
+        };
+
+        let message = match (tickers.join(",").parse(), prompt) {
+            (Ok(tickers), _) => return Action::Subscribe(tickers),
+            (Err(_), Prompt::Remove) => "Cannot remove the last ticker".into(),
+            (Err(e), Prompt::Add) => format!("Invalid ticker {ticker}: {e}"),
+        };
+        self.message = Some(message);
+        Action::None
+    }
+
+    fn draw(&self, frame: &mut Frame, logs: &LogPanel, now: Instant) {
+        #[allow(clippy::cast_possible_truncation)]
+        let [quotes_area, log_area, footer_area] = Layout::vertical([
+            Constraint::Min(4),
+            Constraint::Length(LOG_LINES as u16 + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let header = Row::new([
+            "TICKER", "LAST", "CHANGE", "CHANGE %", "VOLUME", "TICKS", "AGE",
+        ])
+        .style(Style::new().add_modifier(Modifier::BOLD));
+        let rows = self.tickers.iter().map(|ticker| {
+            self.board.rows.get(ticker).map_or_else(
+                || Row::new([ticker, "-", "-", "-", "-", "0", "-"]),
+                |row| row.to_row(ticker, now),
+            )
+        });
+        let widths = [
+            Constraint::Length(8),
+            Constraint::Length(12),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Length(8),
+            Constraint::Length(8),
+        ];
+        frame.render_widget(
+            Table::new(rows, widths)
+                .header(header)
+                .block(Block::bordered().title(" Quotes ")),
+            quotes_area,
+        );
+
+        let lines: Vec<_> = logs
+            .0
+            .lock()
+            .iter()
+            .map(|line| Line::raw(line.clone()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Events ")),
+            log_area,
+        );
+
+        frame.render_widget(Paragraph::new(self.footer()), footer_area);
+    }
+
+    fn footer(&self) -> String {
+        match (&self.prompt, &self.message) {
+            (Some((Prompt::Add, input)), _) => {
+                format!("Add ticker: {input}_  (Enter: confirm, Esc: cancel)")
+            }
+            (Some((Prompt::Remove, input)), _) => {
+                format!(
+                    "Remove ticker: {input}_  (Enter: confirm, Esc: cancel)"
+                )
+            }
+            (None, Some(message)) => format!("{message}  |  {HELP}"),
+            (None, None) => HELP.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn quote(ticker: &str, price: &str) -> StockQuote {
+        format!(
+            r#"{{"ticker":"{ticker}","price":"{price}","volume":10,"timestamp":1}}"#
+        )
+        .parse()
+        .unwrap()
+    }
+
+    fn press(app: &mut App, keys: &str) -> Action {
+        let mut action = Action::None;
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\x1b' => KeyCode::Esc,
+                c => KeyCode::Char(c),
+            };
+            action = app.on_key(KeyEvent::from(code));
+        }
+        action
+    }
+
+    #[test]
+    fn tracks_change_since_open() {
+        let mut board = Board::default();
+        let now = Instant::now();
+        for price in ["100", "102", "101"] {
+            board.record(&quote("AAPL", price), now);
+        }
+
+        let row = &board.rows["AAPL"];
+        assert_eq!(row.change(), Decimal::ONE);
+        assert_eq!(signed(row.change_percent()), "+1.00");
+        assert_eq!(row.last.cmp(&row.previous), Ordering::Less);
+        assert_eq!((row.volume, row.ticks), (30, 3));
+    }
+
+    #[rstest]
+    #[case("atsla\n", Action::Subscribe("AAPL,MSFT,TSLA".parse().unwrap()))]
+    #[case("dmsft\n", Action::Subscribe("AAPL".parse().unwrap()))]
+    #[case("aaapl\n", Action::None)]
+    #[case("dtsla\n", Action::None)]
+    #[case("atsla\x1b", Action::None)]
+    #[case("q", Action::Quit)]
+    fn edits_subscription(#[case] keys: &str, #[case] expected: Action) {
+        let mut app = App::new("AAPL,MSFT".parse().unwrap());
+
+        assert_eq!(press(&mut app, keys), expected);
+        assert!(app.prompt.is_none());
+    }
+
+    #[test]
+    fn keeps_the_last_ticker() {
+        let mut app = App::new(Tickers::one("AAPL"));
+
+        assert_eq!(press(&mut app, "daapl\n"), Action::None);
+        assert_eq!(
+            app.message.as_deref(),
+            Some("Cannot remove the last ticker")
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Capture files: received datagrams with their receive time and source,
+//! for analysing a session later.
+//!
+//! A capture starts with [`CAPTURE_MAGIC`], then holds one frame (see
+//! [`write_frame`]) per datagram: the receive time as big-endian
+//! microseconds since the Unix epoch, the source address, and the payload.
+//! An address is a family byte (4 or 6), the IP octets and a big-endian
+//! port.
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::frame::{write_frame, FrameReader};
+
+/// `QCAP` and the format version.
+pub const CAPTURE_MAGIC: &[u8; 5] = b"QCAP\x01";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    pub received_micros: u64,
+    pub source: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// A record of `payload` received from `source` just now.
+    pub fn now(source: SocketAddr, payload: &[u8]) -> Self {
+        let received_micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| u64::try_from(now.as_micros()).unwrap_or(0));
+        Self {
+            received_micros,
+            source,
+            payload: payload.to_vec(),
+        }
+    }
+
+    /// Bytes the record takes up in a capture, frame header included.
+    pub const fn encoded_len(&self) -> usize {
+        let ip_len = if self.source.is_ipv4() { 4 } else { 16 };
+        4 + 8 + 1 + ip_len + 2 + self.payload.len()
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(self.encoded_len() - 4);
+        body.extend_from_slice(&self.received_micros.to_be_bytes());
+        match self.source.ip() {
+            IpAddr::V4(ip) => {
+                body.push(4);
+                body.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                body.push(6);
+                body.extend_from_slice(&ip.octets());
+            }
+        }
+        body.extend_from_slice(&self.source.port().to_be_bytes());
+        body.extend_from_slice(&self.payload);
+        body
+    }
+
+    fn decode(body: &[u8]) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Truncated capture record",
+            )
+        };
+        let (micros, rest) =
+            body.split_first_chunk::<8>().ok_or_else(invalid)?;
+        let (family, rest) = rest.split_first().ok_or_else(invalid)?;
+        let (ip, rest): (IpAddr, _) = match family {
+            4 => {
+                let (octets, rest) =
+                    rest.split_first_chunk::<4>().ok_or_else(invalid)?;
+                (Ipv4Addr::from(*octets).into(), rest)
+            }
+            6 => {
+                let (octets, rest) =
+                    rest.split_first_chunk::<16>().ok_or_else(invalid)?;
+                (Ipv6Addr::from(*octets).into(), rest)
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown address family {other} in capture"),
+                ))
+            }
+        };
+        let (port, payload) =
+            rest.split_first_chunk::<2>().ok_or_else(invalid)?;
+
+        Ok(Self {
+            received_micros: u64::from_be_bytes(*micros),
+            source: SocketAddr::new(ip, u16::from_be_bytes(*port)),
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// Appends records to a capture.
+pub struct CaptureWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Starts a new capture on `inner`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the header cannot be written.
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(CAPTURE_MAGIC)?;
+        Ok(Self { inner })
+    }
+
+    /// Continues a capture whose header `inner` already holds.
+    pub const fn resume(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Writes and flushes one record.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload is too large for a frame or the
+    /// underlying writer fails.
+    pub fn write(&mut self, record: &CaptureRecord) -> io::Result<()> {
+        write_frame(&mut self.inner, &record.encode())
+    }
+}
+
+/// Reads the records of a capture written by [`CaptureWriter`].
+pub struct CaptureReader<R> {
+    frames: FrameReader<R>,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// # Errors
+    ///
+    /// Returns an error if `inner` does not start with [`CAPTURE_MAGIC`].
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut magic = [0; CAPTURE_MAGIC.len()];
+        inner.read_exact(&mut magic)?;
+        if magic != *CAPTURE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a quote capture",
+            ));
+        }
+        Ok(Self {
+            frames: FrameReader::new(inner),
+        })
+    }
+
+    /// The next record, or `None` at the end of the capture.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails or a record is malformed.
+    pub fn read_record(&mut self) -> io::Result<Option<CaptureRecord>> {
+        self.frames
+            .read_frame()?
+            .map(|body| CaptureRecord::decode(&body))
+            .transpose()
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::io::Cursor;
+
+    fn records() -> impl Strategy<Value = Vec<CaptureRecord>> {
+        let record = (
+            any::<u64>(),
+            any::<IpAddr>(),
+            any::<u16>(),
+            prop::collection::vec(any::<u8>(), 0..256),
+        )
+            .prop_map(|(received_micros, ip, port, payload)| CaptureRecord {
+                received_micros,
+                source: SocketAddr::new(ip, port),
+                payload,
+            });
+        prop::collection::vec(record, 0..10)
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip(records in records()) {
+            let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+            for record in &records {
+                writer.write(record).unwrap();
+            }
+
+            let len: usize = records.iter().map(CaptureRecord::encoded_len).sum();
+            prop_assert_eq!(writer.inner.len(), CAPTURE_MAGIC.len() + len);
+
+            let reader = CaptureReader::new(Cursor::new(writer.inner)).unwrap();
+            let read: Vec<_> = reader.map(Result::unwrap).collect();
+
+            prop_assert_eq!(read, records);
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(CaptureReader::new(Cursor::new(b"QCAP\x02")).is_err());
+        assert!(CaptureReader::new(Cursor::new(b"QC")).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let mut capture = CAPTURE_MAGIC.to_vec();
+        write_frame(&mut capture, &[0; 9]).unwrap();
+
+        let mut reader = CaptureReader::new(Cursor::new(capture)).unwrap();
+
+        assert!(reader.read_record().is_err());
+    }
+}
@@ -0,0 +1,385 @@
+use anyhow::anyhow;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const KEY_LEN: usize = 32;
+const COUNTER_LEN: usize = 8;
+const HEADER_LEN: usize = 1 + COUNTER_LEN;
+const MAC_TAG_LEN: usize = 32;
+const AEAD_TAG_LEN: usize = 16;
+
+/// How quote datagrams of a session are protected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protection {
+    /// HMAC-SHA256 tag, payload in clear text.
+    Mac,
+    /// ChaCha20-Poly1305, payload encrypted and authenticated.
+    Aead,
+}
+
+impl Protection {
+    const MAC_KEYWORD: &str = "mac";
+    const AEAD_KEYWORD: &str = "aead";
+
+    const fn id(self) -> u8 {
+        match self {
+            Self::Mac => 1,
+            Self::Aead => 2,
+        }
+    }
+}
+
+impl FromStr for Protection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s.eq_ignore_ascii_case(Self::MAC_KEYWORD) {
+            return Ok(Self::Mac);
+        }
+
+        if s.eq_ignore_ascii_case(Self::AEAD_KEYWORD) {
+            return Ok(Self::Aead);
+        }
+
+        Err(anyhow!("Unknown datagram protection '{s}'"))
+    }
+}
+
+impl fmt::Display for Protection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mac => write!(f, "{}", Self::MAC_KEYWORD),
+            Self::Aead => write!(f, "{}", Self::AEAD_KEYWORD),
+        }
+    }
+}
+
+/// Secret shared by the server and one client for one stream. Sent over
+/// the control connection as hex.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DatagramKey([u8; KEY_LEN]);
+
+impl DatagramKey {
+    pub fn generate() -> Self {
+        let mut key = [0_u8; KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self(key)
+    }
+}
+
+impl FromStr for DatagramKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s.len() != KEY_LEN * 2 || !s.is_ascii() {
+            return Err(anyhow!("Key must be {} hex digits", KEY_LEN * 2));
+        }
+
+        let mut key = [0_u8; KEY_LEN];
+        for (byte, pair) in key.iter_mut().zip(s.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair)?;
+            *byte = u8::from_str_radix(pair, 16)
+                .map_err(|e| anyhow!("Invalid key: {e}"))?;
+        }
+
+        Ok(Self(key))
+    }
+}
+
+impl fmt::Display for DatagramKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl fmt::Debug for DatagramKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DatagramKey(..)")
+    }
+}
+
+/// Why a received datagram was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum OpenError {
+    #[error("datagram too short")]
+    TooShort,
+    #[error("datagram not protected with {0}")]
+    WrongProtection(Protection),
+    #[error("authentication failed")]
+    BadTag,
+    #[error("counter {0} already seen")]
+    Replayed(u64),
+}
+
+/// Protects outgoing datagrams. Clones share the counter, so a stream that
+/// moves to a new address never reuses a nonce.
+#[derive(Debug, Clone)]
+pub struct Sealer {
+    key: DatagramKey,
+    protection: Protection,
+    counter: Arc<AtomicU64>,
+}
+
+impl Sealer {
+    pub fn new(key: DatagramKey, protection: Protection) -> Self {
+        Self {
+            key,
+            protection,
+            counter: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Lays out `protection id | counter (u64 BE) | body | tag`.
+    ///
+    /// # Panics
+    ///
+    /// Never in practice: ChaCha20-Poly1305 only fails on payloads larger
+    /// than 256 GiB.
+    pub fn seal(&self, payload: &[u8]) -> Vec<u8> {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed);
+        let mut datagram = Vec::with_capacity(
+            HEADER_LEN + payload.len() + MAC_TAG_LEN.max(AEAD_TAG_LEN),
+        );
+        datagram.push(self.protection.id());
+        datagram.extend_from_slice(&counter.to_be_bytes());
+
+        match self.protection {
+            Protection::Mac => {
+                datagram.extend_from_slice(payload);
+                let tag = mac(&self.key, &datagram).finalize().into_bytes();
+                datagram.extend_from_slice(&tag);
+            }
+            Protection::Aead => {
+                let aad = datagram.clone();
+                let sealed = cipher(&self.key)
+                    .encrypt(
+                        &nonce(counter).into(),
+                        Payload {
+                            msg: payload,
+                            aad: &aad,
+                        },
+                    )
+                    .expect("ChaCha20-Poly1305 encryption cannot fail");
+                datagram.extend_from_slice(&sealed);
+            }
+        }
+
+        datagram
+    }
+}
+
+/// Verifies incoming datagrams and drops replays. Counters must increase,
+/// so a datagram overtaken by a later one is dropped as well.
+#[derive(Debug, Clone)]
+pub struct Opener {
+    key: DatagramKey,
+    protection: Protection,
+    last_counter: Option<u64>,
+}
+
+impl Opener {
+    pub const fn new(key: DatagramKey, protection: Protection) -> Self {
+        Self {
+            key,
+            protection,
+            last_counter: None,
+        }
+    }
+
+    /// Returns the payload of an authentic datagram.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`OpenError`] if the datagram is malformed, forged or
+    /// replayed.
+    pub fn open(&mut self, datagram: &[u8]) -> Result<Vec<u8>, OpenError> {
+        let tag_len = match self.protection {
+            Protection::Mac => MAC_TAG_LEN,
+            Protection::Aead => AEAD_TAG_LEN,
+        };
+        if datagram.len() < HEADER_LEN + tag_len {
+            return Err(OpenError::TooShort);
+        }
+        if datagram[0] != self.protection.id() {
+            return Err(OpenError::WrongProtection(self.protection));
+        }
+
+        let (header, rest) = datagram.split_at(HEADER_LEN);
+        let mut counter = [0_u8; COUNTER_LEN];
+        counter.copy_from_slice(&header[1..]);
+        let counter = u64::from_be_bytes(counter);
+
+        let payload = match self.protection {
+            Protection::Mac => {
+                let (body, tag) = datagram.split_at(datagram.len() - tag_len);
+                mac(&self.key, body)
+                    .verify_slice(tag)
+                    .map_err(|_| OpenError::BadTag)?;
+                body[HEADER_LEN..].to_vec()
+            }
+            Protection::Aead => cipher(&self.key)
+                .decrypt(
+                    &nonce(counter).into(),
+                    Payload {
+                        msg: rest,
+                        aad: header,
+                    },
+                )
+                .map_err(|_| OpenError::BadTag)?,
+        };
+
+        // Only authentic datagrams may advance the counter.
+        if self.last_counter.is_some_and(|last| counter <= last) {
+            return Err(OpenError::Replayed(counter));
+        }
+        self.last_counter = Some(counter);
+
+        Ok(payload)
+    }
+}
+
+fn mac(key: &DatagramKey, data: &[u8]) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(&key.0)
+        .expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac
+}
+
+fn cipher(key: &DatagramKey) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(&key.0.into())
+}
+
+fn nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0_u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::{any, prop};
+    use proptest::{prop_assert_eq, proptest};
+    use rstest::rstest;
+
+    fn pair(protection: Protection) -> (Sealer, Opener) {
+        let key = DatagramKey::generate();
+        (Sealer::new(key.clone(), protection), Opener::new(key, protection))
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip(
+            payload in prop::collection::vec(any::<u8>(), 0..512),
+            aead in any::<bool>(),
+        ) {
+            let protection =
+                if aead { Protection::Aead } else { Protection::Mac };
+            let (sealer, mut opener) = pair(protection);
+
+            let opened = opener.open(&sealer.seal(&payload)).unwrap();
+            prop_assert_eq!(opened, payload);
+        }
+
+        #[test]
+        fn key_roundtrip(bytes in any::<[u8; 32]>()) {
+            let key = DatagramKey(bytes);
+            let parsed: DatagramKey = key.to_string().parse().unwrap();
+            prop_assert_eq!(parsed, key);
+        }
+    }
+
+    #[test]
+    fn aead_hides_payload() {
+        let (sealer, _) = pair(Protection::Aead);
+        let datagram = sealer.seal(b"AAPL");
+        assert!(!datagram.windows(4).any(|w| w == b"AAPL"));
+    }
+
+    #[rstest]
+    #[case(Protection::Mac)]
+    #[case(Protection::Aead)]
+    fn rejects_tampering(#[case] protection: Protection) {
+        let (sealer, mut opener) = pair(protection);
+        let mut datagram = sealer.seal(b"{\"ticker\":\"AAPL\"}");
+        let last = datagram.len() - 1;
+        datagram[last] ^= 1;
+
+        assert_eq!(opener.open(&datagram), Err(OpenError::BadTag));
+    }
+
+    #[rstest]
+    #[case(Protection::Mac)]
+    #[case(Protection::Aead)]
+    fn rejects_wrong_key(#[case] protection: Protection) {
+        let (sealer, _) = pair(protection);
+        let (_, mut other) = pair(protection);
+
+        assert_eq!(other.open(&sealer.seal(b"x")), Err(OpenError::BadTag));
+    }
+
+    #[rstest]
+    #[case(&b""[..])]
+    #[case(&b"PONG"[..])]
+    fn rejects_short_datagrams(#[case] datagram: &[u8]) {
+        let (_, mut opener) = pair(Protection::Mac);
+        assert_eq!(opener.open(datagram), Err(OpenError::TooShort));
+    }
+
+    #[test]
+    fn rejects_plain_quote() {
+        let (_, mut opener) = pair(Protection::Mac);
+        let plain =
+            br#"{"ticker":"AAPL","price":"1","volume":1,"timestamp":1}"#;
+        assert_eq!(
+            opener.open(plain),
+            Err(OpenError::WrongProtection(Protection::Mac))
+        );
+    }
+
+    #[test]
+    fn rejects_replay() {
+        let (sealer, mut opener) = pair(Protection::Aead);
+        let first = sealer.seal(b"first");
+        let second = sealer.seal(b"second");
+
+        opener.open(&first).unwrap();
+        opener.open(&second).unwrap();
+
+        assert_eq!(opener.open(&first), Err(OpenError::Replayed(0)));
+    }
+
+    #[test]
+    fn clones_share_counter() {
+        let (sealer, mut opener) = pair(Protection::Aead);
+        let moved = sealer.clone();
+
+        opener.open(&sealer.seal(b"a")).unwrap();
+        assert!(opener.open(&moved.seal(b"b")).is_ok());
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("abc")]
+    #[case(&"zz".repeat(32))]
+    fn rejects_invalid_key(#[case] input: &str) {
+        assert!(input.parse::<DatagramKey>().is_err());
+    }
+
+    #[rstest]
+    #[case("mac", Protection::Mac)]
+    #[case("AEAD", Protection::Aead)]
+    fn parses_protection(#[case] input: &str, #[case] expected: Protection) {
+        assert_eq!(input.parse::<Protection>().unwrap(), expected);
+    }
+}
@@ -0,0 +1,207 @@
+use std::io::{self, Read, Write};
+
+/// Upper bound on a single frame payload, guards against garbage lengths.
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+const LEN_PREFIX_SIZE: usize = 4;
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// Writes `payload` prefixed with its length as a big-endian `u32`.
+///
+/// # Errors
+///
+/// Returns an error if the payload exceeds [`MAX_FRAME_LEN`] or the
+/// underlying writer fails.
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Frame too large: {} bytes", payload.len()),
+        ));
+    }
+
+    let len = u32::try_from(payload.len())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Reads length-prefixed frames written by [`write_frame`].
+///
+/// Partially received frames are kept between calls, so a reader with a
+/// read timeout can be polled without losing synchronisation.
+pub struct FrameReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub const fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+        }
+    }
+
+    pub const fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns the next complete frame, or `None` on a clean end of stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying reader fails (including read
+    /// timeouts), the stream ends in the middle of a frame, or a frame
+    /// announces a length above [`MAX_FRAME_LEN`].
+    pub fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(Some(frame));
+            }
+
+            let mut chunk = [0_u8; READ_CHUNK_SIZE];
+            match self.inner.read(&mut chunk)? {
+                0 if self.buf.is_empty() => return Ok(None),
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Stream ended in the middle of a frame",
+                    ))
+                }
+                n => self.buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+
+    fn take_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let Some(prefix) = self.buf.get(..LEN_PREFIX_SIZE) else {
+            return Ok(None);
+        };
+
+        let mut len_bytes = [0_u8; LEN_PREFIX_SIZE];
+        len_bytes.copy_from_slice(prefix);
+        let len = u32::from_be_bytes(len_bytes) as usize;
+
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Frame too large: {len} bytes"),
+            ));
+        }
+
+        if self.buf.len() < LEN_PREFIX_SIZE + len {
+            return Ok(None);
+        }
+
+        let frame = self.buf[LEN_PREFIX_SIZE..LEN_PREFIX_SIZE + len].to_vec();
+        self.buf.drain(..LEN_PREFIX_SIZE + len);
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::{prop, Strategy};
+    use proptest::{prop_assert_eq, proptest};
+    use std::io::Cursor;
+
+    fn payloads() -> impl Strategy<Value = Vec<Vec<u8>>> {
+        prop::collection::vec(prop::collection::vec(0_u8.., 0..512), 0..10)
+    }
+
+    fn encode(payloads: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for payload in payloads {
+            write_frame(&mut out, payload).unwrap();
+        }
+        out
+    }
+
+    /// Yields at most one byte per read and a timeout between bytes.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        stalled: bool,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.stalled = !self.stalled;
+            if self.stalled {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            if self.pos == self.data.len() {
+                return Ok(0);
+            }
+            buf[0] = self.data[self.pos];
+            self.pos += 1;
+            Ok(1)
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip(payloads in payloads()) {
+            let mut reader = FrameReader::new(Cursor::new(encode(&payloads)));
+
+            for payload in &payloads {
+                let frame = reader.read_frame().unwrap();
+                prop_assert_eq!(frame.as_ref(), Some(payload));
+            }
+            prop_assert_eq!(reader.read_frame().unwrap(), None);
+        }
+
+        #[test]
+        fn survives_timeouts(payloads in payloads()) {
+            let mut reader = FrameReader::new(Trickle {
+                data: encode(&payloads),
+                pos: 0,
+                stalled: false,
+            });
+
+            let mut received = Vec::new();
+            loop {
+                match reader.read_frame() {
+                    Ok(Some(frame)) => received.push(frame),
+                    Ok(None) => break,
+                    Err(e) => prop_assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+                }
+            }
+            prop_assert_eq!(received, payloads);
+        }
+    }
+
+    #[test]
+    fn empty_stream_is_clean_eof() {
+        let mut reader = FrameReader::new(Cursor::new(Vec::new()));
+        assert_eq!(reader.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_frame_is_error() {
+        let mut data = encode(&[b"hello".to_vec()]);
+        data.pop();
+
+        let mut reader = FrameReader::new(Cursor::new(data));
+        let err = reader.read_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn rejects_oversized_length() {
+        let len = u32::try_from(MAX_FRAME_LEN + 1).unwrap();
+        let mut reader = FrameReader::new(Cursor::new(len.to_be_bytes()));
+        let err = reader.read_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn write_rejects_oversized_payload() {
+        let payload = vec![0_u8; MAX_FRAME_LEN + 1];
+        assert!(write_frame(&mut Vec::new(), &payload).is_err());
+    }
+}
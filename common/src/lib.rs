@@ -1,5 +1,15 @@
+mod capture;
+mod datagram;
+mod frame;
 mod protocol;
 mod quote;
+pub mod tls;
 
-pub use protocol::{Command, Response, Tickers, UdpAddr};
+pub use capture::{CaptureReader, CaptureRecord, CaptureWriter, CAPTURE_MAGIC};
+pub use datagram::{DatagramKey, OpenError, Opener, Protection, Sealer};
+pub use frame::{write_frame, FrameReader, MAX_FRAME_LEN};
+pub use protocol::{
+    Command, Notification, Pong, Probe, Response, SessionId, StreamTarget,
+    Tickers, UdpAddr,
+};
 pub use quote::StockQuote;
@@ -5,6 +5,8 @@ use std::net::{SocketAddr, ToSocketAddrs};
 use std::str::FromStr;
 use url::Url;
 
+use crate::datagram::{DatagramKey, Protection};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Tickers(NonEmpty<String>);
 
@@ -72,6 +74,10 @@ impl UdpAddr {
         self.0
     }
 
+    pub const fn is_multicast(&self) -> bool {
+        self.0.ip().is_multicast()
+    }
+
     fn from_url(url: &Url) -> Result<Self> {
         if url.scheme() != Self::UDP_SCHEME {
             return Err(anyhow!(
@@ -129,15 +135,186 @@ impl From<SocketAddr> for UdpAddr {
     }
 }
 
+/// Opaque handle the server hands out for a stream registered in two steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionId(u64);
+
+impl FromStr for SessionId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        u64::from_str_radix(s, 16)
+            .map(Self)
+            .map_err(|e| anyhow!("Invalid session id '{s}': {e}"))
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl From<u64> for SessionId {
+    fn from(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+/// Carried by a keep-alive and echoed in its `PONG`, so the client can
+/// match replies to pings and time the round trip. The server does not
+/// interpret it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Probe {
+    pub seq: u64,
+    /// When the ping was sent, in microseconds on the client's clock.
+    pub sent_micros: u64,
+}
+
+impl Probe {
+    /// Parses the optional `<seq> <sent_micros>` ending a `PING`,
+    /// `REGISTER` or `PONG`.
+    fn parse_rest<'a>(
+        mut parts: impl Iterator<Item = &'a str>,
+        kind: &str,
+    ) -> Result<Option<Self>> {
+        let Some(seq) = parts.next() else {
+            return Ok(None);
+        };
+        let sent_micros = parts
+            .next()
+            .ok_or_else(|| anyhow!("{kind}: missing probe timestamp"))?;
+        if parts.next().is_some() {
+            return Err(anyhow!("{kind}: too many arguments"));
+        }
+
+        Ok(Some(Self {
+            seq: seq
+                .parse()
+                .map_err(|e| anyhow!("{kind}: invalid probe seq: {e}"))?,
+            sent_micros: sent_micros
+                .parse()
+                .map_err(|e| anyhow!("{kind}: invalid probe timestamp: {e}"))?,
+        }))
+    }
+
+    /// Writes ` <seq> <sent_micros>` after a keyword, if there is a probe.
+    fn write_suffix(
+        probe: Option<Self>,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        probe.map_or(Ok(()), |probe| {
+            write!(f, " {} {}", probe.seq, probe.sent_micros)
+        })
+    }
+}
+
+/// The server's answer to a `PING` or `REGISTER` datagram, echoing its
+/// probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pong(pub Option<Probe>);
+
+impl Pong {
+    pub const KEYWORD: &str = "PONG";
+
+    /// Recognizes a `PONG` among received datagrams.
+    pub fn from_datagram(datagram: &[u8]) -> Option<Self> {
+        if !datagram.starts_with(Self::KEYWORD.as_bytes()) {
+            return None;
+        }
+        std::str::from_utf8(datagram).ok()?.parse().ok()
+    }
+}
+
+impl FromStr for Pong {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        if parts.next() != Some(Self::KEYWORD) {
+            return Err(anyhow!("Not a PONG: {s}"));
+        }
+        Probe::parse_rest(parts, Self::KEYWORD).map(Self)
+    }
+}
+
+impl fmt::Display for Pong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(Self::KEYWORD)?;
+        Probe::write_suffix(self.0, f)
+    }
+}
+
+/// Where the server should deliver a stream of quotes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StreamTarget {
+    /// Datagrams sent to a client-chosen UDP address.
+    Udp(UdpAddr),
+    /// Length-prefixed frames on the TCP connection that issued the command.
+    Tcp,
+    /// Datagrams sent back to wherever the client's `REGISTER` datagram
+    /// came from, for clients behind NAT.
+    Nat,
+}
+
+impl StreamTarget {
+    const TCP_KEYWORD: &str = "tcp";
+    const NAT_KEYWORD: &str = "nat";
+}
+
+impl FromStr for StreamTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case(Self::TCP_KEYWORD) {
+            return Ok(Self::Tcp);
+        }
+
+        if s.eq_ignore_ascii_case(Self::NAT_KEYWORD) {
+            return Ok(Self::Nat);
+        }
+
+        s.parse().map(Self::Udp)
+    }
+}
+
+impl fmt::Display for StreamTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Udp(udp_addr) => write!(f, "{udp_addr}"),
+            Self::Tcp => write!(f, "{}", Self::TCP_KEYWORD),
+            Self::Nat => write!(f, "{}", Self::NAT_KEYWORD),
+        }
+    }
+}
+
+impl From<UdpAddr> for StreamTarget {
+    fn from(udp_addr: UdpAddr) -> Self {
+        Self::Udp(udp_addr)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Command {
-    Stream { udp_addr: UdpAddr, tickers: Tickers },
-    Ping,
+    Stream {
+        target: StreamTarget,
+        tickers: Tickers,
+    },
+    Ping(Option<Probe>),
+    /// Sent over UDP to bind a `STREAM nat` session to the sender's address.
+    Register(SessionId, Option<Probe>),
+    /// Proves the connection may issue `STREAM`, when the server requires it.
+    Auth(String),
+    /// Asks for a key protecting the datagrams of the next UDP stream.
+    Secure(Protection),
 }
 
 impl Command {
-    pub const fn stream(udp_addr: UdpAddr, tickers: Tickers) -> Self {
-        Self::Stream { udp_addr, tickers }
+    pub fn stream(target: impl Into<StreamTarget>, tickers: Tickers) -> Self {
+        Self::Stream {
+            target: target.into(),
+            tickers,
+        }
     }
 }
 
@@ -149,9 +326,9 @@ impl FromStr for Command {
         let cmd = parts.next().ok_or_else(|| anyhow!("Empty command"))?;
         match cmd.to_uppercase().as_str() {
             "STREAM" => {
-                let udp_addr: UdpAddr = parts
+                let target: StreamTarget = parts
                     .next()
-                    .ok_or_else(|| anyhow!("STREAM: missing UDP address"))?
+                    .ok_or_else(|| anyhow!("STREAM: missing target"))?
                     .parse()?;
 
                 let tickers: Tickers = parts
@@ -163,9 +340,41 @@ impl FromStr for Command {
                     return Err(anyhow!("STREAM: too many arguments"));
                 }
 
-                Ok(Self::stream(udp_addr, tickers))
+                Ok(Self::stream(target, tickers))
+            }
+            "PING" => Probe::parse_rest(parts, "PING").map(Self::Ping),
+            "REGISTER" => {
+                let session_id: SessionId = parts
+                    .next()
+                    .ok_or_else(|| anyhow!("REGISTER: missing session id"))?
+                    .parse()?;
+                let probe = Probe::parse_rest(parts, "REGISTER")?;
+
+                Ok(Self::Register(session_id, probe))
+            }
+            "AUTH" => {
+                let token = parts
+                    .next()
+                    .ok_or_else(|| anyhow!("AUTH: missing token"))?;
+
+                if parts.next().is_some() {
+                    return Err(anyhow!("AUTH: too many arguments"));
+                }
+
+                Ok(Self::Auth(token.to_string()))
+            }
+            "SECURE" => {
+                let protection: Protection = parts
+                    .next()
+                    .ok_or_else(|| anyhow!("SECURE: missing protection"))?
+                    .parse()?;
+
+                if parts.next().is_some() {
+                    return Err(anyhow!("SECURE: too many arguments"));
+                }
+
+                Ok(Self::Secure(protection))
             }
-            "PING" => Ok(Self::Ping),
             other => Err(anyhow!("Unknown command: {other}")),
         }
     }
@@ -174,10 +383,19 @@ impl FromStr for Command {
 impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Stream { udp_addr, tickers } => {
-                write!(f, "STREAM {udp_addr} {tickers}")
+            Self::Stream { target, tickers } => {
+                write!(f, "STREAM {target} {tickers}")
+            }
+            Self::Ping(probe) => {
+                f.write_str("PING")?;
+                Probe::write_suffix(*probe, f)
+            }
+            Self::Register(session_id, probe) => {
+                write!(f, "REGISTER {session_id}")?;
+                Probe::write_suffix(*probe, f)
             }
-            Self::Ping => write!(f, "PING"),
+            Self::Auth(token) => write!(f, "AUTH {token}"),
+            Self::Secure(protection) => write!(f, "SECURE {protection}"),
         }
     }
 }
@@ -185,7 +403,20 @@ impl fmt::Display for Command {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Response {
     Ok,
+    /// Accepted, and the stream is waiting for a `REGISTER` with this id.
+    Session(SessionId),
+    /// Accepted `SECURE`, the next UDP stream is protected with this key.
+    Key(DatagramKey),
     Error(String),
+    /// The server is ending the stream or connection, e.g. on shutdown.
+    /// Also sent in place of a quote on UDP and TCP streams.
+    Bye,
+}
+
+impl Response {
+    pub const BYE: &str = "BYE";
+    const SESSION_PREFIX: &str = "OK SESSION ";
+    const KEY_PREFIX: &str = "OK KEY ";
 }
 
 impl FromStr for Response {
@@ -198,6 +429,18 @@ impl FromStr for Response {
             return Ok(Self::Ok);
         }
 
+        if s.trim_end() == Self::BYE {
+            return Ok(Self::Bye);
+        }
+
+        if let Some(id) = s.trim_end().strip_prefix(Self::SESSION_PREFIX) {
+            return id.parse().map(Self::Session);
+        }
+
+        if let Some(key) = s.trim_end().strip_prefix(Self::KEY_PREFIX) {
+            return key.parse().map(Self::Key);
+        }
+
         if let Some(msg) = s.strip_prefix("ERR ") {
             return Ok(Self::Error(msg.to_string()));
         }
@@ -214,8 +457,83 @@ impl fmt::Display for Response {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Ok => write!(f, "OK"),
+            Self::Session(id) => write!(f, "{}{id}", Self::SESSION_PREFIX),
+            Self::Key(key) => write!(f, "{}{key}", Self::KEY_PREFIX),
             Self::Error(msg) if msg.is_empty() => write!(f, "ERR"),
             Self::Error(msg) => write!(f, "ERR {msg}"),
+            Self::Bye => f.write_str(Self::BYE),
+        }
+    }
+}
+
+/// Pushed by the server on a control connection at any time, in between
+/// replies. Every notification line starts with `NOTICE`, so it cannot be
+/// mistaken for a [`Response`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// The stream to `target` ends in `secs` seconds unless a ping arrives.
+    Expiring { target: UdpAddr, secs: u64 },
+    /// The server stopped generating quotes for a ticker.
+    Halted(String),
+    /// Quotes for a halted ticker are generated again.
+    Resumed(String),
+    /// The server is shutting down; `BYE` follows.
+    Shutdown,
+}
+
+impl Notification {
+    const PREFIX: &str = "NOTICE ";
+
+    /// Whether a control line is a notification rather than a response.
+    pub fn is_notification(line: &str) -> bool {
+        line.trim_start().starts_with(Self::PREFIX)
+    }
+}
+
+impl FromStr for Notification {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let body = s
+            .trim()
+            .strip_prefix(Self::PREFIX)
+            .ok_or_else(|| anyhow!("Not a notification: {s}"))?;
+        let mut parts = body.split_whitespace();
+        let kind = parts.next().ok_or_else(|| anyhow!("Empty notification"))?;
+        let mut argument = |name: &str| {
+            parts
+                .next()
+                .ok_or_else(|| anyhow!("{kind}: missing {name}"))
+        };
+
+        let notification = match kind {
+            "EXPIRING" => Self::Expiring {
+                target: argument("target")?.parse()?,
+                secs: argument("seconds")?.parse()?,
+            },
+            "HALTED" => Self::Halted(argument("ticker")?.to_string()),
+            "RESUMED" => Self::Resumed(argument("ticker")?.to_string()),
+            "SHUTDOWN" => Self::Shutdown,
+            other => return Err(anyhow!("Unknown notification: {other}")),
+        };
+
+        if parts.next().is_some() {
+            return Err(anyhow!("{kind}: too many arguments"));
+        }
+        Ok(notification)
+    }
+}
+
+impl fmt::Display for Notification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(Self::PREFIX)?;
+        match self {
+            Self::Expiring { target, secs } => {
+                write!(f, "EXPIRING {target} {secs}")
+            }
+            Self::Halted(ticker) => write!(f, "HALTED {ticker}"),
+            Self::Resumed(ticker) => write!(f, "RESUMED {ticker}"),
+            Self::Shutdown => write!(f, "SHUTDOWN"),
         }
     }
 }
@@ -262,27 +580,81 @@ mod tests {
             .prop_map(|v| Tickers::from_str(&v.join(",")).unwrap())
     }
 
+    fn valid_session_id() -> impl Strategy<Value = SessionId> {
+        any::<u64>().prop_map(SessionId::from)
+    }
+
+    fn valid_stream_target() -> impl Strategy<Value = StreamTarget> {
+        prop_oneof![
+            valid_udp_target().prop_map(StreamTarget::Udp),
+            Just(StreamTarget::Tcp),
+            Just(StreamTarget::Nat),
+        ]
+    }
+
     fn valid_stream_command() -> impl Strategy<Value = Command> {
-        (valid_udp_target(), valid_tickers())
+        (valid_stream_target(), valid_tickers())
             .prop_map(|(target, tickers)| Command::stream(target, tickers))
     }
 
+    fn valid_probe() -> impl Strategy<Value = Option<Probe>> {
+        prop::option::of(
+            (any::<u64>(), any::<u64>())
+                .prop_map(|(seq, sent_micros)| Probe { seq, sent_micros }),
+        )
+    }
+
     fn valid_ping_command() -> impl Strategy<Value = Command> {
-        Just(Command::Ping)
+        valid_probe().prop_map(Command::Ping)
+    }
+
+    fn valid_register_command() -> impl Strategy<Value = Command> {
+        (valid_session_id(), valid_probe())
+            .prop_map(|(id, probe)| Command::Register(id, probe))
+    }
+
+    // Lowercase only, so commands survive the case-insensitivity check.
+    fn valid_auth_command() -> impl Strategy<Value = Command> {
+        "[a-z0-9_-]{1,64}".prop_map(Command::Auth)
+    }
+
+    fn valid_secure_command() -> impl Strategy<Value = Command> {
+        prop_oneof![Just(Protection::Mac), Just(Protection::Aead)]
+            .prop_map(Command::Secure)
     }
 
     fn valid_command() -> impl Strategy<Value = Command> {
-        prop_oneof![valid_stream_command(), valid_ping_command(),]
+        prop_oneof![
+            valid_stream_command(),
+            valid_ping_command(),
+            valid_register_command(),
+            valid_auth_command(),
+            valid_secure_command(),
+        ]
     }
 
     fn valid_response() -> impl Strategy<Value = Response> {
         prop_oneof![
             Just(Response::Ok),
+            Just(Response::Bye),
+            valid_session_id().prop_map(Response::Session),
+            "[0-9a-f]{64}".prop_map(|key| Response::Key(key.parse().unwrap())),
             Just(Response::Error(String::new())),
             "[a-zA-Z0-9]{1,50}".prop_map(Response::Error),
         ]
     }
 
+    fn valid_notification() -> impl Strategy<Value = Notification> {
+        prop_oneof![
+            (valid_udp_target(), any::<u64>()).prop_map(|(target, secs)| {
+                Notification::Expiring { target, secs }
+            }),
+            valid_ticker().prop_map(Notification::Halted),
+            valid_ticker().prop_map(Notification::Resumed),
+            Just(Notification::Shutdown),
+        ]
+    }
+
     mod udp_addr {
         use super::*;
 
@@ -340,6 +712,16 @@ mod tests {
             assert!(input.parse::<UdpAddr>().is_err());
         }
 
+        #[rstest]
+        #[case("udp://239.1.1.1:6000", true)]
+        #[case("udp://[ff15::1]:6000", true)]
+        #[case("udp://127.0.0.1:6000", false)]
+        #[case("udp://[::1]:6000", false)]
+        fn detects_multicast(#[case] input: &str, #[case] expected: bool) {
+            let target: UdpAddr = input.parse().unwrap();
+            assert_eq!(target.is_multicast(), expected);
+        }
+
         #[test]
         fn parses_ipv6_with_brackets() {
             let target: UdpAddr = "udp://[::1]:8080".parse().unwrap();
@@ -348,6 +730,36 @@ mod tests {
         }
     }
 
+    mod stream_target {
+        use super::*;
+
+        proptest! {
+            #[test]
+            fn roundtrip(target in valid_stream_target()) {
+                let serialized = target.to_string();
+                let parsed: StreamTarget = serialized.parse().unwrap();
+                prop_assert_eq!(target, parsed);
+            }
+        }
+
+        #[rstest]
+        #[case("tcp")]
+        #[case("TCP")]
+        fn parses_tcp_keyword(#[case] input: &str) {
+            assert_eq!(
+                input.parse::<StreamTarget>().unwrap(),
+                StreamTarget::Tcp
+            );
+        }
+
+        #[rstest]
+        #[case("tcp://127.0.0.1:8080")]
+        #[case("tcpx")]
+        fn rejects_invalid(#[case] input: &str) {
+            assert!(input.parse::<StreamTarget>().is_err());
+        }
+    }
+
     mod tickers {
         use super::*;
 
@@ -456,7 +868,21 @@ mod tests {
 
         #[test]
         fn ping_display() {
-            assert_eq!(Command::Ping.to_string(), "PING");
+            assert_eq!(Command::Ping(None).to_string(), "PING");
+            let probe = Probe {
+                seq: 7,
+                sent_micros: 1500,
+            };
+            assert_eq!(Command::Ping(Some(probe)).to_string(), "PING 7 1500");
+        }
+
+        #[rstest]
+        #[case("PING 7")]
+        #[case("PING 7 x")]
+        #[case("PING 7 1500 extra")]
+        #[case("REGISTER 1f 7")]
+        fn rejects_invalid_probe(#[case] input: &str) {
+            assert!(input.parse::<Command>().is_err());
         }
 
         #[rstest]
@@ -480,8 +906,45 @@ mod tests {
             assert!(input.parse::<Command>().is_err());
         }
 
+        #[test]
+        fn parses_tcp_stream() {
+            let cmd: Command = "STREAM tcp AAPL,TSLA".parse().unwrap();
+            let tickers: Tickers = "AAPL,TSLA".parse().unwrap();
+            assert_eq!(cmd, Command::stream(StreamTarget::Tcp, tickers));
+        }
+
+        #[rstest]
+        #[case("REGISTER")]
+        #[case("REGISTER not-hex")]
+        #[case("REGISTER 1f extra 1 2")]
+        fn rejects_invalid_register(#[case] input: &str) {
+            assert!(input.parse::<Command>().is_err());
+        }
+
+        #[test]
+        fn auth_preserves_token_case() {
+            let cmd: Command = "auth S3cret-Token".parse().unwrap();
+            assert_eq!(cmd, Command::Auth("S3cret-Token".to_string()));
+        }
+
+        #[rstest]
+        #[case("SECURE")]
+        #[case("SECURE rot13")]
+        #[case("SECURE mac extra")]
+        fn rejects_invalid_secure(#[case] input: &str) {
+            assert!(input.parse::<Command>().is_err());
+        }
+
+        #[rstest]
+        #[case("AUTH")]
+        #[case("AUTH token extra")]
+        fn rejects_invalid_auth(#[case] input: &str) {
+            assert!(input.parse::<Command>().is_err());
+        }
+
         #[rstest]
         #[case("STREAM udp://127.0.0.1:8080 AAPL extra")]
+        #[case("STREAM tcp AAPL extra")]
         fn rejects_stream_extra_args(#[case] input: &str) {
             assert!(input.parse::<Command>().is_err());
         }
@@ -506,7 +969,7 @@ mod tests {
 
                 match parsed {
                     Response::Error(parsed_msg) => prop_assert_eq!(parsed_msg, msg),
-                    Response::Ok => prop_assert!(false, "Expected Error variant"),
+                    _ => prop_assert!(false, "Expected Error variant"),
                 }
             }
         }
@@ -516,6 +979,12 @@ mod tests {
             assert_eq!(Response::Ok.to_string(), "OK");
         }
 
+        #[test]
+        fn bye_roundtrip() {
+            assert_eq!(Response::Bye.to_string(), "BYE");
+            assert_eq!("BYE\n".parse::<Response>().unwrap(), Response::Bye);
+        }
+
         #[test]
         fn error_display() {
             let resp = Response::Error("Something failed".to_string());
@@ -530,6 +999,24 @@ mod tests {
             assert!(input.parse::<Response>().is_err());
         }
 
+        #[test]
+        fn session_display() {
+            let resp = Response::Session(SessionId::from(0xab));
+            assert_eq!(resp.to_string(), "OK SESSION 00000000000000ab");
+        }
+
+        #[rstest]
+        #[case("OK KEY")]
+        #[case("OK KEY 00ff")]
+        fn rejects_invalid_key(#[case] input: &str) {
+            assert!(input.parse::<Response>().is_err());
+        }
+
+        #[test]
+        fn rejects_invalid_session_id() {
+            assert!("OK SESSION xyz".parse::<Response>().is_err());
+        }
+
         #[test]
         fn parses_empty_error_message() {
             let resp: Response = "ERR".parse().unwrap();
@@ -542,4 +1029,64 @@ mod tests {
             assert_eq!(resp, Response::Error(String::new()));
         }
     }
+
+    mod notification {
+        use super::*;
+
+        proptest! {
+            #[test]
+            fn roundtrip(notification in valid_notification()) {
+                let line = notification.to_string();
+                prop_assert!(Notification::is_notification(&line));
+                prop_assert!(line.parse::<Response>().is_err());
+                prop_assert_eq!(line.parse::<Notification>().unwrap(), notification);
+            }
+        }
+
+        #[test]
+        fn expiring_display() {
+            let notification = Notification::Expiring {
+                target: "127.0.0.1:9000".parse().unwrap(),
+                secs: 2,
+            };
+            assert_eq!(
+                notification.to_string(),
+                "NOTICE EXPIRING udp://127.0.0.1:9000 2"
+            );
+        }
+
+        #[rstest]
+        #[case("HALTED AAPL")]
+        #[case("NOTICE")]
+        #[case("NOTICE HALTED")]
+        #[case("NOTICE SHUTDOWN now")]
+        #[case("NOTICE EXPIRING udp://127.0.0.1:9000")]
+        #[case("NOTICE REBOOT")]
+        fn rejects_invalid(#[case] input: &str) {
+            assert!(input.parse::<Notification>().is_err());
+        }
+    }
+
+    mod pong {
+        use super::*;
+
+        proptest! {
+            #[test]
+            fn roundtrip(probe in valid_probe()) {
+                let pong = Pong(probe);
+                let datagram = pong.to_string();
+                prop_assert_eq!(Pong::from_datagram(datagram.as_bytes()), Some(pong));
+            }
+        }
+
+        #[rstest]
+        #[case(&b"PONGS"[..])]
+        #[case(&b"PONG 1"[..])]
+        #[case(&b"PING 1 2"[..])]
+        #[case(&b"BYE"[..])]
+        #[case(&[b'P', b'O', b'N', b'G', b' ', 0xff][..])]
+        fn rejects_other_datagrams(#[case] datagram: &[u8]) {
+            assert_eq!(Pong::from_datagram(datagram), None);
+        }
+    }
 }
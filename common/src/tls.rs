@@ -0,0 +1,34 @@
+use anyhow::{anyhow, Context, Result};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use std::path::Path;
+
+/// Reads every certificate from a PEM file, leaf first for a chain.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or holds no certificates.
+pub fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .with_context(|| {
+            format!("Failed to read certificates from {}", path.display())
+        })?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", path.display()));
+    }
+
+    Ok(certs)
+}
+
+/// Reads the first PKCS#8, PKCS#1 or SEC1 private key from a PEM file.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or holds no private key.
+pub fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).with_context(|| {
+        format!("Failed to read private key from {}", path.display())
+    })
+}
@@ -0,0 +1,474 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Subcommand;
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client_handler::{ClientManager, SubscriberId};
+use crate::fanout::FanOut;
+use crate::generator::GeneratorControl;
+use crate::health::Heartbeat;
+use crate::notify::Notifier;
+use crate::session::SessionRegistry;
+use crate::shutdown::Workers;
+use common::{Notification, UdpAddr};
+
+/// Operator commands, sent one per line to the admin port. Doubles as the
+/// `admin` subcommand of the server binary.
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+    /// List UDP clients with their tickers and last ping age
+    Clients,
+    /// Stop streaming to a UDP client
+    Kick { target: UdpAddr },
+    /// Stop generating quotes for a ticker
+    Halt { ticker: String },
+    /// Generate quotes for a halted ticker again
+    Resume { ticker: String },
+    /// Change the quote interval
+    Interval { millis: u64 },
+    /// Show server statistics
+    Stats,
+}
+
+impl FromStr for AdminCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        let keyword = parts
+            .next()
+            .ok_or_else(|| anyhow!("Empty command"))?
+            .to_uppercase();
+        let mut argument = |name: &str| {
+            parts
+                .next()
+                .ok_or_else(|| anyhow!("{keyword}: missing {name}"))
+        };
+
+        let command = match keyword.as_str() {
+            "CLIENTS" => Self::Clients,
+            "KICK" => Self::Kick {
+                target: argument("target")?.parse()?,
+            },
+            "HALT" => Self::Halt {
+                ticker: argument("ticker")?.to_uppercase(),
+            },
+            "RESUME" => Self::Resume {
+                ticker: argument("ticker")?.to_uppercase(),
+            },
+            "INTERVAL" => Self::Interval {
+                millis: argument("milliseconds")?
+                    .parse()
+                    .context("INTERVAL: invalid milliseconds")?,
+            },
+            "STATS" => Self::Stats,
+            _ => return Err(anyhow!("Unknown command: {keyword}")),
+        };
+
+        if parts.next().is_some() {
+            return Err(anyhow!("{keyword}: too many arguments"));
+        }
+        Ok(command)
+    }
+}
+
+impl fmt::Display for AdminCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Clients => write!(f, "CLIENTS"),
+            Self::Kick { target } => write!(f, "KICK {target}"),
+            Self::Halt { ticker } => write!(f, "HALT {ticker}"),
+            Self::Resume { ticker } => write!(f, "RESUME {ticker}"),
+            Self::Interval { millis } => write!(f, "INTERVAL {millis}"),
+            Self::Stats => write!(f, "STATS"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ClientView {
+    target: String,
+    tickers: String,
+    source_ip: IpAddr,
+    last_ping_secs: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct Stats {
+    uptime_secs: u64,
+    clients: usize,
+    nat_sessions: usize,
+    subscribers: usize,
+    quote_interval_ms: u128,
+    halted: Vec<String>,
+}
+
+/// Executes [`AdminCommand`]s against the live server state.
+#[derive(Clone)]
+pub struct Admin {
+    client_manager: Arc<ClientManager>,
+    fan_out: FanOut,
+    sessions: SessionRegistry,
+    control: GeneratorControl,
+    notifier: Notifier,
+    universe: Vec<String>,
+    started: Instant,
+}
+
+impl Admin {
+    pub fn new(
+        client_manager: Arc<ClientManager>,
+        fan_out: FanOut,
+        sessions: SessionRegistry,
+        control: GeneratorControl,
+        notifier: Notifier,
+        universe: Vec<String>,
+    ) -> Self {
+        Self {
+            client_manager,
+            fan_out,
+            sessions,
+            control,
+            notifier,
+            universe,
+            started: Instant::now(),
+        }
+    }
+
+    /// Returns the JSON payload of the reply, if the command has one.
+    pub fn execute(&self, command: &AdminCommand) -> Result<Option<Value>> {
+        match command {
+            AdminCommand::Clients => {
+                Ok(Some(serde_json::to_value(self.clients())?))
+            }
+            AdminCommand::Kick { target } => self.kick(*target).map(|()| None),
+            AdminCommand::Halt { ticker } => {
+                self.check_ticker(ticker)?;
+                if !self.control.halt(ticker) {
+                    return Err(anyhow!("{ticker} is already halted"));
+                }
+                self.notifier
+                    .broadcast(&Notification::Halted(ticker.clone()));
+                Ok(None)
+            }
+            AdminCommand::Resume { ticker } => {
+                if !self.control.resume(ticker) {
+                    return Err(anyhow!("{ticker} is not halted"));
+                }
+                self.notifier
+                    .broadcast(&Notification::Resumed(ticker.clone()));
+                Ok(None)
+            }
+            AdminCommand::Interval { millis } => {
+                self.control.set_interval(Duration::from_millis(*millis))?;
+                Ok(None)
+            }
+            AdminCommand::Stats => {
+                Ok(Some(serde_json::to_value(self.stats())?))
+            }
+        }
+    }
+
+    fn clients(&self) -> Vec<ClientView> {
+        let mut clients: Vec<_> = self
+            .client_manager
+            .snapshot()
+            .into_iter()
+            .map(|client| ClientView {
+                target: client.target.to_string(),
+                tickers: client.tickers.to_string(),
+                source_ip: client.source_ip,
+                last_ping_secs: client.last_ping.elapsed().as_secs_f64(),
+            })
+            .collect();
+        clients.sort_by(|a, b| a.target.cmp(&b.target));
+        clients
+    }
+
+    fn kick(&self, target: UdpAddr) -> Result<()> {
+        if !self.client_manager.contains(&target) {
+            return Err(anyhow!("No client {target}"));
+        }
+
+        self.client_manager.remove(&target);
+        self.fan_out.unsubscribe(&SubscriberId::Udp(target));
+        self.sessions.remove_target(&target);
+        Ok(())
+    }
+
+    fn check_ticker(&self, ticker: &str) -> Result<()> {
+        if self.universe.iter().any(|known| known == ticker) {
+            Ok(())
+        } else {
+            Err(anyhow!("Unknown ticker {ticker}"))
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            uptime_secs: self.started.elapsed().as_secs(),
+            clients: self.client_manager.count(),
+            nat_sessions: self.sessions.count(),
+            subscribers: self.fan_out.count(),
+            quote_interval_ms: self.control.interval().as_millis(),
+            halted: self.control.halted(),
+        }
+    }
+}
+
+/// Line-based admin port. Replies are `OK`, `OK <json>` or `ERR <message>`.
+/// It has no authentication, so bind it to loopback or a private network.
+pub struct AdminServer {
+    listener: TcpListener,
+    admin: Admin,
+    running: Arc<AtomicBool>,
+    heartbeat: Option<Heartbeat>,
+    workers: Workers,
+}
+
+impl AdminServer {
+    const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+    const READ_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    pub fn bind(
+        addr: SocketAddr,
+        admin: Admin,
+        running: Arc<AtomicBool>,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            admin,
+            running,
+            heartbeat: None,
+            workers: Workers::new(),
+        })
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    pub fn with_workers(mut self, workers: Workers) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn run(&self) {
+        if let Ok(addr) = self.listener.local_addr() {
+            info!("Admin interface listening on {addr}");
+        }
+
+        while self.running.load(Ordering::SeqCst) {
+            if let Some(heartbeat) = &self.heartbeat {
+                heartbeat.beat();
+            }
+            match self.listener.accept() {
+                Ok((stream, peer_addr)) => {
+                    let admin = self.admin.clone();
+                    let running = self.running.clone();
+                    let name = format!("admin {peer_addr}");
+                    self.workers.spawn(name, move || {
+                        if let Err(e) = Self::serve(stream, &admin, &running) {
+                            warn!("Admin {peer_addr} error: {e}");
+                        }
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Self::ACCEPT_POLL_INTERVAL);
+                }
+                Err(e) => error!("Failed to accept admin connection: {e}"),
+            }
+        }
+
+        info!("Admin interface stopped");
+    }
+
+    fn serve(
+        stream: TcpStream,
+        admin: &Admin,
+        running: &AtomicBool,
+    ) -> Result<()> {
+        let peer_addr = stream.peer_addr()?;
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Self::READ_POLL_INTERVAL))?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+
+        while running.load(Ordering::SeqCst) {
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            let result = String::from_utf8_lossy(&line)
+                .trim()
+                .parse::<AdminCommand>()
+                .and_then(|command| {
+                    info!("Admin {peer_addr}: {command}");
+                    admin.execute(&command)
+                });
+
+            match result {
+                Ok(None) => writeln!(writer, "OK")?,
+                Ok(Some(payload)) => writeln!(writer, "OK {payload}")?,
+                Err(e) => {
+                    warn!("Admin {peer_addr} command failed: {e}");
+                    writeln!(writer, "ERR {e}")?;
+                }
+            }
+            writer.flush()?;
+            line.clear();
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends one command to a running server's admin port and returns the
+/// reply payload, pretty-printed if it is JSON.
+pub fn send(addr: SocketAddr, command: &AdminCommand) -> Result<String> {
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)
+        .with_context(|| format!("Cannot reach {addr}"))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    writeln!(&stream, "{command}")?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    let reply = reply.trim_end();
+
+    if let Some(message) = reply.strip_prefix("ERR ") {
+        return Err(anyhow!("{message}"));
+    }
+    let payload = reply
+        .strip_prefix("OK")
+        .ok_or_else(|| anyhow!("Unexpected reply: {reply}"))?
+        .trim_start();
+
+    Ok(serde_json::from_str::<Value>(payload)
+        .and_then(|json| serde_json::to_string_pretty(&json))
+        .unwrap_or_else(|_| payload.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serve, Serving};
+    use rstest::{fixture, rstest};
+
+    type TestAdmin = Serving<Admin>;
+
+    #[fixture]
+    fn server() -> TestAdmin {
+        let admin = Admin::new(
+            Arc::new(ClientManager::default()),
+            FanOut::new(),
+            SessionRegistry::new(Duration::from_secs(5)),
+            GeneratorControl::new(Duration::from_millis(100)),
+            Notifier::new(),
+            vec!["AAPL".to_string(), "TSLA".to_string()],
+        );
+        serve(admin.clone(), |addr, running| {
+            AdminServer::bind(addr, admin, running)
+        })
+    }
+
+    #[rstest]
+    #[case(AdminCommand::Clients)]
+    #[case(AdminCommand::Kick { target: "127.0.0.1:9000".parse().unwrap() })]
+    #[case(AdminCommand::Halt { ticker: "AAPL".to_string() })]
+    #[case(AdminCommand::Resume { ticker: "AAPL".to_string() })]
+    #[case(AdminCommand::Interval { millis: 250 })]
+    #[case(AdminCommand::Stats)]
+    fn command_roundtrip(#[case] command: AdminCommand) {
+        assert_eq!(
+            command.to_string().parse::<AdminCommand>().unwrap(),
+            command
+        );
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("KICK")]
+    #[case("HALT AAPL TSLA")]
+    #[case("INTERVAL soon")]
+    #[case("REBOOT")]
+    fn rejects_invalid_commands(#[case] input: &str) {
+        assert!(input.parse::<AdminCommand>().is_err());
+    }
+
+    #[rstest]
+    fn lists_and_kicks_clients(server: TestAdmin) {
+        let target: UdpAddr = "127.0.0.1:9000".parse().unwrap();
+        server.shared.client_manager.register(
+            target,
+            &"AAPL,TSLA".parse().unwrap(),
+            "127.0.0.1".parse().unwrap(),
+        );
+
+        let listed = send(server.addr, &AdminCommand::Clients).unwrap();
+        assert!(listed.contains("\"target\": \"udp://127.0.0.1:9000\""));
+        assert!(listed.contains("\"tickers\": \"AAPL,TSLA\""));
+
+        send(server.addr, &AdminCommand::Kick { target }).unwrap();
+        assert!(!server.shared.client_manager.contains(&target));
+
+        let again = send(server.addr, &AdminCommand::Kick { target });
+        assert!(again.is_err());
+    }
+
+    #[rstest]
+    fn halts_resumes_and_retimes(server: TestAdmin) {
+        let halt = AdminCommand::Halt {
+            ticker: "AAPL".to_string(),
+        };
+        send(server.addr, &halt).unwrap();
+        assert!(server.shared.control.is_halted("AAPL"));
+        assert!(send(server.addr, &halt).is_err());
+
+        let unknown = AdminCommand::Halt {
+            ticker: "NOPE".to_string(),
+        };
+        assert!(send(server.addr, &unknown).is_err());
+
+        send(server.addr, &AdminCommand::Interval { millis: 250 }).unwrap();
+        let stats = send(server.addr, &AdminCommand::Stats).unwrap();
+        assert!(stats.contains("\"quote_interval_ms\": 250"));
+        assert!(stats.contains("\"AAPL\""));
+
+        let resume = AdminCommand::Resume {
+            ticker: "AAPL".to_string(),
+        };
+        send(server.addr, &resume).unwrap();
+        assert!(!server.shared.control.is_halted("AAPL"));
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Context, Result};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Client tokens accepted by `AUTH`, loaded from a credentials file with
+/// one `<name> <token>` pair per line. Blank lines and `#` comments are
+/// skipped.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    entries: Vec<(String, String)>,
+}
+
+impl Credentials {
+    pub fn load(path: &Path) -> Result<Self> {
+        fs::read_to_string(path)
+            .with_context(|| {
+                format!("Failed to read credentials file {}", path.display())
+            })?
+            .parse()
+            .with_context(|| {
+                format!("Invalid credentials file {}", path.display())
+            })
+    }
+
+    /// Returns the name of the client the token belongs to.
+    pub fn authenticate(&self, token: &str) -> Option<&str> {
+        // Check every entry so the time taken does not reveal which one
+        // matched.
+        self.entries.iter().fold(None, |found, (name, expected)| {
+            let matches =
+                constant_time_eq(token.as_bytes(), expected.as_bytes());
+            found.or_else(|| matches.then_some(name.as_str()))
+        })
+    }
+
+    pub const fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl FromStr for Credentials {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let (Some(name), Some(token), None) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(anyhow!(
+                    "Line {}: expected '<name> <token>'",
+                    number + 1
+                ));
+            };
+
+            entries.push((name.to_string(), token.to_string()));
+        }
+
+        if entries.is_empty() {
+            return Err(anyhow!("No credentials found"));
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self.entries.iter().map(|(name, _)| name).collect();
+        f.debug_struct("Credentials")
+            .field("names", &names)
+            .finish()
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn credentials() -> Credentials {
+        "# dashboards\nalice s3cret\n\n  bob  hunter2  \n"
+            .parse()
+            .unwrap()
+    }
+
+    #[rstest]
+    fn parses_entries(credentials: Credentials) {
+        assert_eq!(credentials.len(), 2);
+    }
+
+    #[rstest]
+    #[case("s3cret", Some("alice"))]
+    #[case("hunter2", Some("bob"))]
+    #[case("hunter", None)]
+    #[case("", None)]
+    fn authenticates_tokens(
+        credentials: Credentials,
+        #[case] token: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        assert_eq!(credentials.authenticate(token), expected);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("# only a comment\n")]
+    #[case("alice\n")]
+    #[case("alice s3cret extra\n")]
+    fn rejects_invalid(#[case] input: &str) {
+        assert!(input.parse::<Credentials>().is_err());
+    }
+
+    #[rstest]
+    fn debug_hides_tokens(credentials: Credentials) {
+        let debug = format!("{credentials:?}");
+        assert!(debug.contains("alice"));
+        assert!(!debug.contains("s3cret"));
+    }
+}
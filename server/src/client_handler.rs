@@ -3,19 +3,47 @@ use crossbeam::channel::{Receiver, RecvTimeoutError, TryRecvError};
 use log::{debug, info, warn};
 use parking_lot::Mutex;
 use std::collections::HashMap;
-use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::fmt;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use common::{StockQuote, Tickers, UdpAddr};
+use crate::fanout::Stop;
+use crate::metrics::METRICS;
+use common::{write_frame, Response, Sealer, StockQuote, Tickers, UdpAddr};
+
+/// Identifies one consumer of the quote fan-out, whatever its transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SubscriberId {
+    Udp(UdpAddr),
+    Tcp(SocketAddr),
+    Multicast(UdpAddr),
+    WebSocket(SocketAddr),
+    Sse(SocketAddr),
+}
+
+impl fmt::Display for SubscriberId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Udp(addr) => write!(f, "{addr}"),
+            Self::Tcp(peer) => write!(f, "tcp://{peer}"),
+            Self::Multicast(group) => write!(f, "multicast {group}"),
+            Self::WebSocket(peer) => write!(f, "ws://{peer}"),
+            Self::Sse(peer) => write!(f, "sse://{peer}"),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
-#[allow(dead_code)]
 pub struct ClientInfo {
     pub target: UdpAddr,
     pub tickers: Tickers,
     pub last_ping: Instant,
     pub source_ip: IpAddr,
+    /// Set once the client has been told it is about to expire, cleared
+    /// by its next ping.
+    pub expiry_warned: bool,
 }
 
 impl ClientInfo {
@@ -25,11 +53,13 @@ impl ClientInfo {
             tickers,
             last_ping: Instant::now(),
             source_ip,
+            expiry_warned: false,
         }
     }
 
     pub fn touch(&mut self) {
         self.last_ping = Instant::now();
+        self.expiry_warned = false;
     }
 
     #[must_use]
@@ -72,7 +102,6 @@ impl ClientManager {
         self.clients.lock().insert(target, client);
     }
 
-    #[allow(dead_code)]
     pub fn update_ping(&self, target: &UdpAddr) -> bool {
         let mut clients = self.clients.lock();
 
@@ -106,7 +135,6 @@ impl ClientManager {
         found
     }
 
-    #[allow(dead_code)]
     pub fn remove(&self, target: &UdpAddr) {
         if self.clients.lock().remove(target).is_some() {
             info!("Removed client {target}");
@@ -131,17 +159,42 @@ impl ClientManager {
         removed
     }
 
-    #[allow(dead_code)]
+    /// Clients silent for over half the ping timeout, with the time they
+    /// have left. Each is returned once until it pings again.
+    pub fn expiring(&self) -> Vec<(UdpAddr, Duration)> {
+        let timeout = self.ping_timeout;
+
+        self.clients
+            .lock()
+            .values_mut()
+            .filter(|client| {
+                !client.expiry_warned && client.is_expired(timeout / 2)
+            })
+            .map(|client| {
+                client.expiry_warned = true;
+                let left = timeout.saturating_sub(client.last_ping.elapsed());
+                (client.target, left)
+            })
+            .collect()
+    }
+
+    /// Streams whose `STREAM` came from `source_ip`.
+    pub fn count_by_source(&self, source_ip: IpAddr) -> usize {
+        self.clients
+            .lock()
+            .values()
+            .filter(|client| client.source_ip == source_ip)
+            .count()
+    }
+
     pub fn snapshot(&self) -> Vec<ClientInfo> {
         self.clients.lock().values().cloned().collect()
     }
 
-    #[allow(dead_code)]
     pub fn count(&self) -> usize {
         self.clients.lock().len()
     }
 
-    #[allow(dead_code)]
     pub fn contains(&self, target: &UdpAddr) -> bool {
         self.clients.lock().contains_key(target)
     }
@@ -153,12 +206,58 @@ impl Default for ClientManager {
     }
 }
 
+enum QuoteSink {
+    Udp {
+        socket: Arc<UdpSocket>,
+        addr: UdpAddr,
+        sealer: Option<Sealer>,
+    },
+    Tcp(Box<dyn Write + Send>),
+    Sse(TcpStream),
+}
+
+impl QuoteSink {
+    /// Returns the number of payload bytes sent.
+    fn send(&mut self, quote: &StockQuote) -> Result<usize> {
+        self.write("quote", &quote.to_bytes())
+    }
+
+    /// Tells the client the server has ended its stream.
+    fn send_bye(&mut self) -> Result<()> {
+        self.write("bye", Response::BYE.as_bytes()).map(drop)
+    }
+
+    /// `event` names the Server-Sent Event; other sinks send bare data.
+    fn write(&mut self, event: &str, data: &[u8]) -> Result<usize> {
+        match self {
+            Self::Udp {
+                socket,
+                addr,
+                sealer,
+            } => {
+                let sealed = sealer.as_ref().map(|sealer| sealer.seal(data));
+                let datagram = sealed.as_deref().unwrap_or(data);
+                return Ok(socket.send_to(datagram, addr.socket_addr())?);
+            }
+            Self::Tcp(stream) => write_frame(stream, data)?,
+            Self::Sse(stream) => {
+                write!(stream, "event: {event}\ndata: ")?;
+                stream.write_all(data)?;
+                stream.write_all(b"\n\n")?;
+                stream.flush()?;
+            }
+        }
+
+        Ok(data.len())
+    }
+}
+
 pub struct ClientStreamer {
-    addr: UdpAddr,
+    id: SubscriberId,
     tickers: Tickers,
-    socket: UdpSocket,
+    sink: QuoteSink,
     quote_rx: Receiver<StockQuote>,
-    stop_rx: Receiver<()>,
+    stop_rx: Receiver<Stop>,
 }
 
 impl ClientStreamer {
@@ -167,35 +266,138 @@ impl ClientStreamer {
     pub fn new(
         addr: UdpAddr,
         tickers: Tickers,
+        sealer: Option<Sealer>,
         quote_rx: Receiver<StockQuote>,
-        stop_rx: Receiver<()>,
+        stop_rx: Receiver<Stop>,
     ) -> Result<Self> {
         let socket = UdpSocket::bind("0.0.0.0:0")?;
 
-        Ok(Self {
+        Ok(Self::with_socket(
+            Arc::new(socket),
             addr,
             tickers,
-            socket,
+            sealer,
+            quote_rx,
+            stop_rx,
+        ))
+    }
+
+    /// Streams through an existing socket, so datagrams leave from a port
+    /// the client has already talked to. With a `sealer`, every datagram
+    /// is authenticated with the session key.
+    pub const fn with_socket(
+        socket: Arc<UdpSocket>,
+        addr: UdpAddr,
+        tickers: Tickers,
+        sealer: Option<Sealer>,
+        quote_rx: Receiver<StockQuote>,
+        stop_rx: Receiver<Stop>,
+    ) -> Self {
+        Self {
+            id: SubscriberId::Udp(addr),
+            tickers,
+            sink: QuoteSink::Udp {
+                socket,
+                addr,
+                sealer,
+            },
+            quote_rx,
+            stop_rx,
+        }
+    }
+
+    pub fn multicast(
+        group: UdpAddr,
+        tickers: Tickers,
+        ttl: u32,
+        quote_rx: Receiver<StockQuote>,
+        stop_rx: Receiver<Stop>,
+    ) -> Result<Self> {
+        let socket = if group.socket_addr().is_ipv4() {
+            let socket = UdpSocket::bind("0.0.0.0:0")?;
+            socket.set_multicast_ttl_v4(ttl)?;
+            socket
+        } else {
+            UdpSocket::bind("[::]:0")?
+        };
+
+        Ok(Self {
+            id: SubscriberId::Multicast(group),
+            tickers,
+            sink: QuoteSink::Udp {
+                socket: Arc::new(socket),
+                addr: group,
+                sealer: None,
+            },
+            quote_rx,
+            stop_rx,
+        })
+    }
+
+    /// Writes length-prefixed frames to a control connection, plain or
+    /// TLS, that has already answered the `STREAM` command.
+    pub fn over_tcp(
+        stream: impl Write + Send + 'static,
+        peer_addr: SocketAddr,
+        tickers: Tickers,
+        quote_rx: Receiver<StockQuote>,
+        stop_rx: Receiver<Stop>,
+    ) -> Self {
+        Self {
+            id: SubscriberId::Tcp(peer_addr),
+            tickers,
+            sink: QuoteSink::Tcp(Box::new(stream)),
+            quote_rx,
+            stop_rx,
+        }
+    }
+
+    /// Streams Server-Sent Events on an HTTP connection whose response
+    /// headers have already been written.
+    pub fn over_sse(
+        stream: TcpStream,
+        tickers: Tickers,
+        quote_rx: Receiver<StockQuote>,
+        stop_rx: Receiver<Stop>,
+    ) -> Result<Self> {
+        let peer_addr = stream.peer_addr()?;
+
+        Ok(Self {
+            id: SubscriberId::Sse(peer_addr),
+            tickers,
+            sink: QuoteSink::Sse(stream),
             quote_rx,
             stop_rx,
         })
     }
 
-    pub fn run(self) {
-        info!("Starting stream to {} for tickers: {}", self.addr, self.tickers);
+    pub const fn id(&self) -> SubscriberId {
+        self.id
+    }
+
+    pub fn run(mut self) {
+        info!("Starting stream to {} for tickers: {}", self.id, self.tickers);
 
         if let Err(e) = self.stream_loop() {
-            warn!("Streamer for {} stopped: {}", self.addr, e);
+            warn!("Streamer for {} stopped: {}", self.id, e);
         }
+        METRICS.forget_client(&self.id.to_string());
 
-        info!("Stream to {} ended", self.addr);
+        info!("Stream to {} ended", self.id);
     }
 
-    fn stream_loop(&self) -> Result<()> {
+    fn stream_loop(&mut self) -> Result<()> {
         loop {
             match self.stop_rx.try_recv() {
-                Ok(()) | Err(TryRecvError::Disconnected) => {
-                    debug!("Stop signal received for {}", self.addr);
+                Ok(reason) => {
+                    debug!("Stop signal received for {}", self.id);
+                    if reason == Stop::Shutdown {
+                        self.sink.send_bye()?;
+                    }
+                    break;
+                }
+                Err(TryRecvError::Disconnected) => {
+                    debug!("Stop channel closed for {}", self.id);
                     break;
                 }
                 Err(TryRecvError::Empty) => {}
@@ -207,7 +409,10 @@ impl ClientStreamer {
                 }
                 Err(RecvTimeoutError::Timeout) => {}
                 Err(RecvTimeoutError::Disconnected) => {
-                    debug!("Quote channel disconnected for {}", self.addr);
+                    debug!("Quote channel disconnected for {}", self.id);
+                    if self.stop_rx.try_recv() == Ok(Stop::Shutdown) {
+                        self.sink.send_bye()?;
+                    }
                     break;
                 }
             }
@@ -215,16 +420,23 @@ impl ClientStreamer {
         Ok(())
     }
 
-    fn maybe_send_quote(&self, quote: &StockQuote) -> Result<()> {
+    fn maybe_send_quote(&mut self, quote: &StockQuote) -> Result<()> {
         if !self.tickers.contains(&quote.ticker) {
             return Ok(());
         }
 
-        let data = quote.to_bytes();
-        let addr = self.addr.socket_addr();
-
-        self.socket.send_to(&data, addr)?;
-        debug!("Sent {} to {}", quote.ticker, self.addr);
+        let client = self.id.to_string();
+        match self.sink.send(quote) {
+            Ok(bytes) => {
+                let datagram = matches!(self.sink, QuoteSink::Udp { .. });
+                METRICS.quote_sent(&client, bytes, datagram);
+            }
+            Err(e) => {
+                METRICS.send_failed(&client);
+                return Err(e);
+            }
+        }
+        debug!("Sent {} to {}", quote.ticker, self.id);
 
         Ok(())
     }
@@ -322,6 +534,21 @@ mod tests {
             assert!(!manager.contains(&target));
         }
 
+        #[rstest]
+        fn counts_streams_by_source(
+            manager: ClientManager,
+            target: UdpAddr,
+            tickers: Tickers,
+            source_ip: IpAddr,
+        ) {
+            let other: UdpAddr = "127.0.0.1:8081".parse().unwrap();
+            manager.register(target, &tickers, source_ip);
+            manager.register(other, &tickers, source_ip);
+
+            assert_eq!(manager.count_by_source(source_ip), 2);
+            assert_eq!(manager.count_by_source("10.0.0.9".parse().unwrap()), 0);
+        }
+
         #[rstest]
         fn update_ping_returns_false_for_unknown(manager: ClientManager) {
             let unknown: UdpAddr = "127.0.0.1:9999".parse().unwrap();
@@ -371,6 +598,28 @@ mod tests {
             assert!(manager.contains(&target));
         }
 
+        #[rstest]
+        fn warns_expiring_clients_once(
+            target: UdpAddr,
+            tickers: Tickers,
+            source_ip: IpAddr,
+        ) {
+            let manager = ClientManager::new(Duration::from_millis(100));
+            manager.register(target, &tickers, source_ip);
+            assert_eq!(manager.expiring().len(), 0);
+
+            thread::sleep(Duration::from_millis(60));
+            let expiring = manager.expiring();
+            assert_eq!(expiring.len(), 1);
+            assert_eq!(expiring[0].0, target);
+            assert!(expiring[0].1 <= Duration::from_millis(40));
+            assert_eq!(manager.expiring().len(), 0);
+
+            manager.update_ping(&target);
+            thread::sleep(Duration::from_millis(60));
+            assert_eq!(manager.expiring().len(), 1);
+        }
+
         #[rstest]
         #[case(8080, 8081)]
         #[case(9000, 9001)]
@@ -425,4 +674,155 @@ mod tests {
             assert!(manager.update_ping_by_source(&source_addr));
         }
     }
+
+    mod client_streamer_tests {
+        use super::*;
+        use common::{DatagramKey, FrameReader, Opener, Protection};
+        use crossbeam::channel::unbounded;
+        use rust_decimal::Decimal;
+        use std::net::TcpListener;
+
+        fn quote(ticker: &str) -> StockQuote {
+            StockQuote::new(ticker, Decimal::new(10050, 2), 100).unwrap()
+        }
+
+        #[rstest]
+        fn udp_sends_only_subscribed_tickers(tickers: Tickers) {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            let addr = UdpAddr::from(receiver.local_addr().unwrap());
+
+            let (tx, rx) = unbounded();
+            let (_stop_tx, stop_rx) = unbounded();
+            let streamer =
+                ClientStreamer::new(addr, tickers, None, rx, stop_rx).unwrap();
+            assert_eq!(streamer.id(), SubscriberId::Udp(addr));
+
+            tx.send(quote("GOOGL")).unwrap();
+            tx.send(quote("AAPL")).unwrap();
+            drop(tx);
+            streamer.run();
+
+            let mut buf = [0_u8; 1024];
+            let len = receiver.recv(&mut buf).unwrap();
+            let received: StockQuote =
+                String::from_utf8_lossy(&buf[..len]).parse().unwrap();
+            assert_eq!(received.ticker, "AAPL");
+        }
+
+        #[rstest]
+        fn udp_seals_with_session_key(tickers: Tickers) {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            let addr = UdpAddr::from(receiver.local_addr().unwrap());
+            let key = DatagramKey::generate();
+            let sealer = Sealer::new(key.clone(), Protection::Aead);
+
+            let (tx, rx) = unbounded();
+            let (_stop_tx, stop_rx) = unbounded();
+            let streamer =
+                ClientStreamer::new(addr, tickers, Some(sealer), rx, stop_rx)
+                    .unwrap();
+
+            tx.send(quote("AAPL")).unwrap();
+            drop(tx);
+            streamer.run();
+
+            let mut buf = [0_u8; 1024];
+            let len = receiver.recv(&mut buf).unwrap();
+            let mut opener = Opener::new(key, Protection::Aead);
+            let payload = opener.open(&buf[..len]).unwrap();
+            let received: StockQuote =
+                String::from_utf8_lossy(&payload).parse().unwrap();
+            assert_eq!(received.ticker, "AAPL");
+        }
+
+        #[rstest]
+        fn tcp_sends_framed_quotes(tickers: Tickers) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let client =
+                TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (server_side, peer_addr) = listener.accept().unwrap();
+
+            let (tx, rx) = unbounded();
+            let (_stop_tx, stop_rx) = unbounded();
+            let streamer = ClientStreamer::over_tcp(
+                server_side,
+                peer_addr,
+                tickers,
+                rx,
+                stop_rx,
+            );
+            assert_eq!(
+                streamer.id(),
+                SubscriberId::Tcp(client.local_addr().unwrap())
+            );
+
+            tx.send(quote("TSLA")).unwrap();
+            tx.send(quote("GOOGL")).unwrap();
+            drop(tx);
+            streamer.run();
+
+            let mut reader = FrameReader::new(client);
+            let frame = reader.read_frame().unwrap().unwrap();
+            let received: StockQuote =
+                String::from_utf8_lossy(&frame).parse().unwrap();
+            assert_eq!(received.ticker, "TSLA");
+            assert_eq!(reader.read_frame().unwrap(), None);
+        }
+
+        #[rstest]
+        fn stops_on_signal(target: UdpAddr, tickers: Tickers) {
+            let (_tx, rx) = unbounded();
+            let (stop_tx, stop_rx) = unbounded();
+            let streamer =
+                ClientStreamer::new(target, tickers, None, rx, stop_rx)
+                    .unwrap();
+
+            stop_tx.send(Stop::Removed).unwrap();
+            streamer.run();
+        }
+
+        #[rstest]
+        fn says_bye_on_shutdown(tickers: Tickers) {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver
+                .set_read_timeout(Some(Duration::from_secs(2)))
+                .unwrap();
+            let addr = UdpAddr::from(receiver.local_addr().unwrap());
+            let fan_out = crate::fanout::FanOut::new();
+            let (rx, stop_rx) = fan_out.subscribe(SubscriberId::Udp(addr));
+            let streamer =
+                ClientStreamer::new(addr, tickers, None, rx, stop_rx).unwrap();
+
+            fan_out.stop_all();
+            streamer.run();
+
+            let mut buf = [0_u8; 1024];
+            let len = receiver.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], Response::BYE.as_bytes());
+        }
+
+        #[rstest]
+        fn says_nothing_when_expired(tickers: Tickers) {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver
+                .set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            let addr = UdpAddr::from(receiver.local_addr().unwrap());
+            let fan_out = crate::fanout::FanOut::new();
+            let (rx, stop_rx) = fan_out.subscribe(SubscriberId::Udp(addr));
+            let streamer =
+                ClientStreamer::new(addr, tickers, None, rx, stop_rx).unwrap();
+
+            fan_out.unsubscribe(&SubscriberId::Udp(addr));
+            streamer.run();
+
+            assert!(receiver.recv(&mut [0_u8; 1024]).is_err());
+        }
+    }
 }
@@ -0,0 +1,434 @@
+use anyhow::{anyhow, Context, Result};
+use clap::builder::RangedU64ValueParser;
+use clap::{Parser, Subcommand};
+
+use crate::admin::AdminCommand;
+use crate::auth::Credentials;
+use crate::limits::Limits;
+use crate::policy::TargetPolicy;
+use crate::replay::{Pacing, Replay};
+use crate::tls;
+use common::{Tickers, UdpAddr};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Quote streaming server")]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<ServerCommand>,
+
+    #[arg(short = 't', long, default_value = "5000")]
+    pub tcp_port: u16,
+
+    #[arg(short = 'p', long, default_value = "5001")]
+    pub ping_port: u16,
+
+    #[arg(
+        short = 'm',
+        long = "multicast",
+        value_name = "GROUP=TICKERS",
+        help = "Publish tickers to a multicast group, e.g. \
+                udp://239.1.1.1:6000=AAPL,TSLA (repeatable)"
+    )]
+    pub multicast_groups: Vec<MulticastGroup>,
+
+    #[arg(long, default_value = "1", help = "TTL of multicast datagrams")]
+    pub multicast_ttl: u32,
+
+    #[arg(long, help = "Serve a WebSocket gateway on this port")]
+    pub ws_port: Option<u16>,
+
+    #[arg(long, help = "Serve the HTTP quote API on this port")]
+    pub http_port: Option<u16>,
+
+    #[arg(long, help = "Serve Prometheus metrics at /metrics on this port")]
+    pub metrics_port: Option<u16>,
+
+    #[arg(
+        long,
+        help = "Serve the admin interface on this port, loopback only"
+    )]
+    pub admin_port: Option<u16>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Require AUTH with a token from this file before STREAM"
+    )]
+    pub credentials: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value_t = TargetPolicy::SameIp,
+        value_name = "POLICY",
+        help = "UDP stream targets clients may request: same-ip, \
+                unrestricted, or a list of networks like 10.0.0.0/8,fd00::/8"
+    )]
+    pub target_policy: TargetPolicy,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_key",
+        help = "Serve the TCP control port over TLS with this PEM certificate \
+                chain"
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_cert",
+        help = "PEM private key for --tls-cert"
+    )]
+    pub tls_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_cert",
+        help = "Require client certificates issued by these PEM CA \
+                certificates"
+    )]
+    pub tls_client_ca: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value = "5",
+        help = "How long to wait for threads to stop on shutdown"
+    )]
+    pub shutdown_timeout: u64,
+
+    #[arg(
+        long,
+        default_value_t = Limits::default().connections_per_ip,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Control connections allowed from one IP at a time"
+    )]
+    pub max_connections_per_ip: usize,
+
+    #[arg(
+        long,
+        default_value_t = Limits::default().streams_per_client,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "UDP and NAT streams one client IP may hold"
+    )]
+    pub max_streams_per_client: usize,
+
+    #[arg(
+        long,
+        default_value_t = Limits::default().tickers_per_stream,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Tickers allowed in one STREAM command"
+    )]
+    pub max_tickers_per_stream: usize,
+
+    #[arg(
+        long,
+        default_value_t = Limits::default().commands_per_sec,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Commands per second allowed on one control connection"
+    )]
+    pub max_commands_per_sec: u32,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Replay the quotes in a client capture or CSV history instead \
+                of generating them"
+    )]
+    pub replay: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FACTOR|max",
+        default_value = "1",
+        requires = "replay",
+        help = "Replay this many times faster than recorded (0.001 to \
+                1000000), or without waiting"
+    )]
+    pub replay_speed: Pacing,
+
+    #[arg(
+        long,
+        requires = "replay",
+        help = "Start the replay over at the end"
+    )]
+    pub replay_loop: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ServerCommand {
+    /// Probe a running server's monitoring port; exits non-zero unless
+    /// it is healthy (for Docker HEALTHCHECK)
+    Healthcheck(HealthcheckArgs),
+    /// Send a command to a running server's admin port
+    Admin(AdminArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct HealthcheckArgs {
+    #[arg(
+        long,
+        default_value = "127.0.0.1:9100",
+        help = "Address of the server's --metrics-port"
+    )]
+    pub addr: SocketAddr,
+
+    #[arg(long, help = "Require readiness instead of liveness")]
+    pub ready: bool,
+}
+
+impl HealthcheckArgs {
+    pub const fn path(&self) -> &'static str {
+        if self.ready {
+            "/readyz"
+        } else {
+            "/healthz"
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct AdminArgs {
+    #[arg(
+        long,
+        default_value = "127.0.0.1:5002",
+        help = "Address of the server's --admin-port"
+    )]
+    pub addr: SocketAddr,
+
+    #[command(subcommand)]
+    pub command: AdminCommand,
+}
+
+/// A multicast address that carries a fixed set of tickers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MulticastGroup {
+    pub addr: UdpAddr,
+    pub tickers: Tickers,
+}
+
+impl FromStr for MulticastGroup {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, tickers) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected GROUP=TICKERS, got '{s}'"))?;
+
+        let addr: UdpAddr = addr.parse().context("Invalid group address")?;
+        if !addr.is_multicast() {
+            return Err(anyhow!("{addr} is not a multicast address"));
+        }
+
+        Ok(Self {
+            addr,
+            tickers: tickers.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub tcp_port: u16,
+    pub udp_ping_port: u16,
+    pub ping_timeout: Duration,
+    pub quote_interval: Duration,
+    pub cleanup_interval: Duration,
+    pub shutdown_timeout: Duration,
+    pub multicast_groups: Vec<MulticastGroup>,
+    pub multicast_ttl: u32,
+    pub ws_port: Option<u16>,
+    pub http_port: Option<u16>,
+    pub metrics_port: Option<u16>,
+    pub admin_port: Option<u16>,
+    pub credentials: Option<Credentials>,
+    pub target_policy: TargetPolicy,
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+    pub limits: Limits,
+    pub replay: Option<Replay>,
+}
+
+impl ServerConfig {
+    pub fn from_args(args: &Args) -> Result<Self> {
+        let credentials = args
+            .credentials
+            .as_deref()
+            .map(Credentials::load)
+            .transpose()?;
+
+        let tls = match (&args.tls_cert, &args.tls_key) {
+            (Some(cert), Some(key)) => Some(tls::server_config(
+                cert,
+                key,
+                args.tls_client_ca.as_deref(),
+            )?),
+            _ => None,
+        };
+
+        let mut replay =
+            args.replay.as_deref().map(Replay::load).transpose()?;
+        if let Some(replay) = &mut replay {
+            // Looping without waiting would queue quotes faster than any
+            // subscriber can take them.
+            if args.replay_loop && args.replay_speed == Pacing::Max {
+                return Err(anyhow!(
+                    "--replay-speed max cannot be combined with --replay-loop"
+                ));
+            }
+            replay.pacing = args.replay_speed;
+            replay.repeat = args.replay_loop;
+        }
+
+        Ok(Self {
+            tcp_port: args.tcp_port,
+            udp_ping_port: args.ping_port,
+            shutdown_timeout: Duration::from_secs(args.shutdown_timeout),
+            multicast_groups: args.multicast_groups.clone(),
+            multicast_ttl: args.multicast_ttl,
+            ws_port: args.ws_port,
+            http_port: args.http_port,
+            metrics_port: args.metrics_port,
+            admin_port: args.admin_port,
+            credentials,
+            target_policy: args.target_policy.clone(),
+            tls,
+            limits: Limits {
+                connections_per_ip: args.max_connections_per_ip,
+                streams_per_client: args.max_streams_per_client,
+                tickers_per_stream: args.max_tickers_per_stream,
+                commands_per_sec: args.max_commands_per_sec,
+            },
+            replay,
+            ..Self::default()
+        })
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            tcp_port: 5000,
+            udp_ping_port: 5001,
+            ping_timeout: Duration::from_secs(5),
+            quote_interval: Duration::from_millis(100),
+            cleanup_interval: Duration::from_secs(1),
+            shutdown_timeout: Duration::from_secs(5),
+            multicast_groups: Vec::new(),
+            multicast_ttl: 1,
+            ws_port: None,
+            http_port: None,
+            metrics_port: None,
+            admin_port: None,
+            credentials: None,
+            target_policy: TargetPolicy::default(),
+            tls: None,
+            limits: Limits::default(),
+            replay: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::io::Write;
+
+    #[test]
+    fn parses_multicast_group() {
+        let group: MulticastGroup =
+            "udp://239.1.1.1:6000=aapl,TSLA".parse().unwrap();
+        assert_eq!(group.addr.to_string(), "udp://239.1.1.1:6000");
+        assert_eq!(group.tickers.to_string(), "AAPL,TSLA");
+    }
+
+    #[rstest]
+    #[case("udp://239.1.1.1:6000")]
+    #[case("udp://239.1.1.1:6000=")]
+    #[case("udp://127.0.0.1:6000=AAPL")]
+    #[case("=AAPL")]
+    fn rejects_invalid_group(#[case] input: &str) {
+        assert!(input.parse::<MulticastGroup>().is_err());
+    }
+
+    #[rstest]
+    #[case(&["server", "healthcheck"], "/healthz")]
+    #[case(&["server", "healthcheck", "--ready"], "/readyz")]
+    fn parses_healthcheck(#[case] argv: &[&str], #[case] path: &str) {
+        let args = Args::parse_from(argv);
+
+        let Some(ServerCommand::Healthcheck(check)) = args.command else {
+            panic!("expected healthcheck");
+        };
+        assert_eq!(check.addr.to_string(), "127.0.0.1:9100");
+        assert_eq!(check.path(), path);
+    }
+
+    #[test]
+    fn parses_admin_command() {
+        let args = Args::parse_from([
+            "server",
+            "admin",
+            "--addr",
+            "127.0.0.1:7002",
+            "halt",
+            "aapl",
+        ]);
+
+        let Some(ServerCommand::Admin(admin)) = args.command else {
+            panic!("expected admin");
+        };
+        assert_eq!(admin.addr.to_string(), "127.0.0.1:7002");
+        assert_eq!(admin.command.to_string(), "HALT aapl");
+    }
+
+    #[rstest]
+    #[case("--max-connections-per-ip")]
+    #[case("--max-streams-per-client")]
+    #[case("--max-tickers-per-stream")]
+    #[case("--max-commands-per-sec")]
+    fn rejects_zero_limit(#[case] flag: &str) {
+        assert!(Args::try_parse_from(["server", flag, "0"]).is_err());
+        assert!(Args::try_parse_from(["server", flag, "1"]).is_ok());
+    }
+
+    #[test]
+    fn from_args_keeps_defaults() {
+        let args = Args::parse_from([
+            "server",
+            "-t",
+            "6000",
+            "-m",
+            "udp://239.0.0.1:7000=V",
+        ]);
+        let config = ServerConfig::from_args(&args).unwrap();
+
+        assert_eq!(config.tcp_port, 6000);
+        assert_eq!(config.udp_ping_port, 5001);
+        assert_eq!(config.multicast_groups.len(), 1);
+        assert_eq!(config.ping_timeout, ServerConfig::default().ping_timeout);
+        assert_eq!(config.limits, Limits::default());
+    }
+
+    #[rstest]
+    #[case(&["--replay-speed", "max"], true)]
+    #[case(&["--replay-loop"], true)]
+    #[case(&["--replay-speed", "max", "--replay-loop"], false)]
+    fn checks_replay_options(#[case] options: &[&str], #[case] valid: bool) {
+        let mut history = tempfile::NamedTempFile::new().unwrap();
+        writeln!(history, "timestamp,ticker,price,volume\n1,V,1,1").unwrap();
+        let path = history.path().to_str().unwrap();
+        let args = Args::parse_from(
+            ["server", "--replay", path].iter().chain(options),
+        );
+
+        assert_eq!(ServerConfig::from_args(&args).is_ok(), valid);
+    }
+}
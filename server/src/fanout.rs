@@ -0,0 +1,191 @@
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::client_handler::SubscriberId;
+use crate::metrics::METRICS;
+use common::StockQuote;
+
+type QuoteChannels = Arc<Mutex<HashMap<SubscriberId, Sender<StockQuote>>>>;
+type StopChannels = Arc<Mutex<HashMap<SubscriberId, Sender<Stop>>>>;
+type LastValues = Arc<Mutex<HashMap<String, StockQuote>>>;
+
+/// Why a streamer is told to stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The server is shutting down; the client is told `BYE`.
+    Shutdown,
+    /// Only this subscriber is dropped, e.g. expired or kicked; it may
+    /// come back.
+    Removed,
+}
+
+/// Delivers every generated quote to each subscribed streamer and lets
+/// other threads tell a streamer to stop. Also keeps the last quote seen
+/// for every ticker.
+#[derive(Clone, Default)]
+pub struct FanOut {
+    quote_channels: QuoteChannels,
+    stop_channels: StopChannels,
+    last_values: LastValues,
+    stopped: Arc<AtomicBool>,
+}
+
+impl FanOut {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a subscriber and returns its quote and stop receivers. An
+    /// existing subscriber with the same id is replaced; its channels
+    /// disconnect, which ends its streamer. After [`Self::stop_all`] the
+    /// new subscriber is told to stop at once.
+    pub fn subscribe(
+        &self,
+        id: SubscriberId,
+    ) -> (Receiver<StockQuote>, Receiver<Stop>) {
+        let (quote_tx, quote_rx) = unbounded();
+        let (stop_tx, stop_rx) = unbounded();
+
+        self.quote_channels.lock().insert(id, quote_tx);
+        self.stop_channels.lock().insert(id, stop_tx);
+        if self.stopped.load(Ordering::SeqCst) {
+            self.remove(&id, Stop::Shutdown);
+        }
+
+        (quote_rx, stop_rx)
+    }
+
+    /// Removes a subscriber and signals its streamer to stop.
+    pub fn unsubscribe(&self, id: &SubscriberId) -> bool {
+        self.remove(id, Stop::Removed)
+    }
+
+    /// Removes every subscriber and signals its streamer to stop. Returns
+    /// how many there were.
+    pub fn stop_all(&self) -> usize {
+        self.stopped.store(true, Ordering::SeqCst);
+        let ids: Vec<_> = self.quote_channels.lock().keys().copied().collect();
+        ids.iter()
+            .filter(|id| self.remove(id, Stop::Shutdown))
+            .count()
+    }
+
+    fn remove(&self, id: &SubscriberId, reason: Stop) -> bool {
+        // Stop goes first, so a streamer that sees its quote channel close
+        // can tell it was stopped rather than replaced.
+        let stop_tx = self.stop_channels.lock().remove(id);
+        if let Some(stop_tx) = stop_tx {
+            let _ = stop_tx.send(reason);
+        }
+
+        self.quote_channels.lock().remove(id).is_some()
+    }
+
+    pub fn broadcast(&self, quote: &StockQuote) {
+        self.last_values
+            .lock()
+            .insert(quote.ticker.clone(), quote.clone());
+
+        for sender in self.quote_channels.lock().values() {
+            if sender.send(quote.clone()).is_err() {
+                METRICS.quote_dropped();
+            }
+        }
+    }
+
+    pub fn latest(&self, ticker: &str) -> Option<StockQuote> {
+        self.last_values.lock().get(ticker).cloned()
+    }
+
+    #[allow(dead_code)]
+    pub fn contains(&self, id: &SubscriberId) -> bool {
+        self.quote_channels.lock().contains_key(id)
+    }
+
+    pub fn count(&self) -> usize {
+        self.quote_channels.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::UdpAddr;
+    use crossbeam::channel::TryRecvError;
+    use rstest::{fixture, rstest};
+    use rust_decimal::Decimal;
+
+    #[fixture]
+    fn id() -> SubscriberId {
+        SubscriberId::Udp("127.0.0.1:8080".parse::<UdpAddr>().unwrap())
+    }
+
+    #[fixture]
+    fn quote() -> StockQuote {
+        StockQuote::new("AAPL", Decimal::new(10050, 2), 100).unwrap()
+    }
+
+    #[rstest]
+    fn broadcast_reaches_subscribers(id: SubscriberId, quote: StockQuote) {
+        let fan_out = FanOut::new();
+        let (quote_rx, _stop_rx) = fan_out.subscribe(id);
+
+        fan_out.broadcast(&quote);
+
+        assert_eq!(quote_rx.try_recv().unwrap(), quote);
+    }
+
+    #[rstest]
+    fn broadcast_updates_last_value(quote: StockQuote) {
+        let fan_out = FanOut::new();
+        assert_eq!(fan_out.latest("AAPL"), None);
+
+        fan_out.broadcast(&quote);
+
+        assert_eq!(fan_out.latest("AAPL"), Some(quote));
+        assert_eq!(fan_out.latest("TSLA"), None);
+    }
+
+    #[rstest]
+    fn unsubscribe_signals_stop(id: SubscriberId) {
+        let fan_out = FanOut::new();
+        let (quote_rx, stop_rx) = fan_out.subscribe(id);
+
+        assert!(fan_out.unsubscribe(&id));
+
+        assert_eq!(stop_rx.try_recv(), Ok(Stop::Removed));
+        assert_eq!(quote_rx.try_recv(), Err(TryRecvError::Disconnected));
+        assert!(!fan_out.contains(&id));
+    }
+
+    #[rstest]
+    fn unsubscribe_unknown_returns_false(id: SubscriberId) {
+        assert!(!FanOut::new().unsubscribe(&id));
+    }
+
+    #[rstest]
+    fn resubscribe_disconnects_previous(id: SubscriberId) {
+        let fan_out = FanOut::new();
+        let (old_quote_rx, old_stop_rx) = fan_out.subscribe(id);
+        let (_quote_rx, _stop_rx) = fan_out.subscribe(id);
+
+        assert_eq!(old_quote_rx.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(old_stop_rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[rstest]
+    fn stop_all_stops_current_and_later_subscribers(id: SubscriberId) {
+        let fan_out = FanOut::new();
+        let (_quote_rx, stop_rx) = fan_out.subscribe(id);
+
+        assert_eq!(fan_out.stop_all(), 1);
+        assert_eq!(stop_rx.try_recv(), Ok(Stop::Shutdown));
+
+        let (_late_quote_rx, late_stop_rx) = fan_out.subscribe(id);
+        assert_eq!(late_stop_rx.try_recv(), Ok(Stop::Shutdown));
+        assert_eq!(fan_out.count(), 0);
+    }
+}
@@ -1,15 +1,19 @@
-use anyhow::Result;
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 use common::{StockQuote, Tickers};
 
 pub struct QuoteGenerator {
     prices: HashMap<String, Decimal>,
-    rng: ThreadRng,
+    rng: StdRng,
 }
 
 impl QuoteGenerator {
@@ -39,13 +43,13 @@ impl QuoteGenerator {
     pub fn with_prices(prices: HashMap<String, Decimal>) -> Self {
         Self {
             prices,
-            rng: rand::thread_rng(),
+            rng: StdRng::from_entropy(),
         }
     }
 
     #[allow(dead_code)]
     pub fn with_tickers(tickers: &Tickers) -> Self {
-        let mut rng = rand::thread_rng();
+        let mut rng = StdRng::from_entropy();
         let prices = tickers
             .iter()
             .map(|ticker| {
@@ -122,11 +126,71 @@ impl Default for QuoteGenerator {
     }
 }
 
+/// Settings of the running generator that can change while it runs.
+#[derive(Clone)]
+pub struct GeneratorControl {
+    interval_ms: Arc<AtomicU64>,
+    halted: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl GeneratorControl {
+    pub const MAX_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new(interval: Duration) -> Self {
+        let control = Self {
+            interval_ms: Arc::new(AtomicU64::new(0)),
+            halted: Arc::new(Mutex::new(BTreeSet::new())),
+        };
+        control.store_interval(interval);
+        control
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.load(Ordering::Relaxed))
+    }
+
+    /// Accepts 1 ms up to [`Self::MAX_INTERVAL`].
+    pub fn set_interval(&self, interval: Duration) -> Result<()> {
+        if interval.as_millis() == 0 || interval > Self::MAX_INTERVAL {
+            return Err(anyhow!(
+                "Interval must be between 1 and {} ms",
+                Self::MAX_INTERVAL.as_millis()
+            ));
+        }
+        self.store_interval(interval);
+        Ok(())
+    }
+
+    fn store_interval(&self, interval: Duration) {
+        let millis = u64::try_from(interval.as_millis()).unwrap_or(u64::MAX);
+        self.interval_ms.store(millis, Ordering::Relaxed);
+    }
+
+    /// Stops generating quotes for `ticker`. Returns false if it already
+    /// was halted.
+    pub fn halt(&self, ticker: &str) -> bool {
+        self.halted.lock().insert(ticker.to_uppercase())
+    }
+
+    /// Returns false if `ticker` was not halted.
+    pub fn resume(&self, ticker: &str) -> bool {
+        self.halted.lock().remove(&ticker.to_uppercase())
+    }
+
+    pub fn is_halted(&self, ticker: &str) -> bool {
+        self.halted.lock().contains(ticker)
+    }
+
+    pub fn halted(&self) -> Vec<String> {
+        self.halted.lock().iter().cloned().collect()
+    }
+}
+
 fn is_high_volume_ticker(ticker: &str) -> bool {
     QuoteGenerator::HIGH_VOLUME_TICKERS.contains(&ticker)
 }
 
-fn random_change_factor(rng: &mut ThreadRng) -> Decimal {
+fn random_change_factor(rng: &mut impl Rng) -> Decimal {
     // Change factor from (1 - MAX) to (1 + MAX)
     let change = random_decimal(
         rng,
@@ -136,7 +200,7 @@ fn random_change_factor(rng: &mut ThreadRng) -> Decimal {
     Decimal::ONE + change
 }
 
-fn random_decimal(rng: &mut ThreadRng, min: Decimal, max: Decimal) -> Decimal {
+fn random_decimal(rng: &mut impl Rng, min: Decimal, max: Decimal) -> Decimal {
     let range = max - min;
     let random_factor =
         Decimal::from(rng.gen_range(0_u32..10000)) / dec!(10000);
@@ -144,7 +208,7 @@ fn random_decimal(rng: &mut ThreadRng, min: Decimal, max: Decimal) -> Decimal {
 }
 
 #[allow(dead_code)]
-fn random_price(rng: &mut ThreadRng) -> Decimal {
+fn random_price(rng: &mut impl Rng) -> Decimal {
     random_decimal(
         rng,
         QuoteGenerator::RANDOM_PRICE_MIN,
@@ -274,4 +338,43 @@ mod tests {
             assert!(val < max);
         }
     }
+
+    mod control_tests {
+        use super::*;
+
+        #[test]
+        fn halts_and_resumes_tickers() {
+            let control = GeneratorControl::new(Duration::from_millis(100));
+
+            assert!(control.halt("aapl"));
+            assert!(!control.halt("AAPL"));
+            assert!(control.is_halted("AAPL"));
+            assert_eq!(control.halted(), vec!["AAPL".to_string()]);
+
+            assert!(control.resume("AAPL"));
+            assert!(!control.resume("AAPL"));
+            assert!(!control.is_halted("AAPL"));
+        }
+
+        #[rstest]
+        #[case(Duration::ZERO)]
+        #[case(Duration::from_micros(500))]
+        #[case(GeneratorControl::MAX_INTERVAL + Duration::from_millis(1))]
+        fn rejects_out_of_range_interval(#[case] interval: Duration) {
+            let control = GeneratorControl::new(Duration::from_millis(100));
+
+            assert!(control.set_interval(interval).is_err());
+            assert_eq!(control.interval(), Duration::from_millis(100));
+        }
+
+        #[test]
+        fn changes_interval() {
+            let control = GeneratorControl::new(Duration::from_millis(100));
+            let shared = control.clone();
+
+            control.set_interval(Duration::from_millis(250)).unwrap();
+
+            assert_eq!(shared.interval(), Duration::from_millis(250));
+        }
+    }
 }
@@ -0,0 +1,189 @@
+use anyhow::{anyhow, Context, Result};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+type Beats = Arc<Mutex<BTreeMap<&'static str, Option<Instant>>>>;
+
+/// Heartbeats of the server's long-running threads. A thread that panics,
+/// exits or hangs stops beating and is reported as stalled.
+#[derive(Clone, Default)]
+pub struct Health {
+    beats: Beats,
+}
+
+impl Health {
+    /// Longer than any loop of a healthy subsystem takes to come around.
+    pub const STALE_AFTER: Duration = Duration::from_secs(5);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a subsystem, which keeps the server unready until its
+    /// first beat.
+    pub fn register(&self, name: &'static str) -> Heartbeat {
+        self.beats.lock().insert(name, None);
+        Heartbeat {
+            beats: self.beats.clone(),
+            name,
+        }
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let subsystems: BTreeMap<_, _> = self
+            .beats
+            .lock()
+            .iter()
+            .map(|(name, last)| (*name, SubsystemStatus::from_beat(*last)))
+            .collect();
+
+        HealthReport {
+            live: !subsystems.values().any(|status| {
+                matches!(status, SubsystemStatus::Stalled { .. })
+            }),
+            ready: subsystems
+                .values()
+                .all(|status| *status == SubsystemStatus::Up),
+            subsystems,
+        }
+    }
+}
+
+/// Handle a subsystem thread beats with on every loop iteration. Servers
+/// beat from their accept loop, so the probes notice one that stops.
+#[derive(Clone)]
+pub struct Heartbeat {
+    beats: Beats,
+    name: &'static str,
+}
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        self.beats.lock().insert(self.name, Some(Instant::now()));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum SubsystemStatus {
+    Starting,
+    Up,
+    Stalled { silent_secs: u64 },
+}
+
+impl SubsystemStatus {
+    fn from_beat(last: Option<Instant>) -> Self {
+        match last {
+            None => Self::Starting,
+            Some(last) if last.elapsed() <= Health::STALE_AFTER => Self::Up,
+            Some(last) => Self::Stalled {
+                silent_secs: last.elapsed().as_secs(),
+            },
+        }
+    }
+}
+
+/// Live while no subsystem has stalled; ready once every subsystem is up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub live: bool,
+    pub ready: bool,
+    pub subsystems: BTreeMap<&'static str, SubsystemStatus>,
+}
+
+/// Requests `path` from a running server's monitoring port and fails
+/// unless it answers 200. Returns the response body.
+pub fn probe(addr: SocketAddr, path: &str) -> Result<String> {
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)
+        .with_context(|| format!("Cannot reach {addr}"))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    write!(stream, "GET {path} HTTP/1.1\r\nHost: {addr}\r\n\r\n")?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow!("Malformed response from {addr}"))?;
+    let status = head.lines().next().unwrap_or_default();
+
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(anyhow!("{path} answered '{status}': {body}"));
+    }
+    Ok(body.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn health() -> Health {
+        Health::new()
+    }
+
+    #[rstest]
+    fn starting_is_live_but_not_ready(health: Health) {
+        health.register("generator");
+
+        let report = health.report();
+
+        assert!(report.live);
+        assert!(!report.ready);
+        assert_eq!(report.subsystems["generator"], SubsystemStatus::Starting);
+    }
+
+    #[rstest]
+    fn ready_once_every_subsystem_beats(health: Health) {
+        let generator = health.register("generator");
+        let cleanup = health.register("cleanup");
+        generator.beat();
+        assert!(!health.report().ready);
+
+        cleanup.beat();
+
+        let report = health.report();
+        assert!(report.live);
+        assert!(report.ready);
+    }
+
+    #[rstest]
+    fn silent_subsystem_stalls(health: Health) {
+        let generator = health.register("generator");
+        generator.beat();
+        health.beats.lock().insert(
+            "generator",
+            Instant::now().checked_sub(Duration::from_secs(60)),
+        );
+
+        let report = health.report();
+
+        assert!(!report.live);
+        assert!(!report.ready);
+        assert!(matches!(
+            report.subsystems["generator"],
+            SubsystemStatus::Stalled { silent_secs: 60.. }
+        ));
+    }
+
+    #[test]
+    fn report_serializes_statuses() {
+        let health = Health::new();
+        health.register("control").beat();
+        health.register("generator");
+
+        let json = serde_json::to_string(&health.report()).unwrap();
+
+        assert_eq!(
+            json,
+            r#"{"live":true,"ready":false,"subsystems":{"control":{"status":"up"},"generator":{"status":"starting"}}}"#
+        );
+    }
+}
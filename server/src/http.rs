@@ -0,0 +1,296 @@
+use anyhow::{anyhow, Context, Result};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use url::Url;
+
+use crate::health::Heartbeat;
+use crate::shutdown::Workers;
+
+/// The parts of an HTTP/1.1 request line the server routes on. Headers
+/// and bodies are read past and ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    query: HashMap<String, String>,
+}
+
+impl Request {
+    const MAX_HEADER_LINES: usize = 100;
+
+    pub fn read_from(reader: &mut impl BufRead) -> Result<Self> {
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts
+            .next()
+            .ok_or_else(|| anyhow!("Empty request line"))?
+            .to_string();
+        let target = parts
+            .next()
+            .ok_or_else(|| anyhow!("Missing request target"))?;
+        if !target.starts_with('/') {
+            return Err(anyhow!("Unsupported request target '{target}'"));
+        }
+
+        let url = Url::parse(&format!("http://localhost{target}"))
+            .with_context(|| format!("Invalid request target '{target}'"))?;
+        let query = url.query_pairs().into_owned().collect();
+
+        for _ in 0..Self::MAX_HEADER_LINES {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                return Ok(Self {
+                    method,
+                    path: url.path().to_string(),
+                    query,
+                });
+            }
+        }
+
+        Err(anyhow!("Too many request headers"))
+    }
+
+    pub fn query(&self, key: &str) -> Option<&str> {
+        self.query.get(key).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    status: u16,
+    reason: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(body: &impl Serialize) -> Self {
+        Self {
+            status: 200,
+            reason: "OK",
+            content_type: "application/json",
+            body: serde_json::to_vec(body).unwrap_or_default(),
+        }
+    }
+
+    pub const fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            reason: "OK",
+            content_type,
+            body,
+        }
+    }
+
+    pub fn error(status: u16, reason: &'static str, message: &str) -> Self {
+        Self {
+            status,
+            reason,
+            content_type: "application/json",
+            body: serde_json::to_vec(&serde_json::json!({ "error": message }))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub const fn with_status(
+        mut self,
+        status: u16,
+        reason: &'static str,
+    ) -> Self {
+        self.status = status;
+        self.reason = reason;
+        self
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        Self::error(404, "Not Found", message)
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        Self::error(400, "Bad Request", message)
+    }
+
+    pub fn method_not_allowed() -> Self {
+        Self::error(405, "Method Not Allowed", "Only GET is supported")
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            self.status,
+            self.reason,
+            self.content_type,
+            self.body.len()
+        )?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+/// Routes one request. Handlers own the connection, so they can either
+/// write a [`Response`] or keep streaming (Server-Sent Events).
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: &Request, stream: TcpStream) -> Result<()>;
+}
+
+/// Accepts HTTP connections and hands each request to a [`Handler`] on its
+/// own thread. One request per connection.
+pub struct HttpServer<H> {
+    listener: TcpListener,
+    handler: Arc<H>,
+    running: Arc<AtomicBool>,
+    heartbeat: Option<Heartbeat>,
+    workers: Workers,
+}
+
+impl<H: Handler> HttpServer<H> {
+    const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+    const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn bind(
+        addr: SocketAddr,
+        handler: H,
+        running: Arc<AtomicBool>,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            handler: Arc::new(handler),
+            running,
+            heartbeat: None,
+            workers: Workers::new(),
+        })
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    pub fn with_workers(mut self, workers: Workers) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn run(&self) {
+        if let Ok(addr) = self.listener.local_addr() {
+            info!("HTTP server listening on {addr}");
+        }
+
+        while self.running.load(Ordering::SeqCst) {
+            if let Some(heartbeat) = &self.heartbeat {
+                heartbeat.beat();
+            }
+            match self.listener.accept() {
+                Ok((stream, peer_addr)) => {
+                    let handler = self.handler.clone();
+                    self.workers.spawn(
+                        format!("http {peer_addr}"),
+                        move || {
+                            if let Err(e) =
+                                Self::serve(stream, handler.as_ref())
+                            {
+                                warn!("HTTP {peer_addr} error: {e}");
+                            }
+                        },
+                    );
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Self::ACCEPT_POLL_INTERVAL);
+                }
+                Err(e) => error!("Failed to accept HTTP connection: {e}"),
+            }
+        }
+
+        info!("HTTP server stopped");
+    }
+
+    fn serve(stream: TcpStream, handler: &H) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Self::REQUEST_READ_TIMEOUT))?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let request = match Request::read_from(&mut reader) {
+            Ok(request) => request,
+            Err(e) => {
+                Response::bad_request(&e.to_string()).write_to(&mut &stream)?;
+                return Err(e);
+            }
+        };
+
+        if request.method != "GET" {
+            Response::method_not_allowed().write_to(&mut &stream)?;
+            return Ok(());
+        }
+
+        handler.handle(&request, stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::io::Cursor;
+
+    fn parse(raw: &str) -> Result<Request> {
+        Request::read_from(&mut Cursor::new(raw.as_bytes()))
+    }
+
+    #[test]
+    fn parses_request_line_and_query() {
+        let request = parse(
+            "GET /stream?tickers=AAPL%2CTSLA&x=1 HTTP/1.1\r\n\
+             Host: localhost\r\nAccept: */*\r\n\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/stream");
+        assert_eq!(request.query("tickers"), Some("AAPL,TSLA"));
+        assert_eq!(request.query("missing"), None);
+    }
+
+    #[test]
+    fn tolerates_missing_blank_line() {
+        let request = parse("GET /tickers HTTP/1.1\r\nHost: x\r\n").unwrap();
+        assert_eq!(request.path, "/tickers");
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("GET")]
+    #[case("GET http://[bad HTTP/1.1\r\n\r\n")]
+    fn rejects_malformed(#[case] raw: &str) {
+        assert!(parse(raw).is_err());
+    }
+
+    #[test]
+    fn writes_status_and_body() {
+        let mut out = Vec::new();
+        Response::not_found("nope").write_to(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(text.contains("Content-Length: 16\r\n"));
+        assert!(text.ends_with(r#"{"error":"nope"}"#));
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::Result;
+use log::info;
+use std::io::Write;
+use std::net::TcpStream;
+
+use crate::client_handler::{ClientStreamer, SubscriberId};
+use crate::fanout::FanOut;
+use crate::http::{Handler, Request, Response};
+use common::Tickers;
+
+/// Read-only quote API for scripts and curl:
+///
+/// - `GET /tickers` lists the ticker universe
+/// - `GET /quotes/{ticker}` returns the last quote for a ticker
+/// - `GET /stream?tickers=A,B` streams new quotes as Server-Sent Events
+pub struct QuoteApi {
+    fan_out: FanOut,
+    universe: Vec<String>,
+}
+
+impl QuoteApi {
+    const QUOTES_PREFIX: &str = "/quotes/";
+
+    pub const fn new(fan_out: FanOut, universe: Vec<String>) -> Self {
+        Self { fan_out, universe }
+    }
+
+    fn latest_quote(&self, ticker: &str) -> Response {
+        let ticker = ticker.to_uppercase();
+        self.fan_out.latest(&ticker).map_or_else(
+            || Response::not_found(&format!("No quote for {ticker}")),
+            |quote| Response::json(&quote),
+        )
+    }
+
+    fn stream(&self, request: &Request, mut stream: TcpStream) -> Result<()> {
+        let tickers = match request.query("tickers").map(str::parse::<Tickers>)
+        {
+            Some(Ok(tickers)) => tickers,
+            Some(Err(e)) => {
+                return Ok(Response::bad_request(&e.to_string())
+                    .write_to(&mut stream)?);
+            }
+            None => {
+                return Ok(Response::bad_request(
+                    "Missing 'tickers' parameter",
+                )
+                .write_to(&mut stream)?);
+            }
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+             Cache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+        )?;
+        stream.flush()?;
+
+        let id = SubscriberId::Sse(stream.peer_addr()?);
+        info!("Starting SSE stream to {id} for tickers: {tickers}");
+        let (rx, stop_rx) = self.fan_out.subscribe(id);
+
+        ClientStreamer::over_sse(stream, tickers, rx, stop_rx)?.run();
+
+        self.fan_out.unsubscribe(&id);
+        Ok(())
+    }
+}
+
+impl Handler for QuoteApi {
+    fn handle(&self, request: &Request, mut stream: TcpStream) -> Result<()> {
+        let response = match request.path.as_str() {
+            "/tickers" => Response::json(&self.universe),
+            "/stream" => return self.stream(request, stream),
+            path => match path.strip_prefix(Self::QUOTES_PREFIX) {
+                Some(ticker) if !ticker.is_empty() => self.latest_quote(ticker),
+                _ => Response::not_found(&format!("No route for {path}")),
+            },
+        };
+
+        Ok(response.write_to(&mut stream)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpServer;
+    use crate::test_support::{serve, Serving};
+    use common::StockQuote;
+    use rstest::{fixture, rstest};
+    use rust_decimal::Decimal;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::SocketAddr;
+    use std::thread;
+    use std::time::Duration;
+
+    type TestApi = Serving<FanOut>;
+
+    #[fixture]
+    fn api() -> TestApi {
+        let fan_out = FanOut::new();
+        let universe = vec!["AAPL".to_string(), "TSLA".to_string()];
+        let api = QuoteApi::new(fan_out.clone(), universe);
+        serve(fan_out, |addr, running| HttpServer::bind(addr, api, running))
+    }
+
+    fn quote(ticker: &str) -> StockQuote {
+        StockQuote::new(ticker, Decimal::new(10050, 2), 100).unwrap()
+    }
+
+    fn send_request(addr: SocketAddr, target: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(stream, "GET {target} HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+        stream
+    }
+
+    fn get(addr: SocketAddr, target: &str) -> (String, String) {
+        let mut response = String::new();
+        send_request(addr, target)
+            .read_to_string(&mut response)
+            .unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_string();
+        (status, body.to_string())
+    }
+
+    #[rstest]
+    fn lists_tickers(api: TestApi) {
+        let (status, body) = get(api.addr, "/tickers");
+
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, r#"["AAPL","TSLA"]"#);
+    }
+
+    #[rstest]
+    fn returns_latest_quote(api: TestApi) {
+        let first = quote("AAPL");
+        let second = quote("AAPL");
+        api.shared.broadcast(&first);
+        api.shared.broadcast(&second);
+
+        let (status, body) = get(api.addr, "/quotes/aapl");
+
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body.parse::<StockQuote>().unwrap(), second);
+    }
+
+    #[rstest]
+    #[case("/quotes/NOPE")]
+    #[case("/quotes/")]
+    #[case("/unknown")]
+    fn unknown_is_not_found(api: TestApi, #[case] target: &str) {
+        let (status, _) = get(api.addr, target);
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    }
+
+    #[rstest]
+    #[case("/stream")]
+    #[case("/stream?tickers=,")]
+    fn stream_requires_tickers(api: TestApi, #[case] target: &str) {
+        let (status, _) = get(api.addr, target);
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    }
+
+    #[rstest]
+    fn streams_server_sent_events(api: TestApi) {
+        let stream = send_request(api.addr, "/stream?tickers=TSLA");
+        let mut reader = BufReader::new(stream);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "HTTP/1.1 200 OK\r\n");
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+
+        let expected = quote("TSLA");
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !api.shared.contains(&SubscriberId::Sse(
+            reader.get_ref().local_addr().unwrap(),
+        )) {
+            assert!(std::time::Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+        api.shared.broadcast(&quote("AAPL"));
+        api.shared.broadcast(&expected);
+
+        let mut event = String::new();
+        reader.read_line(&mut event).unwrap();
+        let mut data = String::new();
+        reader.read_line(&mut data).unwrap();
+
+        assert_eq!(event, "event: quote\n");
+        let json = data.strip_prefix("data: ").unwrap().trim_end();
+        assert_eq!(json.parse::<StockQuote>().unwrap(), expected);
+    }
+}
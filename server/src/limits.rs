@@ -0,0 +1,219 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use thiserror::Error;
+
+use common::Tickers;
+
+/// Why a connection or command was refused. Sent to clients as
+/// `ERR LIMIT <kind>: <detail>`, so they can tell limits from other errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum LimitError {
+    #[error("LIMIT connections: at most {limit} per IP")]
+    Connections { limit: usize },
+    #[error("LIMIT streams: at most {limit} per client")]
+    Streams { limit: usize },
+    #[error("LIMIT tickers: {requested} requested, at most {limit}")]
+    Tickers { requested: usize, limit: usize },
+    #[error("LIMIT rate: at most {limit} commands per second")]
+    Rate { limit: u32 },
+}
+
+/// Caps on what a single peer may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub connections_per_ip: usize,
+    pub streams_per_client: usize,
+    pub tickers_per_stream: usize,
+    pub commands_per_sec: u32,
+}
+
+impl Limits {
+    pub fn check_tickers(&self, tickers: &Tickers) -> Result<(), LimitError> {
+        let requested = tickers.len();
+        if requested > self.tickers_per_stream {
+            return Err(LimitError::Tickers {
+                requested,
+                limit: self.tickers_per_stream,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            connections_per_ip: 16,
+            streams_per_client: 8,
+            tickers_per_stream: 50,
+            commands_per_sec: 20,
+        }
+    }
+}
+
+type ConnectionCounts = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// Counts open control connections per IP.
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+    counts: ConnectionCounts,
+    limit: usize,
+}
+
+impl ConnectionLimiter {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            counts: Arc::new(Mutex::new(HashMap::new())),
+            limit,
+        }
+    }
+
+    /// Reserves a slot for `ip`, released when the permit is dropped.
+    pub fn acquire(&self, ip: IpAddr) -> Result<ConnectionPermit, LimitError> {
+        let mut counts = self.counts.lock();
+        let count = counts.entry(ip).or_insert(0);
+        if *count >= self.limit {
+            return Err(LimitError::Connections { limit: self.limit });
+        }
+        *count += 1;
+        drop(counts);
+
+        Ok(ConnectionPermit {
+            counts: self.counts.clone(),
+            ip,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn count(&self, ip: IpAddr) -> usize {
+        self.counts.lock().get(&ip).copied().unwrap_or(0)
+    }
+}
+
+pub struct ConnectionPermit {
+    counts: ConnectionCounts,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock();
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Token bucket allowing bursts of up to one second's worth of commands.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_sec: u32,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_sec: u32) -> Self {
+        Self {
+            per_sec,
+            tokens: f64::from(per_sec),
+            refilled: Instant::now(),
+        }
+    }
+
+    pub fn check(&mut self) -> Result<(), LimitError> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        let capacity = f64::from(self.per_sec);
+        self.tokens = elapsed.mul_add(capacity, self.tokens).min(capacity);
+        self.refilled = now;
+
+        if self.tokens < 1.0 {
+            return Err(LimitError::Rate {
+                limit: self.per_sec,
+            });
+        }
+        self.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+    use std::thread;
+    use std::time::Duration;
+
+    #[fixture]
+    fn ip() -> IpAddr {
+        "10.0.0.1".parse().unwrap()
+    }
+
+    #[rstest]
+    fn caps_connections_per_ip(ip: IpAddr) {
+        let limiter = ConnectionLimiter::new(2);
+        let _first = limiter.acquire(ip).unwrap();
+        let _second = limiter.acquire(ip).unwrap();
+
+        assert_eq!(
+            limiter.acquire(ip).err(),
+            Some(LimitError::Connections { limit: 2 })
+        );
+        assert!(limiter.acquire("10.0.0.2".parse().unwrap()).is_ok());
+    }
+
+    #[rstest]
+    fn dropped_permit_frees_slot(ip: IpAddr) {
+        let limiter = ConnectionLimiter::new(1);
+        let permit = limiter.acquire(ip).unwrap();
+        drop(permit);
+
+        assert_eq!(limiter.count(ip), 0);
+        assert!(limiter.acquire(ip).is_ok());
+    }
+
+    #[rstest]
+    #[case("AAPL,TSLA", true)]
+    #[case("AAPL,TSLA,GOOGL", false)]
+    fn caps_tickers(#[case] tickers: &str, #[case] allowed: bool) {
+        let limits = Limits {
+            tickers_per_stream: 2,
+            ..Limits::default()
+        };
+
+        let result = limits.check_tickers(&tickers.parse().unwrap());
+
+        assert_eq!(result.is_ok(), allowed);
+    }
+
+    #[test]
+    fn rate_limiter_allows_burst_then_refills() {
+        let mut limiter = RateLimiter::new(50);
+        for _ in 0..50 {
+            limiter.check().unwrap();
+        }
+        assert_eq!(limiter.check(), Err(LimitError::Rate { limit: 50 }));
+
+        thread::sleep(Duration::from_millis(60));
+        assert!(limiter.check().is_ok());
+    }
+
+    #[test]
+    fn error_is_structured() {
+        let error = LimitError::Tickers {
+            requested: 12,
+            limit: 10,
+        };
+        assert_eq!(
+            error.to_string(),
+            "LIMIT tickers: 12 requested, at most 10"
+        );
+    }
+}
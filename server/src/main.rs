@@ -1,10 +1,31 @@
+mod admin;
+mod auth;
 mod client_handler;
+mod config;
+mod fanout;
 mod generator;
+mod health;
+mod http;
+mod http_api;
+mod limits;
+mod metrics;
+mod notify;
+mod policy;
+mod replay;
 mod server;
+mod session;
+mod shutdown;
+mod source;
+#[cfg(test)]
+mod test_support;
+mod tls;
+mod ws_gateway;
 
 use anyhow::Result;
+use clap::Parser;
+use config::{Args, ServerCommand, ServerConfig};
 use log::info;
-use server::{Server, ServerConfig};
+use server::Server;
 use std::sync::atomic::Ordering;
 
 fn main() -> Result<()> {
@@ -13,7 +34,24 @@ fn main() -> Result<()> {
     )
     .init();
 
-    let config = ServerConfig::default();
+    let args = Args::parse();
+    match &args.command {
+        Some(ServerCommand::Healthcheck(check)) => {
+            let body = health::probe(check.addr, check.path())?;
+            println!("{body}");
+            return Ok(());
+        }
+        Some(ServerCommand::Admin(admin)) => {
+            let reply = admin::send(admin.addr, &admin.command)?;
+            if !reply.is_empty() {
+                println!("{reply}");
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let config = ServerConfig::from_args(&args)?;
     let server = Server::new(config);
 
     let running = server.running();
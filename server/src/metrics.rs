@@ -0,0 +1,289 @@
+use anyhow::Result;
+use prometheus::{
+    Encoder, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::net::TcpStream;
+use std::sync::{Arc, LazyLock};
+
+use crate::client_handler::ClientManager;
+use crate::health::{Health, HealthReport};
+use crate::http::{Handler, Request, Response};
+
+/// Process-wide counters, updated from the threads that do the work.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    active_clients: IntGauge,
+    quotes_generated: IntCounterVec,
+    datagrams_sent: IntCounterVec,
+    bytes_sent: IntCounterVec,
+    send_errors: IntCounterVec,
+    quotes_dropped: IntCounter,
+    pings_received: IntCounterVec,
+    clients_expired: IntCounter,
+}
+
+impl Metrics {
+    const NAMESPACE: &str = "quote_server";
+
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        Self {
+            active_clients: gauge(
+                &registry,
+                "active_clients",
+                "UDP clients currently registered",
+            ),
+            quotes_generated: counter_vec(
+                &registry,
+                "quotes_generated_total",
+                "Quotes produced by the generator",
+                "ticker",
+            ),
+            datagrams_sent: counter_vec(
+                &registry,
+                "datagrams_sent_total",
+                "Quote datagrams sent over UDP",
+                "client",
+            ),
+            bytes_sent: counter_vec(
+                &registry,
+                "bytes_sent_total",
+                "Quote payload bytes sent, any transport",
+                "client",
+            ),
+            send_errors: counter_vec(
+                &registry,
+                "send_errors_total",
+                "Failed quote sends, each ends its stream",
+                "client",
+            ),
+            quotes_dropped: counter(
+                &registry,
+                "quotes_dropped_total",
+                "Quotes not delivered because a subscriber had gone away",
+            ),
+            pings_received: counter_vec(
+                &registry,
+                "pings_received_total",
+                "Keep-alive datagrams on the ping port",
+                "kind",
+            ),
+            clients_expired: counter(
+                &registry,
+                "clients_expired_total",
+                "UDP clients removed after missing their pings",
+            ),
+            registry,
+        }
+    }
+
+    pub fn quote_generated(&self, ticker: &str) {
+        self.quotes_generated.with_label_values(&[ticker]).inc();
+    }
+
+    pub fn quote_sent(&self, client: &str, bytes: usize, datagram: bool) {
+        self.bytes_sent
+            .with_label_values(&[client])
+            .inc_by(bytes as u64);
+        if datagram {
+            self.datagrams_sent.with_label_values(&[client]).inc();
+        }
+    }
+
+    pub fn send_failed(&self, client: &str) {
+        self.send_errors.with_label_values(&[client]).inc();
+    }
+
+    pub fn quote_dropped(&self) {
+        self.quotes_dropped.inc();
+    }
+
+    /// `kind` is `ping` or `register`.
+    pub fn ping_received(&self, kind: &str) {
+        self.pings_received.with_label_values(&[kind]).inc();
+    }
+
+    pub fn clients_expired(&self, count: usize) {
+        self.clients_expired.inc_by(count as u64);
+    }
+
+    /// Drops the series of a finished stream, so departed clients do not
+    /// pile up in every scrape.
+    pub fn forget_client(&self, client: &str) {
+        let _ = self.datagrams_sent.remove_label_values(&[client]);
+        let _ = self.bytes_sent.remove_label_values(&[client]);
+        let _ = self.send_errors.remove_label_values(&[client]);
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self, active_clients: usize) -> Result<Vec<u8>> {
+        self.active_clients
+            .set(i64::try_from(active_clients).unwrap_or(i64::MAX));
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str) -> IntGauge {
+    let gauge = IntGauge::with_opts(
+        Opts::new(name, help).namespace(Metrics::NAMESPACE),
+    )
+    .expect("metric options are valid");
+    registry
+        .register(Box::new(gauge.clone()))
+        .expect("metric names are unique");
+    gauge
+}
+
+fn counter(registry: &Registry, name: &str, help: &str) -> IntCounter {
+    let counter = IntCounter::with_opts(
+        Opts::new(name, help).namespace(Metrics::NAMESPACE),
+    )
+    .expect("metric options are valid");
+    registry
+        .register(Box::new(counter.clone()))
+        .expect("metric names are unique");
+    counter
+}
+
+fn counter_vec(
+    registry: &Registry,
+    name: &str,
+    help: &str,
+    label: &str,
+) -> IntCounterVec {
+    let counter = IntCounterVec::new(
+        Opts::new(name, help).namespace(Metrics::NAMESPACE),
+        &[label],
+    )
+    .expect("metric options are valid");
+    registry
+        .register(Box::new(counter.clone()))
+        .expect("metric names are unique");
+    counter
+}
+
+/// Monitoring endpoints:
+///
+/// - `GET /metrics` for Prometheus
+/// - `GET /healthz` answers 503 once a subsystem thread has stalled
+/// - `GET /readyz` answers 503 until every subsystem thread is up
+pub struct MetricsApi {
+    client_manager: Arc<ClientManager>,
+    health: Health,
+}
+
+impl MetricsApi {
+    pub const fn new(
+        client_manager: Arc<ClientManager>,
+        health: Health,
+    ) -> Self {
+        Self {
+            client_manager,
+            health,
+        }
+    }
+
+    fn probe(&self, passed: impl Fn(&HealthReport) -> bool) -> Response {
+        let report = self.health.report();
+        let response = Response::json(&report);
+        if passed(&report) {
+            response
+        } else {
+            response.with_status(503, "Service Unavailable")
+        }
+    }
+}
+
+impl Handler for MetricsApi {
+    fn handle(&self, request: &Request, mut stream: TcpStream) -> Result<()> {
+        let response = match request.path.as_str() {
+            "/metrics" => Response::ok(
+                prometheus::TEXT_FORMAT,
+                METRICS.render(self.client_manager.count())?,
+            ),
+            "/healthz" => self.probe(|report| report.live),
+            "/readyz" => self.probe(|report| report.ready),
+            path => Response::not_found(&format!("No route for {path}")),
+        };
+
+        Ok(response.write_to(&mut stream)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpServer;
+    use crate::test_support::serve;
+    use std::io::{Read, Write};
+    use std::net::IpAddr;
+
+    fn get(
+        client_manager: Arc<ClientManager>,
+        health: Health,
+        path: &str,
+    ) -> String {
+        let api = MetricsApi::new(client_manager, health);
+        let serving =
+            serve((), |addr, running| HttpServer::bind(addr, api, running));
+
+        let mut stream = TcpStream::connect(serving.addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn exposes_counters_and_active_clients() {
+        let manager = Arc::new(ClientManager::default());
+        let source_ip: IpAddr = "127.0.0.1".parse().unwrap();
+        manager.register(
+            "127.0.0.1:9000".parse().unwrap(),
+            &"AAPL".parse().unwrap(),
+            source_ip,
+        );
+        METRICS.quote_sent("udp://127.0.0.1:9000", 42, true);
+
+        let response = get(manager, Health::new(), "/metrics");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("quote_server_active_clients 1"));
+        assert!(response.contains(
+            "quote_server_datagrams_sent_total{client=\"udp://127.0.0.1:9000\"}"
+        ));
+    }
+
+    #[test]
+    fn probes_follow_health() {
+        let manager = Arc::new(ClientManager::default());
+        let health = Health::new();
+        health.register("control").beat();
+        health.register("generator");
+
+        let live = get(manager.clone(), health.clone(), "/healthz");
+        let ready = get(manager, health, "/readyz");
+
+        assert!(live.starts_with("HTTP/1.1 200 OK"));
+        assert!(ready.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(ready.contains(r#""generator":{"status":"starting"}"#));
+    }
+
+    #[test]
+    fn forgets_finished_clients() {
+        let client = "tcp://127.0.0.1:4242";
+        METRICS.quote_sent(client, 10, false);
+        METRICS.forget_client(client);
+
+        let families = METRICS.registry.gather();
+        let rendered = TextEncoder::new().encode_to_string(&families).unwrap();
+
+        assert!(!rendered.contains(client));
+    }
+}
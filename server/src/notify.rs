@@ -0,0 +1,175 @@
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use common::{Notification, SessionId, UdpAddr};
+
+#[derive(Default)]
+struct Routes {
+    connections: HashMap<SocketAddr, Sender<Notification>>,
+    /// Control connection that asked for each UDP stream.
+    owners: HashMap<UdpAddr, SocketAddr>,
+    /// Control connection that opened each NAT session.
+    sessions: HashMap<SessionId, SocketAddr>,
+}
+
+/// Routes [`Notification`]s to control connections, either to all of them
+/// or to the one that owns a stream.
+#[derive(Clone, Default)]
+pub struct Notifier {
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribes the control connection from `peer`, until the returned
+    /// subscription is dropped.
+    pub fn connect(&self, peer: SocketAddr) -> Subscription {
+        let (tx, notices) = unbounded();
+        self.routes.lock().connections.insert(peer, tx);
+
+        Subscription {
+            notifier: self.clone(),
+            peer,
+            notices,
+        }
+    }
+
+    fn disconnect(&self, peer: SocketAddr) {
+        let mut routes = self.routes.lock();
+        routes.connections.remove(&peer);
+        routes.owners.retain(|_, owner| *owner != peer);
+        routes.sessions.retain(|_, owner| *owner != peer);
+    }
+
+    /// Records that `peer` asked for the stream to `target`.
+    pub fn watch(&self, target: UdpAddr, peer: SocketAddr) {
+        self.routes.lock().owners.insert(target, peer);
+    }
+
+    pub fn watch_session(&self, id: SessionId, peer: SocketAddr) {
+        self.routes.lock().sessions.insert(id, peer);
+    }
+
+    /// Hands a NAT session's owner the stream it registered, once its
+    /// target is known.
+    pub fn bind_session(&self, id: SessionId, target: UdpAddr) {
+        let mut routes = self.routes.lock();
+        if let Some(peer) = routes.sessions.get(&id).copied() {
+            routes.owners.insert(target, peer);
+        }
+    }
+
+    /// Notifies the owner of the stream to `target`. Returns false if no
+    /// connected control connection owns it.
+    pub fn notify(&self, target: &UdpAddr, notification: Notification) -> bool {
+        let routes = self.routes.lock();
+        routes
+            .owners
+            .get(target)
+            .and_then(|peer| routes.connections.get(peer))
+            .is_some_and(|tx| tx.send(notification).is_ok())
+    }
+
+    /// Notifies every control connection. Returns how many were reached.
+    pub fn broadcast(&self, notification: &Notification) -> usize {
+        self.routes
+            .lock()
+            .connections
+            .values()
+            .filter(|tx| tx.send(notification.clone()).is_ok())
+            .count()
+    }
+}
+
+/// Notifications waiting to be written to one control connection.
+pub struct Subscription {
+    notifier: Notifier,
+    peer: SocketAddr,
+    notices: Receiver<Notification>,
+}
+
+impl Subscription {
+    pub fn pending(&self) -> impl Iterator<Item = Notification> + '_ {
+        self.notices.try_iter()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.notifier.disconnect(self.peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn peer() -> SocketAddr {
+        "127.0.0.1:40000".parse().unwrap()
+    }
+
+    #[fixture]
+    fn target() -> UdpAddr {
+        "127.0.0.1:9000".parse().unwrap()
+    }
+
+    fn expiring(target: UdpAddr) -> Notification {
+        Notification::Expiring { target, secs: 2 }
+    }
+
+    #[rstest]
+    fn notifies_stream_owner(peer: SocketAddr, target: UdpAddr) {
+        let notifier = Notifier::new();
+        let subscription = notifier.connect(peer);
+        let other = notifier.connect("127.0.0.1:40001".parse().unwrap());
+        notifier.watch(target, peer);
+
+        assert!(notifier.notify(&target, expiring(target)));
+
+        assert_eq!(
+            subscription.pending().collect::<Vec<_>>(),
+            [expiring(target)]
+        );
+        assert_eq!(other.pending().count(), 0);
+    }
+
+    #[rstest]
+    fn routes_registered_sessions(peer: SocketAddr, target: UdpAddr) {
+        let notifier = Notifier::new();
+        let subscription = notifier.connect(peer);
+        let id = SessionId::from(7);
+        notifier.watch_session(id, peer);
+        assert!(!notifier.notify(&target, expiring(target)));
+
+        notifier.bind_session(id, target);
+
+        assert!(notifier.notify(&target, expiring(target)));
+        assert_eq!(subscription.pending().count(), 1);
+    }
+
+    #[rstest]
+    fn broadcasts_until_disconnected(peer: SocketAddr, target: UdpAddr) {
+        let notifier = Notifier::new();
+        let subscription = notifier.connect(peer);
+        let _other = notifier.connect("127.0.0.1:40001".parse().unwrap());
+        notifier.watch(target, peer);
+
+        assert_eq!(notifier.broadcast(&Notification::Shutdown), 2);
+        assert_eq!(
+            subscription.pending().collect::<Vec<_>>(),
+            [Notification::Shutdown]
+        );
+
+        drop(subscription);
+        assert_eq!(notifier.broadcast(&Notification::Shutdown), 1);
+        assert!(!notifier.notify(&target, expiring(target)));
+    }
+}
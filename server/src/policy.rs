@@ -0,0 +1,267 @@
+use anyhow::{anyhow, Context, Result};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use thiserror::Error;
+
+use common::UdpAddr;
+
+/// Why a `STREAM` target was refused.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum TargetError {
+    #[error("{0} is a multicast address, join a published group instead")]
+    Multicast(UdpAddr),
+    #[error("{target} is not the address of this connection ({peer})")]
+    NotPeer { target: UdpAddr, peer: IpAddr },
+    #[error("{0} is not in an allowed target range")]
+    NotAllowed(UdpAddr),
+}
+
+/// An IP network such as `10.0.0.0/8`. A bare address is a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix));
+                let mask = mask.unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix));
+                let mask = mask.unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = s.split_once('/').unwrap_or((s, ""));
+        let addr = addr
+            .parse::<IpAddr>()
+            .with_context(|| format!("Invalid network address '{s}'"))?
+            .to_canonical();
+
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = if prefix.is_empty() {
+            max_prefix
+        } else {
+            prefix
+                .parse()
+                .with_context(|| format!("Invalid prefix length in '{s}'"))?
+        };
+
+        if prefix > max_prefix {
+            return Err(anyhow!("Prefix length {prefix} too long for {addr}"));
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Which UDP addresses a client may ask the server to stream to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TargetPolicy {
+    /// Only the IP address the TCP command came from.
+    #[default]
+    SameIp,
+    /// Any address inside one of the listed networks.
+    AllowList(Vec<Cidr>),
+    /// Any unicast address.
+    Unrestricted,
+}
+
+impl TargetPolicy {
+    const SAME_IP_KEYWORD: &str = "same-ip";
+    const UNRESTRICTED_KEYWORD: &str = "unrestricted";
+
+    pub fn check(
+        &self,
+        target: UdpAddr,
+        peer: IpAddr,
+    ) -> Result<(), TargetError> {
+        if target.is_multicast() {
+            return Err(TargetError::Multicast(target));
+        }
+
+        let ip = target.socket_addr().ip().to_canonical();
+        match self {
+            Self::SameIp if ip != peer.to_canonical() => {
+                Err(TargetError::NotPeer { target, peer })
+            }
+            Self::AllowList(networks)
+                if !networks.iter().any(|net| net.contains(ip)) =>
+            {
+                Err(TargetError::NotAllowed(target))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for TargetPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case(Self::SAME_IP_KEYWORD) {
+            return Ok(Self::SameIp);
+        }
+
+        if s.eq_ignore_ascii_case(Self::UNRESTRICTED_KEYWORD) {
+            return Ok(Self::Unrestricted);
+        }
+
+        let networks = s
+            .split(',')
+            .map(str::trim)
+            .filter(|net| !net.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Cidr>>>()?;
+
+        if networks.is_empty() {
+            return Err(anyhow!("No target networks provided"));
+        }
+
+        Ok(Self::AllowList(networks))
+    }
+}
+
+impl fmt::Display for TargetPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SameIp => write!(f, "{}", Self::SAME_IP_KEYWORD),
+            Self::Unrestricted => write!(f, "{}", Self::UNRESTRICTED_KEYWORD),
+            Self::AllowList(networks) => {
+                let networks: Vec<_> =
+                    networks.iter().map(ToString::to_string).collect();
+                write!(f, "{}", networks.join(","))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn target(s: &str) -> UdpAddr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[rstest]
+    #[case("10.0.0.0/8", "10.200.1.1", true)]
+    #[case("10.0.0.0/8", "11.0.0.1", false)]
+    #[case("192.168.1.7", "192.168.1.7", true)]
+    #[case("192.168.1.7", "192.168.1.8", false)]
+    #[case("0.0.0.0/0", "203.0.113.9", true)]
+    #[case("fd00::/8", "fd12::1", true)]
+    #[case("fd00::/8", "10.0.0.1", false)]
+    #[case("127.0.0.0/8", "::ffff:127.0.0.1", true)]
+    fn cidr_contains(
+        #[case] cidr: &str,
+        #[case] addr: &str,
+        #[case] expected: bool,
+    ) {
+        let cidr: Cidr = cidr.parse().unwrap();
+        assert_eq!(cidr.contains(ip(addr)), expected);
+    }
+
+    #[rstest]
+    #[case("10.0.0.0/33")]
+    #[case("10.0.0/8")]
+    #[case("10.0.0.0/x")]
+    fn rejects_invalid_cidr(#[case] input: &str) {
+        assert!(input.parse::<Cidr>().is_err());
+    }
+
+    #[rstest]
+    #[case("same-ip", TargetPolicy::SameIp)]
+    #[case("UNRESTRICTED", TargetPolicy::Unrestricted)]
+    #[case(
+        "10.0.0.0/8, 192.168.0.0/16",
+        TargetPolicy::AllowList(vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "192.168.0.0/16".parse().unwrap(),
+        ])
+    )]
+    fn parses_policy(#[case] input: &str, #[case] expected: TargetPolicy) {
+        assert_eq!(input.parse::<TargetPolicy>().unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case(" , ")]
+    #[case("everyone")]
+    fn rejects_invalid_policy(#[case] input: &str) {
+        assert!(input.parse::<TargetPolicy>().is_err());
+    }
+
+    #[test]
+    fn same_ip_allows_peer_only() {
+        let policy = TargetPolicy::SameIp;
+        let peer = ip("10.0.0.1");
+
+        assert_eq!(policy.check(target("10.0.0.1:34254"), peer), Ok(()));
+        assert_eq!(
+            policy.check(target("10.0.0.2:34254"), peer),
+            Err(TargetError::NotPeer {
+                target: target("10.0.0.2:34254"),
+                peer
+            })
+        );
+    }
+
+    #[test]
+    fn allow_list_checks_ranges() {
+        let policy: TargetPolicy = "192.168.0.0/16".parse().unwrap();
+        let peer = ip("10.0.0.1");
+
+        assert_eq!(policy.check(target("192.168.5.5:9000"), peer), Ok(()));
+        assert_eq!(
+            policy.check(target("10.0.0.1:9000"), peer),
+            Err(TargetError::NotAllowed(target("10.0.0.1:9000")))
+        );
+    }
+
+    #[rstest]
+    #[case(TargetPolicy::SameIp)]
+    #[case(TargetPolicy::Unrestricted)]
+    #[case("224.0.0.0/4".parse().unwrap())]
+    fn rejects_multicast(#[case] policy: TargetPolicy) {
+        let group = target("239.1.1.1:6000");
+        assert_eq!(
+            policy.check(group, ip("239.1.1.1")),
+            Err(TargetError::Multicast(group))
+        );
+    }
+
+    #[test]
+    fn unrestricted_allows_third_party() {
+        let policy = TargetPolicy::Unrestricted;
+        assert_eq!(
+            policy.check(target("203.0.113.1:9000"), ip("10.0.0.1")),
+            Ok(())
+        );
+    }
+}
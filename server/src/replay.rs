@@ -0,0 +1,380 @@
+//! Serving recorded quotes instead of generated ones, for reproducible
+//! tests: a client capture (`--record`) or a CSV history with
+//! `timestamp,ticker,price,volume` columns (as written by `--output csv`).
+
+use anyhow::{anyhow, Context, Result};
+use common::{CaptureReader, StockQuote, CAPTURE_MAGIC};
+use log::info;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::source::{Next, QuoteSource};
+
+/// How fast recorded quotes are replayed, relative to their timestamps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    /// `Speed(1.0)` keeps the original pacing, `Speed(10.0)` is ten times
+    /// faster.
+    Speed(f64),
+    /// Without waiting between quotes.
+    Max,
+}
+
+impl Pacing {
+    /// Speeds outside this range would stretch or squeeze the gaps between
+    /// quotes past what a [`Duration`] holds.
+    const SPEEDS: std::ops::RangeInclusive<f64> = 0.001..=1e6;
+}
+
+impl FromStr for Pacing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case("max") {
+            return Ok(Self::Max);
+        }
+        let speed: f64 = s.parse().map_err(|_| {
+            anyhow!("Expected a speed factor or 'max', got '{s}'")
+        })?;
+        if !Self::SPEEDS.contains(&speed) {
+            return Err(anyhow!(
+                "Speed must be between {} and {}, got {s}",
+                Self::SPEEDS.start(),
+                Self::SPEEDS.end()
+            ));
+        }
+        Ok(Self::Speed(speed))
+    }
+}
+
+impl fmt::Display for Pacing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Speed(speed) => write!(f, "{speed}x"),
+            Self::Max => write!(f, "max speed"),
+        }
+    }
+}
+
+/// Recorded quotes, in the order they were recorded.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    quotes: Arc<[StockQuote]>,
+    pub pacing: Pacing,
+    /// Starts over once the last quote has been sent.
+    pub repeat: bool,
+}
+
+impl Replay {
+    /// Reads a capture, recognized by its header, or else a CSV history.
+    /// Capture records that are not quotes, such as `PONG`s, are skipped.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut reader =
+            BufReader::new(File::open(path).with_context(|| {
+                format!("Failed to open {}", path.display())
+            })?);
+        let is_capture = reader.fill_buf()?.starts_with(CAPTURE_MAGIC);
+        let quotes = if is_capture {
+            read_capture(reader)
+        } else {
+            read_csv(reader)
+        }
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        if quotes.is_empty() {
+            return Err(anyhow!("No quotes in {}", path.display()));
+        }
+        Ok(Self {
+            quotes: quotes.into(),
+            pacing: Pacing::Speed(1.0),
+            repeat: false,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.quotes.len()
+    }
+
+    /// Every ticker with a recorded quote.
+    pub fn tickers(&self) -> Vec<String> {
+        let tickers: BTreeSet<_> =
+            self.quotes.iter().map(|quote| &quote.ticker).collect();
+        tickers.into_iter().cloned().collect()
+    }
+}
+
+fn read_capture(reader: impl Read) -> Result<Vec<StockQuote>> {
+    let mut quotes = Vec::new();
+    for record in CaptureReader::new(reader)? {
+        let record = record?;
+        let payload = String::from_utf8_lossy(&record.payload);
+        if let Ok(quote) = payload.parse() {
+            quotes.push(quote);
+        }
+    }
+    Ok(quotes)
+}
+
+/// Columns are found by the header, so they may come in any order and
+/// others are ignored.
+fn read_csv(reader: impl BufRead) -> Result<Vec<StockQuote>> {
+    let mut lines = reader.lines();
+    let header = lines.next().ok_or_else(|| anyhow!("Empty file"))??;
+    let columns: Vec<_> = header.split(',').map(str::trim).collect();
+    let column = |name: &str| {
+        columns
+            .iter()
+            .position(|&column| column == name)
+            .ok_or_else(|| anyhow!("No '{name}' column in CSV header"))
+    };
+    let (timestamp, ticker, price, volume) = (
+        column("timestamp")?,
+        column("ticker")?,
+        column("price")?,
+        column("volume")?,
+    );
+
+    let mut quotes = Vec::new();
+    for (number, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        let field = |index: usize| {
+            fields.get(index).copied().ok_or_else(|| {
+                anyhow!(
+                    "Line {}: expected {} fields",
+                    number + 2,
+                    columns.len()
+                )
+            })
+        };
+        let parsed = || -> Result<StockQuote> {
+            Ok(StockQuote {
+                ticker: field(ticker)?.trim_matches('"').to_string(),
+                price: field(price)?.parse()?,
+                volume: field(volume)?.parse()?,
+                timestamp: field(timestamp)?.parse()?,
+            })
+        };
+        quotes.push(parsed().with_context(|| format!("Line {}", number + 2))?);
+    }
+    Ok(quotes)
+}
+
+/// Sends a [`Replay`]'s quotes spaced like their timestamps, scaled by the
+/// pacing, and stamped with the time they are sent.
+pub struct ReplaySource {
+    replay: Replay,
+    position: usize,
+    /// When the current pass started, and the timestamp of its first quote.
+    started: Option<(Instant, u64)>,
+}
+
+impl ReplaySource {
+    /// Most quotes handed over at once, so a fast replay does not stall
+    /// shutdown or flood subscribers in one go.
+    const MAX_BATCH: usize = 1000;
+
+    pub const fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            position: 0,
+            started: None,
+        }
+    }
+
+    /// How long after the start of a pass the quote stamped `timestamp`
+    /// is due.
+    fn due(&self, first: u64, timestamp: u64) -> Duration {
+        let offset = Duration::from_millis(timestamp.saturating_sub(first));
+        match self.replay.pacing {
+            // Saturates for gaps too long to scale, e.g. across years.
+            Pacing::Speed(speed) => {
+                Duration::try_from_secs_f64(offset.as_secs_f64() / speed)
+                    .unwrap_or(Duration::MAX)
+            }
+            Pacing::Max => Duration::ZERO,
+        }
+    }
+}
+
+impl QuoteSource for ReplaySource {
+    fn next(&mut self) -> Next {
+        let quotes = &self.replay.quotes;
+        if self.position == quotes.len() {
+            if !self.replay.repeat {
+                return Next::Finished;
+            }
+            info!("Replay finished, starting over");
+            self.position = 0;
+            self.started = None;
+        }
+
+        let (start, first) = *self
+            .started
+            .get_or_insert_with(|| (Instant::now(), quotes[0].timestamp));
+        let elapsed = start.elapsed();
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| u64::try_from(now.as_millis()).unwrap_or(0));
+
+        let mut batch = Vec::new();
+        while let Some(quote) = quotes.get(self.position) {
+            let due = self.due(first, quote.timestamp);
+            if due > elapsed {
+                if batch.is_empty() {
+                    return Next::Wait(due.saturating_sub(elapsed));
+                }
+                break;
+            }
+            batch.push(StockQuote {
+                timestamp: now_ms,
+                ..quote.clone()
+            });
+            self.position += 1;
+            if batch.len() == Self::MAX_BATCH {
+                break;
+            }
+        }
+        Next::Quotes(batch)
+    }
+
+    fn tickers(&self) -> Vec<String> {
+        self.replay.tickers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{CaptureRecord, CaptureWriter};
+    use rstest::rstest;
+    use std::io::{Cursor, Write};
+    use tempfile::NamedTempFile;
+
+    const CSV: &str = "timestamp,ticker,price,volume\n\
+                       1000,AAPL,187.5,100\n\
+                       1000,TSLA,250,200\n\
+                       1100,AAPL,187.25,300\n";
+
+    fn replay(pacing: Pacing) -> Replay {
+        Replay {
+            quotes: read_csv(Cursor::new(CSV)).unwrap().into(),
+            pacing,
+            repeat: false,
+        }
+    }
+
+    fn tickers(next: &Next) -> Vec<&str> {
+        let Next::Quotes(quotes) = next else {
+            panic!("expected quotes, got {next:?}");
+        };
+        quotes.iter().map(|quote| quote.ticker.as_str()).collect()
+    }
+
+    #[rstest]
+    #[case("1", Pacing::Speed(1.0))]
+    #[case("2.5", Pacing::Speed(2.5))]
+    #[case("MAX", Pacing::Max)]
+    fn parses_pacing(#[case] input: &str, #[case] expected: Pacing) {
+        assert_eq!(input.parse::<Pacing>().unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("0")]
+    #[case("-1")]
+    #[case("1e-300")]
+    #[case("1e7")]
+    #[case("inf")]
+    #[case("NaN")]
+    #[case("fast")]
+    fn rejects_invalid_pacing(#[case] input: &str) {
+        assert!(input.parse::<Pacing>().is_err());
+    }
+
+    #[test]
+    fn reads_csv_by_header() {
+        let csv = "ticker,volume,timestamp,price,extra\nV,5,42,1.5,x\n";
+
+        let quotes = read_csv(Cursor::new(csv)).unwrap();
+
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(quotes[0].ticker, "V");
+        assert_eq!((quotes[0].volume, quotes[0].timestamp), (5, 42));
+    }
+
+    #[rstest]
+    #[case("ticker,price,volume\nV,1,1\n")]
+    #[case("timestamp,ticker,price,volume\n1,V,cheap,1\n")]
+    #[case("timestamp,ticker,price,volume\n1,V\n")]
+    fn rejects_invalid_csv(#[case] csv: &str) {
+        assert!(read_csv(Cursor::new(csv)).is_err());
+    }
+
+    #[test]
+    fn loads_quotes_from_capture() {
+        let source = "127.0.0.1:5000".parse().unwrap();
+        let mut file = NamedTempFile::new().unwrap();
+        let mut writer = CaptureWriter::new(file.as_file_mut()).unwrap();
+        for payload in [
+            r#"{"ticker":"AAPL","price":"1","volume":1,"timestamp":1}"#,
+            "PONG 1 100",
+        ] {
+            let record = CaptureRecord::now(source, payload.as_bytes());
+            writer.write(&record).unwrap();
+        }
+        file.flush().unwrap();
+
+        let replay = Replay::load(file.path()).unwrap();
+
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay.tickers(), ["AAPL"]);
+    }
+
+    #[test]
+    fn keeps_original_spacing() {
+        let mut source = ReplaySource::new(replay(Pacing::Speed(1.0)));
+
+        assert_eq!(tickers(&source.next()), ["AAPL", "TSLA"]);
+        let Next::Wait(wait) = source.next() else {
+            panic!("expected to wait");
+        };
+        assert!(wait > Duration::from_millis(50));
+        assert!(wait <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn saturates_long_gaps() {
+        let source = ReplaySource::new(replay(Pacing::Speed(0.001)));
+
+        assert_eq!(source.due(0, u64::MAX), Duration::MAX);
+    }
+
+    #[test]
+    fn sends_everything_at_max_speed_then_finishes() {
+        let mut source = ReplaySource::new(replay(Pacing::Max));
+
+        assert_eq!(tickers(&source.next()), ["AAPL", "TSLA", "AAPL"]);
+        assert_eq!(source.next(), Next::Finished);
+    }
+
+    #[test]
+    fn repeats() {
+        let mut source = ReplaySource::new(Replay {
+            repeat: true,
+            ..replay(Pacing::Max)
+        });
+
+        source.next();
+
+        assert_eq!(tickers(&source.next()), ["AAPL", "TSLA", "AAPL"]);
+    }
+}
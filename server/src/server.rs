@@ -1,70 +1,122 @@
-use anyhow::Result;
-use crossbeam::channel::{unbounded, Sender};
+use anyhow::{anyhow, Result};
 use log::{debug, error, info, warn};
 use parking_lot::Mutex;
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use crate::client_handler::{ClientManager, ClientStreamer};
-use crate::generator::QuoteGenerator;
-use common::{Command, Response, StockQuote, UdpAddr};
-
-#[derive(Debug, Clone)]
-pub struct ServerConfig {
-    pub tcp_port: u16,
-    pub udp_ping_port: u16,
-    pub ping_timeout: Duration,
-    pub quote_interval: Duration,
-    pub cleanup_interval: Duration,
-}
-
-impl Default for ServerConfig {
-    fn default() -> Self {
-        Self {
-            tcp_port: 5000,
-            udp_ping_port: 5001,
-            ping_timeout: Duration::from_secs(5),
-            quote_interval: Duration::from_millis(100),
-            cleanup_interval: Duration::from_secs(1),
-        }
-    }
-}
+use crate::admin::{Admin, AdminServer};
+use crate::auth::Credentials;
+use crate::client_handler::{ClientManager, ClientStreamer, SubscriberId};
+use crate::config::{MulticastGroup, ServerConfig};
+use crate::fanout::FanOut;
+use crate::generator::GeneratorControl;
+use crate::health::{Health, Heartbeat};
+use crate::http::HttpServer;
+use crate::http_api::QuoteApi;
+use crate::limits::{ConnectionLimiter, LimitError, Limits, RateLimiter};
+use crate::metrics::{MetricsApi, METRICS};
+use crate::notify::{Notifier, Subscription};
+use crate::policy::TargetPolicy;
+use crate::replay::ReplaySource;
+use crate::session::{Registration, SessionRegistry};
+use crate::shutdown::Workers;
+use crate::source::{self, GeneratorSource, QuoteSource};
+use crate::tls;
+use crate::ws_gateway::WsGateway;
+use common::{
+    Command, DatagramKey, Notification, Pong, Response, Sealer, SessionId,
+    StreamTarget, Tickers, UdpAddr,
+};
 
 const PING_BUFFER_SIZE: usize = 1024;
 const UDP_READ_TIMEOUT_SECS: u64 = 1;
-
-type ClientChannels = Arc<Mutex<HashMap<SocketAddr, Sender<StockQuote>>>>;
-type StopChannels = Arc<Mutex<HashMap<SocketAddr, Sender<()>>>>;
+/// How long an idle control connection blocks before checking for
+/// shutdown.
+const CONTROL_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long a TLS client has to complete the handshake before the
+/// connection, and its slot in the per-IP limit, is given up.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What control connection and ping listener threads need from the
+/// server.
+#[derive(Clone)]
+struct ControlContext {
+    client_manager: Arc<ClientManager>,
+    fan_out: FanOut,
+    sessions: SessionRegistry,
+    notifier: Notifier,
+    workers: Workers,
+    running: Arc<AtomicBool>,
+    credentials: Option<Credentials>,
+    target_policy: TargetPolicy,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    limits: Limits,
+}
 
 pub struct Server {
     config: ServerConfig,
     client_manager: Arc<ClientManager>,
-    client_channels: ClientChannels,
-    stop_channels: StopChannels,
+    fan_out: FanOut,
+    sessions: SessionRegistry,
+    connections: ConnectionLimiter,
+    generator: GeneratorControl,
+    /// Taken by the thread publishing its quotes.
+    source: Mutex<Option<Box<dyn QuoteSource>>>,
+    /// The source's tickers.
+    universe: Vec<String>,
+    notifier: Notifier,
+    health: Health,
+    workers: Workers,
     running: Arc<AtomicBool>,
 }
 
 impl Server {
-    const ALL_TICKERS: [&str; 10] = [
-        "AAPL", "GOOGL", "TSLA", "MSFT", "AMZN", "NVDA", "META", "JPM", "JNJ",
-        "V",
-    ];
     pub fn new(config: ServerConfig) -> Self {
+        let generator = GeneratorControl::new(config.quote_interval);
+        let source = Self::quote_source(&config, &generator);
+        Self::with_parts(config, generator, source)
+    }
+
+    /// Publishes what `source` has instead of random or replayed quotes.
+    /// Admin halts still apply to it.
+    #[allow(dead_code)]
+    pub fn with_source(
+        config: ServerConfig,
+        source: Box<dyn QuoteSource>,
+    ) -> Self {
+        let generator = GeneratorControl::new(config.quote_interval);
+        Self::with_parts(config, generator, source)
+    }
+
+    fn with_parts(
+        config: ServerConfig,
+        generator: GeneratorControl,
+        source: Box<dyn QuoteSource>,
+    ) -> Self {
         let client_manager = Arc::new(ClientManager::new(config.ping_timeout));
-        let client_channels = Arc::new(Mutex::new(HashMap::new()));
-        let stop_channels = Arc::new(Mutex::new(HashMap::new()));
+        let fan_out = FanOut::new();
+        let sessions = SessionRegistry::new(config.ping_timeout);
+        let connections =
+            ConnectionLimiter::new(config.limits.connections_per_ip);
+        let universe = source.tickers();
         let running = Arc::new(AtomicBool::new(true));
 
         Self {
             config,
             client_manager,
-            client_channels,
-            stop_channels,
+            fan_out,
+            sessions,
+            connections,
+            generator,
+            source: Mutex::new(Some(source)),
+            universe,
+            notifier: Notifier::new(),
+            health: Health::new(),
+            workers: Workers::new(),
             running,
         }
     }
@@ -76,82 +128,150 @@ impl Server {
     pub fn run(&self) -> Result<()> {
         info!("Starting Quote Server...");
 
+        let ping_socket =
+            Arc::new(Self::bind_ping_socket(self.config.udp_ping_port)?);
+        // Registered first, so readiness waits for the accept loop.
+        let control_heartbeat = self.health.register("control");
+
         self.spawn_quote_generator();
-        self.spawn_ping_listener();
+        self.spawn_multicast_publishers();
+        self.spawn_ping_listener(ping_socket);
         self.spawn_cleanup_thread();
-        self.run_tcp_server()
+        self.spawn_ws_gateway()?;
+        self.spawn_http_server()?;
+        self.spawn_metrics_server()?;
+        self.spawn_admin_server()?;
+
+        let served = self.run_tcp_server(&control_heartbeat);
+        self.running.store(false, Ordering::SeqCst);
+        let stopped = self.shut_down();
+        served.and(stopped)
+    }
+
+    /// Runs once the control port has stopped accepting: ends every stream
+    /// with `BYE` and waits for every thread to finish.
+    fn shut_down(&self) -> Result<()> {
+        info!("Shutting down...");
+        let streams = self.fan_out.stop_all();
+        info!("Sent BYE to {streams} streams");
+
+        let report = self.workers.join(self.config.shutdown_timeout);
+        if !report.is_clean() {
+            return Err(anyhow!("Unclean shutdown: {report}"));
+        }
+        info!("Shutdown complete: {report}");
+        Ok(())
     }
 
     fn is_running(&self) -> bool {
         self.running.load(Ordering::SeqCst)
     }
 
+    /// The replay when configured, or else random quotes.
+    fn quote_source(
+        config: &ServerConfig,
+        control: &GeneratorControl,
+    ) -> Box<dyn QuoteSource> {
+        match &config.replay {
+            Some(replay) => {
+                info!("Replaying {} quotes at {}", replay.len(), replay.pacing);
+                Box::new(ReplaySource::new(replay.clone()))
+            }
+            None => Box::new(GeneratorSource::new(control.clone())),
+        }
+    }
+
     fn spawn_quote_generator(&self) {
-        let channels = self.client_channels.clone();
-        let interval = self.config.quote_interval;
+        let Some(mut source) = self.source.lock().take() else {
+            return;
+        };
+        let fan_out = self.fan_out.clone();
+        let control = self.generator.clone();
+        let heartbeat = self.health.register("generator");
         let running = self.running.clone();
 
-        thread::spawn(move || {
-            Self::quote_generator_loop(&channels, interval, &running);
+        self.workers.spawn("generator".to_string(), move || {
+            source::publish(
+                source.as_mut(),
+                &fan_out,
+                &control,
+                &heartbeat,
+                &running,
+            );
         });
     }
 
-    fn quote_generator_loop(
-        client_channels: &ClientChannels,
-        interval: Duration,
-        running: &Arc<AtomicBool>,
-    ) {
-        let mut generator = QuoteGenerator::new();
-
-        while running.load(Ordering::SeqCst) {
-            thread::sleep(interval);
-
-            for ticker in &Self::ALL_TICKERS {
-                if let Ok(quote) = generator.generate(ticker) {
-                    let channels = client_channels.lock();
-                    for sender in channels.values() {
-                        let _ = sender.send(quote.clone());
-                    }
+    fn spawn_multicast_publishers(&self) {
+        for group in &self.config.multicast_groups {
+            let MulticastGroup { addr, tickers } = group.clone();
+            let ttl = self.config.multicast_ttl;
+            let (rx, stop_rx) =
+                self.fan_out.subscribe(SubscriberId::Multicast(addr));
+
+            self.workers.spawn(format!("multicast {addr}"), move || {
+                match ClientStreamer::multicast(addr, tickers, ttl, rx, stop_rx)
+                {
+                    Ok(streamer) => streamer.run(),
+                    Err(e) => error!("Multicast publisher error: {e}"),
                 }
-            }
+            });
         }
-        info!("Quote generator stopped");
-    }
-
-    fn spawn_ping_listener(&self) {
-        let manager = self.client_manager.clone();
-        let port = self.config.udp_ping_port;
-        let running = self.running.clone();
-
-        thread::spawn(move || {
-            if let Err(e) = Self::ping_listener_loop(&manager, port, &running) {
-                error!("Ping listener error: {e}");
-            }
-        });
     }
 
-    fn ping_listener_loop(
-        client_manager: &Arc<ClientManager>,
-        port: u16,
-        running: &Arc<AtomicBool>,
-    ) -> Result<()> {
+    fn bind_ping_socket(port: u16) -> Result<UdpSocket> {
         let socket = UdpSocket::bind(format!("0.0.0.0:{port}"))?;
         socket.set_read_timeout(Some(Duration::from_secs(
             UDP_READ_TIMEOUT_SECS,
         )))?;
         info!("UDP ping listener on port {port}");
+        Ok(socket)
+    }
+
+    fn spawn_ping_listener(&self, socket: Arc<UdpSocket>) {
+        let context = self.control_context();
+        let heartbeat = self.health.register("ping_listener");
 
+        self.workers.spawn("ping_listener".to_string(), move || {
+            Self::ping_listener_loop(&socket, &context, &heartbeat);
+        });
+    }
+
+    fn ping_listener_loop(
+        socket: &Arc<UdpSocket>,
+        context: &ControlContext,
+        heartbeat: &Heartbeat,
+    ) {
         let mut buf = [0_u8; PING_BUFFER_SIZE];
-        while running.load(Ordering::SeqCst) {
+        while context.running.load(Ordering::SeqCst) {
+            heartbeat.beat();
             match socket.recv_from(&mut buf) {
                 Ok((len, addr)) => {
                     let msg = String::from_utf8_lossy(&buf[..len]);
-                    if msg.trim().eq_ignore_ascii_case("PING") {
-                        if client_manager.update_ping_by_source(&addr) {
-                            debug!("Ping from {addr}");
+                    let probe = match msg.trim().parse::<Command>() {
+                        Ok(Command::Ping(probe)) => {
+                            METRICS.ping_received("ping");
+                            if context
+                                .client_manager
+                                .update_ping_by_source(&addr)
+                            {
+                                debug!("Ping from {addr}");
+                            }
+                            probe
                         }
-                        let _ = socket.send_to(b"PONG", addr);
-                    }
+                        Ok(Command::Register(session_id, probe)) => {
+                            METRICS.ping_received("register");
+                            Self::handle_register(
+                                session_id, addr, socket, context,
+                            );
+                            probe
+                        }
+                        _ => {
+                            debug!("Ignoring datagram from {addr}");
+                            continue;
+                        }
+                    };
+                    let pong = Pong(probe).to_string();
+                    let _ = socket.send_to(pong.as_bytes(), addr);
                 }
                 Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
                 Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
@@ -159,72 +279,243 @@ impl Server {
             }
         }
         info!("Ping listener stopped");
-        Ok(())
+    }
+
+    /// Binds a `STREAM nat` session to the datagram's source address, if
+    /// the target policy allows it, and streams there from the ping
+    /// socket, the only server port the client's NAT is known to accept
+    /// traffic from.
+    fn handle_register(
+        session_id: SessionId,
+        source: SocketAddr,
+        socket: &Arc<UdpSocket>,
+        context: &ControlContext,
+    ) {
+        let sessions = &context.sessions;
+        let registration =
+            sessions.register(session_id, source, &context.target_policy);
+        let (tickers, source_ip) = match registration {
+            Registration::Started { tickers, source_ip } => {
+                (tickers, source_ip)
+            }
+            Registration::Moved {
+                previous,
+                tickers,
+                source_ip,
+            } => {
+                info!("Session {session_id} moved from {previous} to {source}");
+                context.client_manager.remove(&previous);
+                context.fan_out.unsubscribe(&SubscriberId::Udp(previous));
+                (tickers, source_ip)
+            }
+            Registration::Refreshed(target) => {
+                context.client_manager.update_ping(&target);
+                return;
+            }
+            Registration::Rejected(e) => {
+                warn!("Rejected REGISTER for session {session_id}: {e}");
+                return;
+            }
+            Registration::Unknown => return,
+        };
+
+        let udp_addr = UdpAddr::from(source);
+        info!("Session {session_id} registered from {udp_addr}");
+        context.notifier.bind_session(session_id, udp_addr);
+        Self::start_udp_stream(
+            udp_addr,
+            tickers,
+            source_ip,
+            Some(socket.clone()),
+            sessions.sealer(session_id),
+            context,
+        );
     }
 
     fn spawn_cleanup_thread(&self) {
-        let manager = self.client_manager.clone();
-        let channels = self.client_channels.clone();
-        let stop_channels = self.stop_channels.clone();
+        let context = self.control_context();
         let interval = self.config.cleanup_interval;
-        let running = self.running.clone();
+        let heartbeat = self.health.register("cleanup");
 
-        thread::spawn(move || {
-            Self::cleanup_loop(
-                &manager,
-                &channels,
-                &stop_channels,
-                interval,
-                &running,
-            );
+        self.workers.spawn("cleanup".to_string(), move || {
+            Self::cleanup_loop(&context, interval, &heartbeat);
         });
     }
 
     fn cleanup_loop(
-        client_manager: &Arc<ClientManager>,
-        client_channels: &ClientChannels,
-        stop_channels: &StopChannels,
+        context: &ControlContext,
         interval: Duration,
-        running: &Arc<AtomicBool>,
+        heartbeat: &Heartbeat,
     ) {
-        while running.load(Ordering::SeqCst) {
+        let ControlContext {
+            client_manager,
+            fan_out,
+            sessions,
+            notifier,
+            ..
+        } = context;
+
+        while context.running.load(Ordering::SeqCst) {
+            heartbeat.beat();
             thread::sleep(interval);
 
+            for (target, left) in client_manager.expiring() {
+                let secs = left.as_secs();
+                let notice = Notification::Expiring { target, secs };
+                if notifier.notify(&target, notice) {
+                    debug!("Warned {target} it expires in {secs}s");
+                }
+            }
+
             let removed = client_manager.remove_expired();
+            METRICS.clients_expired(removed.len());
             if !removed.is_empty() {
                 for addr in &removed {
-                    client_channels.lock().remove(&addr.socket_addr());
-                    let stop_tx =
-                        stop_channels.lock().remove(&addr.socket_addr());
-                    if let Some(tx) = stop_tx {
-                        let _ = tx.send(());
-                    }
+                    fan_out.unsubscribe(&SubscriberId::Udp(*addr));
+                    sessions.remove_target(addr);
                     info!("Removed inactive client: {addr}");
                 }
             }
+
+            for session_id in sessions.remove_unregistered() {
+                info!("Dropped unregistered session {session_id}");
+            }
         }
         info!("Cleanup thread stopped");
     }
 
-    fn run_tcp_server(&self) -> Result<()> {
+    fn spawn_ws_gateway(&self) -> Result<()> {
+        let Some(port) = self.config.ws_port else {
+            return Ok(());
+        };
+
+        let gateway = WsGateway::bind(
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            self.fan_out.clone(),
+            self.running.clone(),
+        )?
+        .with_heartbeat(self.health.register("ws_gateway"))
+        .with_workers(self.workers.clone());
+
+        self.workers
+            .spawn("ws_gateway".to_string(), move || gateway.run());
+        Ok(())
+    }
+
+    fn spawn_http_server(&self) -> Result<()> {
+        let Some(port) = self.config.http_port else {
+            return Ok(());
+        };
+
+        let api = QuoteApi::new(self.fan_out.clone(), self.universe.clone());
+        let server = HttpServer::bind(
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            api,
+            self.running.clone(),
+        )?
+        .with_heartbeat(self.health.register("http_api"))
+        .with_workers(self.workers.clone());
+
+        self.workers
+            .spawn("http_api".to_string(), move || server.run());
+        Ok(())
+    }
+
+    fn spawn_metrics_server(&self) -> Result<()> {
+        let Some(port) = self.config.metrics_port else {
+            return Ok(());
+        };
+
+        let server = HttpServer::bind(
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            MetricsApi::new(self.client_manager.clone(), self.health.clone()),
+            self.running.clone(),
+        )?
+        .with_workers(self.workers.clone());
+        info!("Prometheus metrics on port {port}");
+
+        self.workers
+            .spawn("metrics".to_string(), move || server.run());
+        Ok(())
+    }
+
+    fn spawn_admin_server(&self) -> Result<()> {
+        let Some(port) = self.config.admin_port else {
+            return Ok(());
+        };
+
+        let admin = Admin::new(
+            self.client_manager.clone(),
+            self.fan_out.clone(),
+            self.sessions.clone(),
+            self.generator.clone(),
+            self.notifier.clone(),
+            self.universe.clone(),
+        );
+        let server = AdminServer::bind(
+            SocketAddr::from(([127, 0, 0, 1], port)),
+            admin,
+            self.running.clone(),
+        )?
+        .with_heartbeat(self.health.register("admin"))
+        .with_workers(self.workers.clone());
+
+        self.workers
+            .spawn("admin".to_string(), move || server.run());
+        Ok(())
+    }
+
+    fn control_context(&self) -> ControlContext {
+        ControlContext {
+            client_manager: self.client_manager.clone(),
+            fan_out: self.fan_out.clone(),
+            sessions: self.sessions.clone(),
+            notifier: self.notifier.clone(),
+            workers: self.workers.clone(),
+            running: self.running.clone(),
+            credentials: self.config.credentials.clone(),
+            target_policy: self.config.target_policy.clone(),
+            tls: self.config.tls.clone(),
+            limits: self.config.limits,
+        }
+    }
+
+    fn run_tcp_server(&self, heartbeat: &Heartbeat) -> Result<()> {
         let listener =
             TcpListener::bind(format!("0.0.0.0:{}", self.config.tcp_port))?;
         listener.set_nonblocking(true)?;
         info!("TCP server listening on port {}", self.config.tcp_port);
+        if let Some(credentials) = &self.config.credentials {
+            info!("AUTH required, {} credentials loaded", credentials.len());
+        }
+        if self.config.tls.is_some() {
+            info!("TLS enabled on the TCP control port");
+        }
 
+        let context = self.control_context();
         while self.is_running() {
+            heartbeat.beat();
             match listener.accept() {
-                Ok((stream, _)) => {
-                    let manager = self.client_manager.clone();
-                    let channels = self.client_channels.clone();
-                    let stop_channels = self.stop_channels.clone();
-                    thread::spawn(move || {
-                        if let Err(e) = Self::handle_tcp_client(
-                            stream,
-                            &manager,
-                            &channels,
-                            &stop_channels,
-                        ) {
+                Ok((mut stream, peer_addr)) => {
+                    let permit = match self.connections.acquire(peer_addr.ip())
+                    {
+                        Ok(permit) => permit,
+                        Err(e) => {
+                            warn!("Refused connection from {peer_addr}: {e}");
+                            // A TLS client could not read a plain reply.
+                            if self.config.tls.is_none() {
+                                let error = Response::Error(e.to_string());
+                                let _ = writeln!(stream, "{error}");
+                            }
+                            continue;
+                        }
+                    };
+                    let context = context.clone();
+                    let name = format!("control {peer_addr}");
+                    self.workers.spawn(name, move || {
+                        let _permit = permit;
+                        if let Err(e) = Self::serve_tcp_client(stream, &context)
+                        {
                             error!("Client handler error: {e}");
                         }
                     });
@@ -240,68 +531,342 @@ impl Server {
         Ok(())
     }
 
-    fn handle_tcp_client(
-        mut stream: TcpStream,
-        client_manager: &Arc<ClientManager>,
-        client_channels: &ClientChannels,
-        stop_channels: &StopChannels,
+    fn serve_tcp_client(
+        stream: TcpStream,
+        context: &ControlContext,
     ) -> Result<()> {
         let peer_addr = stream.peer_addr()?;
         info!("New TCP connection from: {peer_addr}");
 
-        let reader = BufReader::new(stream.try_clone()?);
-        for line in reader.lines() {
-            let line = line?;
-            let response = match line.parse::<Command>() {
-                Ok(Command::Stream { udp_addr, tickers }) => {
-                    Self::handle_stream_command(
-                        udp_addr,
-                        tickers,
+        let Some(config) = &context.tls else {
+            stream.set_read_timeout(Some(CONTROL_POLL_INTERVAL))?;
+            return Self::handle_tcp_client(stream, peer_addr, false, context);
+        };
+
+        let (stream, client_verified) =
+            tls::accept(config.clone(), stream, TLS_HANDSHAKE_TIMEOUT)?;
+        // Set after the handshake, which may take longer than a poll.
+        stream.sock.set_read_timeout(Some(CONTROL_POLL_INTERVAL))?;
+        if client_verified {
+            info!("{peer_addr} presented a verified client certificate");
+        }
+        Self::handle_tcp_client(stream, peer_addr, client_verified, context)
+    }
+
+    /// Serves commands on a plain or TLS control connection. A verified
+    /// client certificate stands in for `AUTH`. Reads time out every
+    /// [`CONTROL_POLL_INTERVAL`], so an idle connection notices shutdown
+    /// and says `BYE`.
+    fn handle_tcp_client(
+        stream: impl Read + Write + Send + 'static,
+        peer_addr: SocketAddr,
+        client_verified: bool,
+        context: &ControlContext,
+    ) -> Result<()> {
+        let credentials = context.credentials.as_ref();
+        let mut authenticated = client_verified || credentials.is_none();
+        // Set by SECURE and consumed by the next UDP or NAT stream.
+        let mut sealer: Option<Sealer> = None;
+        let mut rate_limiter =
+            RateLimiter::new(context.limits.commands_per_sec);
+        let subscription = context.notifier.connect(peer_addr);
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+
+        while Self::next_line(&mut reader, &mut line, &subscription, context)? {
+            let command =
+                String::from_utf8_lossy(&line).trim_end().parse::<Command>();
+            if let Err(e) = Self::check_limits(
+                command.as_ref().ok(),
+                &mut rate_limiter,
+                peer_addr,
+                context,
+            ) {
+                warn!("Rejected command from {peer_addr}: {e}");
+                Self::reply(reader.get_mut(), &Response::Error(e.to_string()))?;
+                line.clear();
+                continue;
+            }
+
+            let response = match command {
+                Ok(Command::Auth(token)) => {
+                    let response =
+                        Self::handle_auth(&token, credentials, peer_addr);
+                    authenticated |= response == Response::Ok;
+                    response
+                }
+                Ok(Command::Stream { .. }) if !authenticated => {
+                    warn!("Rejected unauthenticated STREAM from {peer_addr}");
+                    Response::Error("Authentication required".to_string())
+                }
+                Ok(Command::Stream {
+                    target: StreamTarget::Udp(udp_addr),
+                    tickers,
+                }) => Self::handle_stream_command(
+                    udp_addr,
+                    tickers,
+                    peer_addr,
+                    sealer.take(),
+                    context,
+                ),
+                Ok(Command::Stream {
+                    target: StreamTarget::Tcp,
+                    tickers,
+                }) => {
+                    Self::reply(reader.get_mut(), &Response::Ok)?;
+                    // The connection carries frames from here on.
+                    drop(subscription);
+                    Self::stream_over_tcp(
+                        reader.into_inner(),
                         peer_addr,
-                        client_manager,
-                        client_channels,
-                        stop_channels,
-                    )
+                        tickers,
+                        &context.fan_out,
+                    );
+                    info!("TCP stream closed: {peer_addr}");
+                    return Ok(());
                 }
-                Ok(Command::Ping) => Response::Ok,
+                Ok(Command::Stream {
+                    target: StreamTarget::Nat,
+                    tickers,
+                }) => {
+                    info!("Awaiting REGISTER for tickers: {tickers}");
+                    let id = context.sessions.open(
+                        tickers,
+                        peer_addr.ip(),
+                        sealer.take(),
+                    );
+                    context.notifier.watch_session(id, peer_addr);
+                    Response::Session(id)
+                }
+                Ok(Command::Secure(protection)) => {
+                    debug!("{peer_addr} asked for {protection} datagrams");
+                    let key = DatagramKey::generate();
+                    sealer = Some(Sealer::new(key.clone(), protection));
+                    Response::Key(key)
+                }
+                Ok(Command::Ping(_)) => Response::Ok,
+                Ok(Command::Register(..)) => Response::Error(
+                    "REGISTER must be sent to the UDP ping port".to_string(),
+                ),
                 Err(e) => {
                     warn!("Command parse error: {e}");
                     Response::Error(e.to_string())
                 }
             };
 
-            writeln!(stream, "{response}")?;
+            Self::reply(reader.get_mut(), &response)?;
+            line.clear();
+        }
+
+        if context.running.load(Ordering::SeqCst) {
+            info!("TCP connection closed: {peer_addr}");
+        } else {
+            Self::notify(reader.get_mut(), &Notification::Shutdown)?;
+            Self::reply(reader.get_mut(), &Response::Bye)?;
+            info!("Closed control connection {peer_addr} for shutdown");
+        }
+        Ok(())
+    }
+
+    /// Reads the next command line into `line`, writing out pending
+    /// notifications while waiting. Returns false at the end of the stream,
+    /// or once shutdown has begun.
+    fn next_line(
+        reader: &mut BufReader<impl Read + Write>,
+        line: &mut Vec<u8>,
+        subscription: &Subscription,
+        context: &ControlContext,
+    ) -> Result<bool> {
+        while context.running.load(Ordering::SeqCst) {
+            for notification in subscription.pending() {
+                Self::notify(reader.get_mut(), &notification)?;
+            }
+            match reader.read_until(b'\n', line) {
+                Ok(read) => return Ok(read > 0),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    ) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(false)
+    }
+
+    fn notify(
+        stream: &mut impl Write,
+        notification: &Notification,
+    ) -> Result<()> {
+        writeln!(stream, "{notification}")?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    fn reply(stream: &mut impl Write, response: &Response) -> Result<()> {
+        writeln!(stream, "{response}")?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Applies the command rate and, for `STREAM`, the ticker and stream
+    /// caps. A `STREAM tcp` is bounded by the connection cap instead, and
+    /// re-streaming to an existing UDP target replaces that stream.
+    fn check_limits(
+        command: Option<&Command>,
+        rate_limiter: &mut RateLimiter,
+        peer_addr: SocketAddr,
+        context: &ControlContext,
+    ) -> Result<(), LimitError> {
+        rate_limiter.check()?;
+
+        let Some(Command::Stream { target, tickers }) = command else {
+            return Ok(());
+        };
+        context.limits.check_tickers(tickers)?;
+
+        let replaces = match target {
+            StreamTarget::Udp(addr) => context.client_manager.contains(addr),
+            StreamTarget::Tcp => true,
+            StreamTarget::Nat => false,
+        };
+        let ip = peer_addr.ip();
+        let streams = context.client_manager.count_by_source(ip)
+            + context.sessions.count_pending(ip);
+        let limit = context.limits.streams_per_client;
+        if !replaces && streams >= limit {
+            return Err(LimitError::Streams { limit });
         }
 
-        info!("TCP connection closed: {peer_addr}");
         Ok(())
     }
 
+    fn handle_auth(
+        token: &str,
+        credentials: Option<&Credentials>,
+        peer_addr: SocketAddr,
+    ) -> Response {
+        let Some(credentials) = credentials else {
+            return Response::Ok;
+        };
+
+        credentials.authenticate(token).map_or_else(
+            || {
+                warn!("Invalid AUTH token from {peer_addr}");
+                Response::Error("Invalid token".to_string())
+            },
+            |name| {
+                info!("{peer_addr} authenticated as {name}");
+                Response::Ok
+            },
+        )
+    }
+
     fn handle_stream_command(
         udp_addr: UdpAddr,
-        tickers: common::Tickers,
+        tickers: Tickers,
         peer_addr: SocketAddr,
-        client_manager: &Arc<ClientManager>,
-        client_channels: &ClientChannels,
-        stop_channels: &StopChannels,
+        sealer: Option<Sealer>,
+        context: &ControlContext,
     ) -> Response {
+        if let Err(e) = context.target_policy.check(udp_addr, peer_addr.ip()) {
+            warn!("Rejected stream target from {peer_addr}: {e}");
+            return Response::Error(e.to_string());
+        }
+
         info!("Starting stream to {udp_addr} for tickers: {tickers}");
 
-        client_manager.register(udp_addr, &tickers, peer_addr.ip());
+        Self::start_udp_stream(
+            udp_addr,
+            tickers,
+            peer_addr.ip(),
+            None,
+            sealer,
+            context,
+        );
+        context.notifier.watch(udp_addr, peer_addr);
 
-        let (tx, rx) = unbounded();
-        let (stop_tx, stop_rx) = unbounded();
+        Response::Ok
+    }
 
-        client_channels.lock().insert(udp_addr.socket_addr(), tx);
-        stop_channels.lock().insert(udp_addr.socket_addr(), stop_tx);
+    /// Registers a UDP client and spawns its streamer. Without a `socket`
+    /// the streamer binds its own.
+    fn start_udp_stream(
+        udp_addr: UdpAddr,
+        tickers: Tickers,
+        source_ip: IpAddr,
+        socket: Option<Arc<UdpSocket>>,
+        sealer: Option<Sealer>,
+        context: &ControlContext,
+    ) {
+        context
+            .client_manager
+            .register(udp_addr, &tickers, source_ip);
+
+        let (rx, stop_rx) =
+            context.fan_out.subscribe(SubscriberId::Udp(udp_addr));
+
+        context
+            .workers
+            .spawn(format!("stream {udp_addr}"), move || {
+                let streamer = match socket {
+                    Some(socket) => Ok(ClientStreamer::with_socket(
+                        socket, udp_addr, tickers, sealer, rx, stop_rx,
+                    )),
+                    None => ClientStreamer::new(
+                        udp_addr, tickers, sealer, rx, stop_rx,
+                    ),
+                };
+
+                match streamer {
+                    Ok(streamer) => streamer.run(),
+                    Err(e) => error!("Stream handler error: {e}"),
+                }
+            });
+    }
 
-        thread::spawn(move || {
-            match ClientStreamer::new(udp_addr, tickers, rx, stop_rx) {
-                Ok(streamer) => streamer.run(),
-                Err(e) => error!("Stream handler error: {e}"),
-            }
-        });
+    /// Turns the control connection into a framed quote stream. Runs on the
+    /// connection's own thread until the peer goes away or is stopped.
+    fn stream_over_tcp(
+        stream: impl Write + Send + 'static,
+        peer_addr: SocketAddr,
+        tickers: Tickers,
+        fan_out: &FanOut,
+    ) {
+        let (rx, stop_rx) = fan_out.subscribe(SubscriberId::Tcp(peer_addr));
 
-        Response::Ok
+        let streamer =
+            ClientStreamer::over_tcp(stream, peer_addr, tickers, rx, stop_rx);
+        let id = streamer.id();
+        streamer.run();
+
+        fan_out.unsubscribe(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::Next;
+
+    struct Fixed;
+
+    impl QuoteSource for Fixed {
+        fn next(&mut self) -> Next {
+            Next::Finished
+        }
+
+        fn tickers(&self) -> Vec<String> {
+            vec!["XYZ".to_string()]
+        }
+    }
+
+    #[test]
+    fn serves_injected_source() {
+        let server =
+            Server::with_source(ServerConfig::default(), Box::new(Fixed));
+
+        assert_eq!(server.universe, ["XYZ"]);
+        assert!(server.source.lock().is_some());
     }
 }
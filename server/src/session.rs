@@ -0,0 +1,380 @@
+use log::{debug, info};
+use parking_lot::Mutex;
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use common::{Sealer, SessionId, Tickers, UdpAddr};
+
+use crate::policy::{TargetError, TargetPolicy};
+
+/// Outcome of a `REGISTER` datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Registration {
+    /// First registration, streaming should start towards the sender.
+    Started {
+        tickers: Tickers,
+        source_ip: IpAddr,
+    },
+    /// The sender's address changed (e.g. a new NAT mapping); streaming
+    /// should move from `previous` to the sender.
+    Moved {
+        previous: UdpAddr,
+        tickers: Tickers,
+        source_ip: IpAddr,
+    },
+    /// Already streaming to the sender, acts as a keep-alive.
+    Refreshed(UdpAddr),
+    /// The target policy does not allow streaming to the sender; the
+    /// session keeps its current target.
+    Rejected(TargetError),
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+struct NatSession {
+    tickers: Tickers,
+    source_ip: IpAddr,
+    target: Option<UdpAddr>,
+    sealer: Option<Sealer>,
+    opened: Instant,
+}
+
+/// Tracks `STREAM nat` sessions between the TCP command and the UDP
+/// `REGISTER` that reveals the address to stream to.
+#[derive(Clone)]
+pub struct SessionRegistry {
+    sessions: Arc<Mutex<HashMap<SessionId, NatSession>>>,
+    register_timeout: Duration,
+}
+
+impl SessionRegistry {
+    pub fn new(register_timeout: Duration) -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            register_timeout,
+        }
+    }
+
+    pub fn open(
+        &self,
+        tickers: Tickers,
+        source_ip: IpAddr,
+        sealer: Option<Sealer>,
+    ) -> SessionId {
+        let session = NatSession {
+            tickers,
+            source_ip,
+            target: None,
+            sealer,
+            opened: Instant::now(),
+        };
+
+        let mut sessions = self.sessions.lock();
+        let mut rng = rand::thread_rng();
+        let id = loop {
+            let id = SessionId::from(rng.gen::<u64>());
+            if !sessions.contains_key(&id) {
+                break id;
+            }
+        };
+
+        info!("Opened session {id} for {source_ip}");
+        sessions.insert(id, session);
+        id
+    }
+
+    /// Binds the session to `source`, if `policy` allows streaming there
+    /// for the client that opened it.
+    pub fn register(
+        &self,
+        id: SessionId,
+        source: SocketAddr,
+        policy: &TargetPolicy,
+    ) -> Registration {
+        let source = UdpAddr::from(source);
+
+        let mut sessions = self.sessions.lock();
+        let Some(session) = sessions.get_mut(&id) else {
+            drop(sessions);
+            debug!("REGISTER for unknown session {id} from {source}");
+            return Registration::Unknown;
+        };
+
+        let tickers = session.tickers.clone();
+        let source_ip = session.source_ip;
+        if let Err(e) = policy.check(source, source_ip) {
+            return Registration::Rejected(e);
+        }
+        let previous = session.target.replace(source);
+        drop(sessions);
+
+        match previous {
+            None => Registration::Started { tickers, source_ip },
+            Some(previous) if previous == source => {
+                Registration::Refreshed(source)
+            }
+            Some(previous) => Registration::Moved {
+                previous,
+                tickers,
+                source_ip,
+            },
+        }
+    }
+
+    /// The datagram protection negotiated before `STREAM nat`. Clones share
+    /// the counter, so a moved stream keeps counting where it left off.
+    pub fn sealer(&self, id: SessionId) -> Option<Sealer> {
+        self.sessions.lock().get(&id)?.sealer.clone()
+    }
+
+    /// Forgets sessions streaming to `target`, called once it has expired.
+    pub fn remove_target(&self, target: &UdpAddr) {
+        self.sessions
+            .lock()
+            .retain(|_, session| session.target.as_ref() != Some(target));
+    }
+
+    /// Forgets sessions that never received a `REGISTER` in time.
+    pub fn remove_unregistered(&self) -> Vec<SessionId> {
+        let timeout = self.register_timeout;
+        let mut removed = Vec::new();
+
+        self.sessions.lock().retain(|id, session| {
+            let stale =
+                session.target.is_none() && session.opened.elapsed() > timeout;
+            if stale {
+                removed.push(*id);
+            }
+            !stale
+        });
+
+        removed
+    }
+
+    /// Sessions from `source_ip` still waiting for their `REGISTER`; once
+    /// registered they are tracked by the client manager.
+    pub fn count_pending(&self, source_ip: IpAddr) -> usize {
+        self.sessions
+            .lock()
+            .values()
+            .filter(|s| s.target.is_none() && s.source_ip == source_ip)
+            .count()
+    }
+
+    pub fn count(&self) -> usize {
+        self.sessions.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{DatagramKey, Protection};
+    use rstest::{fixture, rstest};
+    use std::thread;
+
+    #[fixture]
+    fn registry() -> SessionRegistry {
+        SessionRegistry::new(Duration::from_secs(5))
+    }
+
+    #[fixture]
+    fn tickers() -> Tickers {
+        "AAPL,TSLA".parse().unwrap()
+    }
+
+    #[fixture]
+    fn source_ip() -> IpAddr {
+        "203.0.113.7".parse().unwrap()
+    }
+
+    #[fixture]
+    fn source() -> SocketAddr {
+        "203.0.113.7:40000".parse().unwrap()
+    }
+
+    #[rstest]
+    fn first_register_starts(
+        registry: SessionRegistry,
+        tickers: Tickers,
+        source_ip: IpAddr,
+        source: SocketAddr,
+    ) {
+        let id = registry.open(tickers.clone(), source_ip, None);
+
+        assert_eq!(
+            registry.register(id, source, &TargetPolicy::SameIp),
+            Registration::Started { tickers, source_ip }
+        );
+    }
+
+    #[rstest]
+    fn repeated_register_refreshes(
+        registry: SessionRegistry,
+        tickers: Tickers,
+        source_ip: IpAddr,
+        source: SocketAddr,
+    ) {
+        let id = registry.open(tickers, source_ip, None);
+        registry.register(id, source, &TargetPolicy::SameIp);
+
+        assert_eq!(
+            registry.register(id, source, &TargetPolicy::SameIp),
+            Registration::Refreshed(source.into())
+        );
+    }
+
+    #[rstest]
+    fn register_from_new_address_moves(
+        registry: SessionRegistry,
+        tickers: Tickers,
+        source_ip: IpAddr,
+        source: SocketAddr,
+    ) {
+        let id = registry.open(tickers.clone(), source_ip, None);
+        registry.register(id, source, &TargetPolicy::SameIp);
+        let moved: SocketAddr = "203.0.113.7:40001".parse().unwrap();
+
+        assert_eq!(
+            registry.register(id, moved, &TargetPolicy::SameIp),
+            Registration::Moved {
+                previous: source.into(),
+                tickers,
+                source_ip,
+            }
+        );
+    }
+
+    #[rstest]
+    fn rejects_register_from_foreign_ip(
+        registry: SessionRegistry,
+        tickers: Tickers,
+        source_ip: IpAddr,
+        source: SocketAddr,
+    ) {
+        let id = registry.open(tickers.clone(), source_ip, None);
+        let foreign: SocketAddr = "198.51.100.9:40000".parse().unwrap();
+
+        assert!(matches!(
+            registry.register(id, foreign, &TargetPolicy::SameIp),
+            Registration::Rejected(TargetError::NotPeer { .. })
+        ));
+        assert_eq!(
+            registry.register(id, source, &TargetPolicy::SameIp),
+            Registration::Started { tickers, source_ip }
+        );
+    }
+
+    #[rstest]
+    fn rejects_move_to_other_ip(
+        registry: SessionRegistry,
+        tickers: Tickers,
+        source_ip: IpAddr,
+        source: SocketAddr,
+    ) {
+        let id = registry.open(tickers, source_ip, None);
+        registry.register(id, source, &TargetPolicy::SameIp);
+        let spoofed: SocketAddr = "198.51.100.9:40000".parse().unwrap();
+
+        assert!(matches!(
+            registry.register(id, spoofed, &TargetPolicy::SameIp),
+            Registration::Rejected(_)
+        ));
+        assert_eq!(
+            registry.register(id, source, &TargetPolicy::SameIp),
+            Registration::Refreshed(source.into())
+        );
+    }
+
+    #[rstest]
+    fn keeps_sealer(
+        registry: SessionRegistry,
+        tickers: Tickers,
+        source_ip: IpAddr,
+    ) {
+        let sealer = Sealer::new(DatagramKey::generate(), Protection::Mac);
+        let secured = registry.open(tickers.clone(), source_ip, Some(sealer));
+        let plain = registry.open(tickers, source_ip, None);
+
+        assert!(registry.sealer(secured).is_some());
+        assert!(registry.sealer(plain).is_none());
+    }
+
+    #[rstest]
+    fn counts_only_pending_sessions(
+        registry: SessionRegistry,
+        tickers: Tickers,
+        source_ip: IpAddr,
+        source: SocketAddr,
+    ) {
+        registry.open(tickers.clone(), source_ip, None);
+        let registered = registry.open(tickers, source_ip, None);
+        registry.register(registered, source, &TargetPolicy::SameIp);
+
+        assert_eq!(registry.count_pending(source_ip), 1);
+    }
+
+    #[rstest]
+    fn unknown_session(registry: SessionRegistry, source: SocketAddr) {
+        assert_eq!(
+            registry.register(
+                SessionId::from(42),
+                source,
+                &TargetPolicy::SameIp
+            ),
+            Registration::Unknown
+        );
+    }
+
+    #[rstest]
+    fn opened_ids_are_distinct(
+        registry: SessionRegistry,
+        tickers: Tickers,
+        source_ip: IpAddr,
+    ) {
+        let first = registry.open(tickers.clone(), source_ip, None);
+        let second = registry.open(tickers, source_ip, None);
+
+        assert_ne!(first, second);
+        assert_eq!(registry.count(), 2);
+    }
+
+    #[rstest]
+    fn remove_target_forgets_session(
+        registry: SessionRegistry,
+        tickers: Tickers,
+        source_ip: IpAddr,
+        source: SocketAddr,
+    ) {
+        let id = registry.open(tickers, source_ip, None);
+        registry.register(id, source, &TargetPolicy::SameIp);
+
+        registry.remove_target(&source.into());
+
+        assert_eq!(
+            registry.register(id, source, &TargetPolicy::SameIp),
+            Registration::Unknown
+        );
+    }
+
+    #[rstest]
+    fn remove_unregistered_keeps_registered(
+        tickers: Tickers,
+        source_ip: IpAddr,
+        source: SocketAddr,
+    ) {
+        let registry = SessionRegistry::new(Duration::from_millis(10));
+        let pending = registry.open(tickers.clone(), source_ip, None);
+        let registered = registry.open(tickers, source_ip, None);
+        registry.register(registered, source, &TargetPolicy::SameIp);
+        thread::sleep(Duration::from_millis(50));
+
+        let removed = registry.remove_unregistered();
+
+        assert_eq!(removed, vec![pending]);
+        assert_eq!(registry.count(), 1);
+    }
+}
@@ -0,0 +1,154 @@
+use log::error;
+use parking_lot::Mutex;
+use std::fmt;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+struct Worker {
+    name: String,
+    handle: JoinHandle<()>,
+}
+
+/// Named handles of the server's threads, joined on shutdown. Clones share
+/// the handles, so servers given one spawn their connection threads here.
+#[derive(Clone, Default)]
+pub struct Workers {
+    threads: Arc<Mutex<Vec<Worker>>>,
+}
+
+impl Workers {
+    const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns a named thread. Handles of threads that have already finished
+    /// are dropped here, so per-connection threads do not pile up.
+    pub fn spawn(&self, name: String, f: impl FnOnce() + Send + 'static) {
+        let mut threads = self.threads.lock();
+        threads.retain(|worker| !worker.handle.is_finished());
+
+        match thread::Builder::new().name(name.clone()).spawn(f) {
+            Ok(handle) => threads.push(Worker { name, handle }),
+            Err(e) => error!("Failed to spawn {name}: {e}"),
+        }
+    }
+
+    /// Waits up to `timeout` for every thread, including ones spawned while
+    /// waiting. Threads still running at the deadline are left detached.
+    pub fn join(&self, timeout: Duration) -> ShutdownReport {
+        let deadline = Instant::now() + timeout;
+        let mut report = ShutdownReport::default();
+
+        loop {
+            let batch = std::mem::take(&mut *self.threads.lock());
+            if batch.is_empty() {
+                return report;
+            }
+
+            while Instant::now() < deadline
+                && batch.iter().any(|worker| !worker.handle.is_finished())
+            {
+                thread::sleep(Self::JOIN_POLL_INTERVAL);
+            }
+
+            for Worker { name, handle } in batch {
+                if !handle.is_finished() {
+                    report.stuck.push(name);
+                } else if handle.join().is_err() {
+                    report.panicked.push(name);
+                } else {
+                    report.joined += 1;
+                }
+            }
+        }
+    }
+}
+
+/// What [`Workers::join`] saw.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    pub joined: usize,
+    pub panicked: Vec<String>,
+    pub stuck: Vec<String>,
+}
+
+impl ShutdownReport {
+    pub const fn is_clean(&self) -> bool {
+        self.panicked.is_empty() && self.stuck.is_empty()
+    }
+}
+
+impl fmt::Display for ShutdownReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} threads stopped", self.joined)?;
+        if !self.panicked.is_empty() {
+            write!(f, ", panicked: {}", self.panicked.join(", "))?;
+        }
+        if !self.stuck.is_empty() {
+            write!(f, ", still running: {}", self.stuck.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn joins_finished_threads() {
+        let workers = Workers::new();
+        for i in 0..3 {
+            workers.spawn(format!("worker {i}"), || {
+                thread::sleep(Duration::from_millis(20));
+            });
+        }
+
+        let report = workers.join(Duration::from_secs(1));
+
+        assert!(report.is_clean());
+        assert_eq!(report.joined, 3);
+    }
+
+    #[test]
+    fn reports_stuck_and_panicked_threads() {
+        let workers = Workers::new();
+        let release = Arc::new(AtomicBool::new(false));
+        let stuck = release.clone();
+        workers.spawn("stuck".to_string(), move || {
+            while !stuck.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+        workers.spawn("panicking".to_string(), || panic!("boom"));
+
+        // Long enough for the panic to unwind on a busy machine.
+        let report = workers.join(Duration::from_secs(1));
+        release.store(true, Ordering::SeqCst);
+
+        assert_eq!(report.stuck, ["stuck"]);
+        assert_eq!(report.panicked, ["panicking"]);
+        assert_eq!(
+            report.to_string(),
+            "0 threads stopped, panicked: panicking, still running: stuck"
+        );
+    }
+
+    #[test]
+    fn joins_threads_spawned_while_joining() {
+        let workers = Workers::new();
+        let spawner = workers.clone();
+        workers.spawn("acceptor".to_string(), move || {
+            thread::sleep(Duration::from_millis(20));
+            spawner.spawn("connection".to_string(), || {});
+        });
+
+        let report = workers.join(Duration::from_secs(1));
+
+        assert_eq!(report.joined, 2);
+    }
+}
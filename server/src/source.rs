@@ -0,0 +1,199 @@
+use common::StockQuote;
+use log::info;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::fanout::FanOut;
+use crate::generator::{GeneratorControl, QuoteGenerator};
+use crate::health::Heartbeat;
+use crate::metrics::METRICS;
+
+/// Longest [`publish`] sleeps at once, so interval changes and shutdown
+/// take effect promptly.
+const TICK: Duration = Duration::from_millis(100);
+
+/// What a [`QuoteSource`] has for the server when asked.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Next {
+    /// Quotes to publish now.
+    Quotes(Vec<StockQuote>),
+    /// Nothing is due for this long.
+    Wait(Duration),
+    /// Nothing more will come.
+    Finished,
+}
+
+/// Where the quotes the server publishes come from. [`publish`] asks for
+/// the next quotes in a loop, sleeping as long as the source says, so a
+/// source only decides what is due when. A feed that pushes quotes from
+/// its own thread can hand them over through a channel drained here.
+pub trait QuoteSource: Send {
+    fn next(&mut self) -> Next;
+
+    /// Tickers the source has quotes for, which clients can look up and
+    /// the admin interface can halt.
+    fn tickers(&self) -> Vec<String>;
+}
+
+/// Random quotes for a fixed set of tickers, one round every
+/// [`GeneratorControl::interval`].
+pub struct GeneratorSource {
+    generator: QuoteGenerator,
+    control: GeneratorControl,
+    last_round: Instant,
+}
+
+impl GeneratorSource {
+    pub const TICKERS: [&str; 10] = [
+        "AAPL", "GOOGL", "TSLA", "MSFT", "AMZN", "NVDA", "META", "JPM", "JNJ",
+        "V",
+    ];
+
+    pub fn new(control: GeneratorControl) -> Self {
+        Self {
+            generator: QuoteGenerator::new(),
+            control,
+            last_round: Instant::now(),
+        }
+    }
+}
+
+impl QuoteSource for GeneratorSource {
+    fn next(&mut self) -> Next {
+        let remaining = self
+            .control
+            .interval()
+            .saturating_sub(self.last_round.elapsed());
+        if !remaining.is_zero() {
+            return Next::Wait(remaining);
+        }
+        self.last_round = Instant::now();
+
+        let quotes = Self::TICKERS
+            .iter()
+            .filter(|ticker| !self.control.is_halted(ticker))
+            .filter_map(|ticker| self.generator.generate(ticker).ok())
+            .collect();
+        Next::Quotes(quotes)
+    }
+
+    fn tickers(&self) -> Vec<String> {
+        Self::TICKERS.iter().map(ToString::to_string).collect()
+    }
+}
+
+/// Broadcasts what `source` has until `running` is cleared, skipping
+/// halted tickers. Once the source has finished, keeps beating so the
+/// server stays healthy.
+pub fn publish(
+    source: &mut dyn QuoteSource,
+    fan_out: &FanOut,
+    control: &GeneratorControl,
+    heartbeat: &Heartbeat,
+    running: &AtomicBool,
+) {
+    let mut finished = false;
+
+    while running.load(Ordering::SeqCst) {
+        heartbeat.beat();
+        match source.next() {
+            Next::Quotes(quotes) => {
+                for quote in &quotes {
+                    if control.is_halted(&quote.ticker) {
+                        continue;
+                    }
+                    METRICS.quote_generated(&quote.ticker);
+                    fan_out.broadcast(quote);
+                }
+            }
+            Next::Wait(wait) => thread::sleep(wait.min(TICK)),
+            Next::Finished => {
+                if !finished {
+                    info!("Quote source has no more quotes");
+                    finished = true;
+                }
+                thread::sleep(TICK);
+            }
+        }
+    }
+    info!("Quote generator stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_handler::SubscriberId;
+    use crate::health::Health;
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    /// Hands out scripted steps, then stops the loop.
+    struct Script {
+        steps: VecDeque<Next>,
+        running: Arc<AtomicBool>,
+    }
+
+    impl QuoteSource for Script {
+        fn next(&mut self) -> Next {
+            self.steps.pop_front().unwrap_or_else(|| {
+                self.running.store(false, Ordering::SeqCst);
+                Next::Finished
+            })
+        }
+
+        fn tickers(&self) -> Vec<String> {
+            Vec::new()
+        }
+    }
+
+    fn quote(ticker: &str) -> StockQuote {
+        StockQuote::new(ticker, 1.into(), 1).unwrap()
+    }
+
+    #[test]
+    fn publishes_unhalted_quotes() {
+        let fan_out = FanOut::new();
+        let id = SubscriberId::Tcp(SocketAddr::from(([127, 0, 0, 1], 9)));
+        let (rx, _stop) = fan_out.subscribe(id);
+        let control = GeneratorControl::new(Duration::from_millis(100));
+        control.halt("TSLA");
+        let running = Arc::new(AtomicBool::new(true));
+        let mut script = Script {
+            steps: VecDeque::from([
+                Next::Quotes(vec![quote("AAPL"), quote("TSLA")]),
+                Next::Wait(Duration::from_millis(1)),
+                Next::Quotes(vec![quote("V")]),
+            ]),
+            running: running.clone(),
+        };
+
+        publish(
+            &mut script,
+            &fan_out,
+            &control,
+            &Health::new().register("generator"),
+            &running,
+        );
+
+        let published: Vec<_> = rx.try_iter().map(|q| q.ticker).collect();
+        assert_eq!(published, ["AAPL", "V"]);
+    }
+
+    #[test]
+    fn generates_rounds_for_unhalted_tickers() {
+        let control = GeneratorControl::new(Duration::from_millis(1));
+        control.halt("AAPL");
+        let mut source = GeneratorSource::new(control);
+        thread::sleep(Duration::from_millis(2));
+
+        let Next::Quotes(quotes) = source.next() else {
+            panic!("expected a round");
+        };
+
+        assert_eq!(quotes.len(), GeneratorSource::TICKERS.len() - 1);
+        assert!(quotes.iter().all(|quote| quote.ticker != "AAPL"));
+        assert!(matches!(source.next(), Next::Wait(_)));
+    }
+}
@@ -0,0 +1,79 @@
+//! Fixtures shared by the tests of the server's listeners.
+
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::admin::AdminServer;
+use crate::http::{Handler, HttpServer};
+use crate::ws_gateway::WsGateway;
+
+/// A server with an accept loop that runs until `running` is cleared.
+pub trait Listener: Send + 'static {
+    fn local_addr(&self) -> Result<SocketAddr>;
+    fn run(&self);
+}
+
+impl<H: Handler> Listener for HttpServer<H> {
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Self::local_addr(self)
+    }
+
+    fn run(&self) {
+        Self::run(self);
+    }
+}
+
+impl Listener for WsGateway {
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Self::local_addr(self)
+    }
+
+    fn run(&self) {
+        Self::run(self);
+    }
+}
+
+impl Listener for AdminServer {
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Self::local_addr(self)
+    }
+
+    fn run(&self) {
+        Self::run(self);
+    }
+}
+
+/// A server running for one test, stopped when dropped.
+pub struct Serving<T> {
+    /// What the test shares with the server, to drive or inspect it.
+    pub shared: T,
+    pub addr: SocketAddr,
+    running: Arc<AtomicBool>,
+}
+
+impl<T> Drop for Serving<T> {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Binds a server to a free local port with `bind` and runs it on its own
+/// thread.
+pub fn serve<S: Listener, T>(
+    shared: T,
+    bind: impl FnOnce(SocketAddr, Arc<AtomicBool>) -> Result<S>,
+) -> Serving<T> {
+    let running = Arc::new(AtomicBool::new(true));
+    let server = bind("127.0.0.1:0".parse().unwrap(), running.clone()).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+
+    Serving {
+        shared,
+        addr,
+        running,
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::{anyhow, Context, Result};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConnection, StreamOwned};
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use common::tls::{load_certs, load_private_key};
+
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// Builds the TLS setup for the control port. With `client_ca`, clients
+/// must present a certificate issued by one of its certificates.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> Result<Arc<rustls::ServerConfig>> {
+    let builder = rustls::ServerConfig::builder();
+    let builder = match client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(path)? {
+                roots.add(cert).with_context(|| {
+                    format!("Invalid CA certificate in {}", path.display())
+                })?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let config = builder
+        .with_single_cert(load_certs(cert)?, load_private_key(key)?)
+        .context("Certificate does not match private key")?;
+
+    Ok(Arc::new(config))
+}
+
+/// Completes the handshake so failures surface before any command is
+/// read, giving up after `timeout` so a silent peer cannot hold the
+/// connection. Returns whether the client presented a verified
+/// certificate. The socket is left without timeouts.
+pub fn accept(
+    config: Arc<rustls::ServerConfig>,
+    stream: TcpStream,
+    timeout: Duration,
+) -> Result<(TlsStream, bool)> {
+    let deadline = Instant::now() + timeout;
+    let mut tls = StreamOwned::new(ServerConnection::new(config)?, stream);
+    while tls.conn.is_handshaking() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(anyhow!("TLS handshake timed out"));
+        }
+        tls.sock.set_read_timeout(Some(remaining))?;
+        tls.sock.set_write_timeout(Some(remaining))?;
+        tls.conn.complete_io(&mut tls.sock).map_err(|e| {
+            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+                anyhow!("TLS handshake timed out")
+            } else {
+                anyhow!("TLS handshake failed: {e}")
+            }
+        })?;
+    }
+    tls.sock.set_read_timeout(None)?;
+    tls.sock.set_write_timeout(None)?;
+
+    let client_verified = tls.conn.peer_certificates().is_some();
+    Ok((tls, client_verified))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::CertifiedKey;
+    use rstest::{fixture, rstest};
+    use rustls::pki_types::ServerName;
+    use rustls::ClientConnection;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread;
+    use tempfile::TempDir;
+
+    struct Pki {
+        dir: TempDir,
+    }
+
+    impl Pki {
+        fn path(&self, name: &str) -> PathBuf {
+            self.dir.path().join(name)
+        }
+
+        fn write(&self, name: &str, names: &[&str]) {
+            let CertifiedKey { cert, signing_key } =
+                rcgen::generate_simple_self_signed(
+                    names.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                )
+                .unwrap();
+            fs::write(self.path(&format!("{name}.pem")), cert.pem()).unwrap();
+            fs::write(
+                self.path(&format!("{name}.key")),
+                signing_key.serialize_pem(),
+            )
+            .unwrap();
+        }
+
+        fn client_config(&self, with_cert: bool) -> Arc<rustls::ClientConfig> {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(&self.path("server.pem")).unwrap() {
+                roots.add(cert).unwrap();
+            }
+            let builder =
+                rustls::ClientConfig::builder().with_root_certificates(roots);
+            let config = if with_cert {
+                builder
+                    .with_client_auth_cert(
+                        load_certs(&self.path("client.pem")).unwrap(),
+                        load_private_key(&self.path("client.key")).unwrap(),
+                    )
+                    .unwrap()
+            } else {
+                builder.with_no_client_auth()
+            };
+            Arc::new(config)
+        }
+    }
+
+    #[fixture]
+    fn pki() -> Pki {
+        let pki = Pki {
+            dir: TempDir::new().unwrap(),
+        };
+        pki.write("server", &["localhost"]);
+        pki.write("client", &["quote-client"]);
+        pki
+    }
+
+    /// Echoes one byte over TLS and returns what the server side saw.
+    fn exchange(
+        server: Arc<rustls::ServerConfig>,
+        client: Arc<rustls::ClientConfig>,
+    ) -> (Result<bool>, std::io::Result<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let (mut tls, verified) =
+                accept(server, stream, Duration::from_secs(5))?;
+            let mut byte = [0_u8; 1];
+            tls.read_exact(&mut byte)?;
+            tls.write_all(&byte)?;
+            tls.flush()?;
+            Ok(verified)
+        });
+
+        let name = ServerName::try_from("localhost").unwrap();
+        let conn = ClientConnection::new(client, name).unwrap();
+        let mut tls = StreamOwned::new(conn, TcpStream::connect(addr).unwrap());
+        let echoed = tls.write_all(&[42]).and_then(|()| {
+            let mut byte = [0_u8; 1];
+            tls.read_exact(&mut byte).map(|()| byte[0])
+        });
+
+        (handle.join().unwrap(), echoed)
+    }
+
+    #[rstest]
+    fn serves_without_client_auth(pki: Pki) {
+        let server = server_config(
+            &pki.path("server.pem"),
+            &pki.path("server.key"),
+            None,
+        )
+        .unwrap();
+
+        let (verified, echoed) = exchange(server, pki.client_config(false));
+
+        assert!(!verified.unwrap());
+        assert_eq!(echoed.unwrap(), 42);
+    }
+
+    #[rstest]
+    fn verifies_client_certificate(pki: Pki) {
+        let server = server_config(
+            &pki.path("server.pem"),
+            &pki.path("server.key"),
+            Some(&pki.path("client.pem")),
+        )
+        .unwrap();
+
+        let (verified, echoed) = exchange(server, pki.client_config(true));
+
+        assert!(verified.unwrap());
+        assert_eq!(echoed.unwrap(), 42);
+    }
+
+    #[rstest]
+    fn rejects_missing_client_certificate(pki: Pki) {
+        let server = server_config(
+            &pki.path("server.pem"),
+            &pki.path("server.key"),
+            Some(&pki.path("client.pem")),
+        )
+        .unwrap();
+
+        let (verified, _) = exchange(server, pki.client_config(false));
+
+        assert!(verified.is_err());
+    }
+
+    #[rstest]
+    fn gives_up_on_silent_client(pki: Pki) {
+        let server = server_config(
+            &pki.path("server.pem"),
+            &pki.path("server.key"),
+            None,
+        )
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _silent = TcpStream::connect(listener.local_addr().unwrap());
+        let (stream, _) = listener.accept().unwrap();
+        let started = Instant::now();
+
+        let result = accept(server, stream, Duration::from_millis(200));
+
+        let error = result.expect_err("handshake should time out");
+        assert!(error.to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[rstest]
+    fn rejects_mismatched_key(pki: Pki) {
+        let result = server_config(
+            &pki.path("server.pem"),
+            &pki.path("client.key"),
+            None,
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[rstest]
+    fn rejects_missing_files(pki: Pki) {
+        let result = server_config(
+            &pki.path("missing.pem"),
+            &pki.path("server.key"),
+            None,
+        );
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::Result;
+use crossbeam::channel::{Receiver, TryRecvError};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+use crate::client_handler::SubscriberId;
+use crate::fanout::{FanOut, Stop};
+use crate::health::Heartbeat;
+use crate::shutdown::Workers;
+use common::StockQuote;
+
+/// Messages a dashboard sends to change what it receives.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum ClientMessage {
+    Subscribe { tickers: Vec<String> },
+    Unsubscribe { tickers: Vec<String> },
+}
+
+/// Replies to [`ClientMessage`]s. Quotes are sent as bare `StockQuote`
+/// JSON, without an `event` field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum ServerMessage {
+    Subscribed { tickers: Vec<String> },
+    Error { message: String },
+}
+
+/// Accepts WebSocket connections and pushes quotes for the tickers each
+/// connection has subscribed to.
+pub struct WsGateway {
+    listener: TcpListener,
+    fan_out: FanOut,
+    running: Arc<AtomicBool>,
+    heartbeat: Option<Heartbeat>,
+    workers: Workers,
+}
+
+impl WsGateway {
+    const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn bind(
+        addr: SocketAddr,
+        fan_out: FanOut,
+        running: Arc<AtomicBool>,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            fan_out,
+            running,
+            heartbeat: None,
+            workers: Workers::new(),
+        })
+    }
+
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    pub fn with_workers(mut self, workers: Workers) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn run(&self) {
+        if let Ok(addr) = self.listener.local_addr() {
+            info!("WebSocket gateway listening on {addr}");
+        }
+
+        while self.running.load(Ordering::SeqCst) {
+            if let Some(heartbeat) = &self.heartbeat {
+                heartbeat.beat();
+            }
+            match self.listener.accept() {
+                Ok((stream, peer_addr)) => {
+                    let fan_out = self.fan_out.clone();
+                    let running = self.running.clone();
+                    self.workers.spawn(format!("ws {peer_addr}"), move || {
+                        if let Err(e) =
+                            WsConnection::serve(stream, &fan_out, &running)
+                        {
+                            warn!("WebSocket {peer_addr} error: {e}");
+                        }
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Self::ACCEPT_POLL_INTERVAL);
+                }
+                Err(e) => error!("Failed to accept WebSocket connection: {e}"),
+            }
+        }
+
+        info!("WebSocket gateway stopped");
+    }
+}
+
+struct WsConnection {
+    socket: WebSocket<TcpStream>,
+    tickers: BTreeSet<String>,
+    quote_rx: Receiver<StockQuote>,
+    stop_rx: Receiver<Stop>,
+}
+
+impl WsConnection {
+    const READ_POLL_INTERVAL: Duration = Duration::from_millis(10);
+    /// How long a client has to send its upgrade request.
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn serve(
+        stream: TcpStream,
+        fan_out: &FanOut,
+        running: &Arc<AtomicBool>,
+    ) -> Result<()> {
+        let peer_addr = stream.peer_addr()?;
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Self::HANDSHAKE_TIMEOUT))?;
+
+        let socket = tungstenite::accept(stream)
+            .map_err(|e| anyhow::anyhow!("Handshake failed: {e}"))?;
+        socket
+            .get_ref()
+            .set_read_timeout(Some(Self::READ_POLL_INTERVAL))?;
+        info!("New WebSocket connection from: {peer_addr}");
+
+        let id = SubscriberId::WebSocket(peer_addr);
+        let (quote_rx, stop_rx) = fan_out.subscribe(id);
+        let mut connection = Self {
+            socket,
+            tickers: BTreeSet::new(),
+            quote_rx,
+            stop_rx,
+        };
+
+        let result = connection.serve_loop(running);
+        fan_out.unsubscribe(&id);
+        if result.is_ok() {
+            // Tells the dashboard we are going away; fails harmlessly if
+            // it already closed.
+            let _ = connection.socket.close(None);
+            let _ = connection.socket.flush();
+        }
+
+        info!("WebSocket connection closed: {peer_addr}");
+        result
+    }
+
+    fn serve_loop(&mut self, running: &Arc<AtomicBool>) -> Result<()> {
+        while running.load(Ordering::SeqCst) {
+            match self.stop_rx.try_recv() {
+                Ok(_) | Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => {}
+            }
+
+            match self.socket.read() {
+                Ok(Message::Text(text)) => self.handle_message(&text)?,
+                Ok(Message::Close(_))
+                | Err(
+                    tungstenite::Error::ConnectionClosed
+                    | tungstenite::Error::AlreadyClosed,
+                ) => break,
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    ) => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            self.forward_quotes()?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_message(&mut self, text: &str) -> Result<()> {
+        let reply = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(ClientMessage::Subscribe { tickers }) => {
+                self.tickers.extend(normalize(tickers));
+                self.subscribed()
+            }
+            Ok(ClientMessage::Unsubscribe { tickers }) => {
+                for ticker in normalize(tickers) {
+                    self.tickers.remove(&ticker);
+                }
+                self.subscribed()
+            }
+            Err(e) => {
+                debug!("Invalid WebSocket message '{text}': {e}");
+                ServerMessage::Error {
+                    message: e.to_string(),
+                }
+            }
+        };
+
+        self.send_json(&reply)
+    }
+
+    fn subscribed(&self) -> ServerMessage {
+        ServerMessage::Subscribed {
+            tickers: self.tickers.iter().cloned().collect(),
+        }
+    }
+
+    fn forward_quotes(&mut self) -> Result<()> {
+        while let Ok(quote) = self.quote_rx.try_recv() {
+            if self.tickers.contains(&quote.ticker) {
+                self.socket.send(Message::text(quote.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_json(&mut self, message: &ServerMessage) -> Result<()> {
+        let text = serde_json::to_string(message)?;
+        self.socket.send(Message::text(text))?;
+        Ok(())
+    }
+}
+
+fn normalize(tickers: Vec<String>) -> impl Iterator<Item = String> {
+    tickers
+        .into_iter()
+        .map(|t| t.trim().to_uppercase())
+        .filter(|t| !t.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serve, Serving};
+    use rstest::{fixture, rstest};
+    use rust_decimal::Decimal;
+    use tungstenite::stream::MaybeTlsStream;
+
+    type Client = WebSocket<MaybeTlsStream<TcpStream>>;
+
+    type TestGateway = Serving<FanOut>;
+
+    #[fixture]
+    fn gateway() -> TestGateway {
+        let fan_out = FanOut::new();
+        serve(fan_out.clone(), |addr, running| {
+            WsGateway::bind(addr, fan_out, running)
+        })
+    }
+
+    fn connect(addr: SocketAddr) -> Client {
+        let (client, _) = tungstenite::connect(format!("ws://{addr}")).unwrap();
+        client
+    }
+
+    fn send(client: &mut Client, json: &str) {
+        client.send(Message::text(json)).unwrap();
+    }
+
+    fn recv(client: &mut Client) -> serde_json::Value {
+        loop {
+            if let Message::Text(text) = client.read().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    fn quote(ticker: &str) -> StockQuote {
+        StockQuote::new(ticker, Decimal::new(10050, 2), 100).unwrap()
+    }
+
+    #[rstest]
+    fn pushes_subscribed_quotes(gateway: TestGateway) {
+        let mut client = connect(gateway.addr);
+
+        send(
+            &mut client,
+            r#"{"action":"subscribe","tickers":["aapl","TSLA"]}"#,
+        );
+        let ack = recv(&mut client);
+        assert_eq!(ack["event"], "subscribed");
+        assert_eq!(ack["tickers"], serde_json::json!(["AAPL", "TSLA"]));
+
+        gateway.shared.broadcast(&quote("GOOGL"));
+        gateway.shared.broadcast(&quote("AAPL"));
+
+        let pushed = recv(&mut client);
+        assert_eq!(pushed["ticker"], "AAPL");
+    }
+
+    #[rstest]
+    fn unsubscribe_stops_ticker(gateway: TestGateway) {
+        let mut client = connect(gateway.addr);
+
+        send(
+            &mut client,
+            r#"{"action":"subscribe","tickers":["AAPL","TSLA"]}"#,
+        );
+        recv(&mut client);
+        send(&mut client, r#"{"action":"unsubscribe","tickers":["AAPL"]}"#);
+        let ack = recv(&mut client);
+        assert_eq!(ack["tickers"], serde_json::json!(["TSLA"]));
+
+        gateway.shared.broadcast(&quote("AAPL"));
+        gateway.shared.broadcast(&quote("TSLA"));
+
+        let pushed = recv(&mut client);
+        assert_eq!(pushed["ticker"], "TSLA");
+    }
+
+    #[rstest]
+    fn accepts_slow_upgrade_request(gateway: TestGateway) {
+        let stream =
+            MaybeTlsStream::Plain(TcpStream::connect(gateway.addr).unwrap());
+        thread::sleep(WsConnection::READ_POLL_INTERVAL * 10);
+
+        let (mut client, _) =
+            tungstenite::client(format!("ws://{}", gateway.addr), stream)
+                .unwrap();
+        send(&mut client, r#"{"action":"subscribe","tickers":["V"]}"#);
+
+        assert_eq!(recv(&mut client)["event"], "subscribed");
+    }
+
+    #[rstest]
+    fn rejects_invalid_message(gateway: TestGateway) {
+        let mut client = connect(gateway.addr);
+
+        send(&mut client, r#"{"action":"dance"}"#);
+
+        let reply = recv(&mut client);
+        assert_eq!(reply["event"], "error");
+    }
+
+    #[test]
+    fn parses_client_messages() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"action":"unsubscribe","tickers":["V"]}"#)
+                .unwrap();
+        assert_eq!(
+            msg,
+            ClientMessage::Unsubscribe {
+                tickers: vec!["V".to_string()]
+            }
+        );
+    }
+}